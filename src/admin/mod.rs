@@ -0,0 +1,391 @@
+use crate::graphql::cache::CacheBackend;
+use crate::server::{ListenerConfig, Routes, Shutdown};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use warp::http::header::WWW_AUTHENTICATE;
+use warp::http::StatusCode;
+use warp::reject::{MethodNotAllowed, Reject};
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug, Deserialize)]
+pub struct AdminConfig {
+    #[serde(flatten)]
+    pub listener: ListenerConfig,
+    /// Token expected in the `Authorization: Bearer <token>` header
+    #[serde(deserialize_with = "deserialize_token")]
+    pub token: String,
+}
+
+fn deserialize_token<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let token = String::deserialize(deserializer)?;
+    if token.trim().len() == 0 {
+        return Err(serde::de::Error::custom("the admin token can't be empty"));
+    }
+
+    Ok(token)
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: Option<String>,
+    tag: Option<String>,
+}
+
+/// Routes of the admin listener. `/health` and `/ready` are meant for probes and
+/// don't require the token, all the other routes do.
+pub fn routes(token: &str, cache: Arc<dyn CacheBackend>, shutdown: Shutdown) -> Routes {
+    let token = Arc::new(token.to_string());
+
+    let health = warp::path("health")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| json_response(StatusCode::OK, json!({"status": "ok"})));
+
+    let ready_cache = cache.clone();
+    let ready_shutdown = shutdown.clone();
+    let ready = warp::path("ready")
+        .and(warp::path::end())
+        .and(warp::get())
+        .then(move || ready_response(ready_cache.clone(), ready_shutdown.clone()));
+
+    let stats_cache = cache.clone();
+    let stats = warp::path("stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(authorize(token.clone()))
+        .then(move || stats_response(stats_cache.clone()));
+
+    let lookup_cache = cache.clone();
+    let lookup = warp::path("cache")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(authorize(token.clone()))
+        .and(warp::query::<KeyQuery>())
+        .then(move |query: KeyQuery| lookup_response(lookup_cache.clone(), query.key));
+
+    let purge = warp::path("cache")
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(authorize(token.clone()))
+        .and(warp::query::<KeyQuery>())
+        .then(move |query: KeyQuery| purge_response(cache.clone(), query.key, query.tag));
+
+    let shutdown = warp::path("shutdown")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorize(token))
+        .map(move || {
+            println!("Shutdown requested");
+            shutdown.trigger();
+            json_response(StatusCode::ACCEPTED, json!({"status": "shutting down"}))
+        });
+
+    health
+        .or(ready)
+        .unify()
+        .or(stats)
+        .unify()
+        .or(lookup)
+        .unify()
+        .or(purge)
+        .unify()
+        .or(shutdown)
+        .unify()
+        .or_else(|rejection| async move { Ok::<_, Rejection>((rejection_response(rejection),)) })
+        .boxed()
+}
+
+fn authorize(token: Arc<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                match header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) {
+                    Some(t) if constant_time_eq(t.as_bytes(), token.as_bytes()) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Compares the tokens without leaking the length of the common prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn ready_response(cache: Arc<dyn CacheBackend>, shutdown: Shutdown) -> warp::reply::Response {
+    if shutdown.is_triggered() {
+        return json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({"status": "shutting down"}),
+        );
+    }
+
+    match cache.ping().await {
+        Ok(_) => json_response(StatusCode::OK, json!({"status": "ready"})),
+        Err(e) => json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({"status": "cache unavailable", "error": format!("{:?}", e)}),
+        ),
+    }
+}
+
+async fn stats_response(cache: Arc<dyn CacheBackend>) -> warp::reply::Response {
+    match cache.stats().await {
+        Ok(stats) => json_response(StatusCode::OK, json!({ "cache": stats })),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)),
+    }
+}
+
+async fn lookup_response(
+    cache: Arc<dyn CacheBackend>,
+    key: Option<String>,
+) -> warp::reply::Response {
+    let key = match key {
+        Some(k) => k,
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                String::from("Missing key parameter"),
+            )
+        }
+    };
+
+    match cache.get(&key).await {
+        Some(values) => {
+            let values = values
+                .into_iter()
+                .map(|v| {
+                    json!({
+                        "value": v.value,
+                        "created": v.created.to_rfc3339(),
+                        "expiry": v.expiry.to_rfc3339(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            json_response(StatusCode::OK, json!({"key": key, "values": values}))
+        }
+        None => error_response(StatusCode::NOT_FOUND, format!("Key {} not found", key)),
+    }
+}
+
+/// Removes `key` or the keys tagged with `tag`. The cache can't be emptied at once,
+/// the Redis database may be shared with other applications
+async fn purge_response(
+    cache: Arc<dyn CacheBackend>,
+    key: Option<String>,
+    tag: Option<String>,
+) -> warp::reply::Response {
+    let purged = match (key, tag) {
+        (Some(key), None) => cache.delete(&key).await.map(|deleted| deleted as usize),
+        (None, Some(tag)) => cache.purge_tag(&tag).await,
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                String::from("Either key or tag must be given"),
+            )
+        }
+    };
+
+    match purged {
+        Ok(count) => json_response(StatusCode::OK, json!({ "purged": count })),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)),
+    }
+}
+
+fn rejection_response(rejection: Rejection) -> warp::reply::Response {
+    if rejection.find::<Unauthorized>().is_some() {
+        let mut response = error_response(
+            StatusCode::UNAUTHORIZED,
+            String::from("Invalid admin token"),
+        );
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        response
+    } else if rejection.is_not_found() {
+        error_response(StatusCode::NOT_FOUND, String::from("Not found"))
+    } else if rejection.find::<MethodNotAllowed>().is_some() {
+        error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            String::from("Method not allowed"),
+        )
+    } else {
+        error_response(StatusCode::BAD_REQUEST, format!("{:?}", rejection))
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> warp::reply::Response {
+    json_response(status, json!({ "error": message }))
+}
+
+fn json_response(status: StatusCode, body: Value) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{routes, AdminConfig};
+    use crate::graphql::cache::{CacheBackend, MemoryCache};
+    use crate::server::Shutdown;
+    use futures::executor::block_on;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use warp::http::StatusCode;
+
+    fn body(response: &warp::http::Response<warp::hyper::body::Bytes>) -> Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn admin_routes_require_token() {
+        let cache = Arc::new(MemoryCache::new());
+        let shutdown = Shutdown::new();
+        let filter = routes("secret", cache.clone(), shutdown.clone());
+
+        let response = block_on(warp::test::request().path("/stats").reply(&filter));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = block_on(
+            warp::test::request()
+                .path("/stats")
+                .header("authorization", "Bearer wrong!")
+                .reply(&filter),
+        );
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = block_on(
+            warp::test::request()
+                .method("POST")
+                .path("/shutdown")
+                .reply(&filter),
+        );
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!shutdown.is_triggered());
+
+        let response = block_on(warp::test::request().path("/health").reply(&filter));
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = block_on(warp::test::request().path("/other").reply(&filter));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn admin_routes_lookup_and_purge_keys() {
+        let cache = Arc::new(MemoryCache::new());
+        let filter = routes("secret", cache.clone(), Shutdown::new());
+        block_on(cache.insert(String::from("field1"), 60, json!({"a": 1}))).unwrap();
+        block_on(cache.insert(String::from("field2"), 60, json!({"b": 2}))).unwrap();
+
+        let request = || warp::test::request().header("authorization", "Bearer secret");
+
+        let response = block_on(request().path("/stats").reply(&filter));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(&response)["cache"]["keys"], json!(2));
+        assert_eq!(body(&response)["cache"]["writes"], json!(2));
+
+        let response = block_on(request().path("/cache?key=field1").reply(&filter));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(&response)["values"][0]["value"], json!({"a": 1}));
+
+        let response = block_on(request().path("/cache?key=field3").reply(&filter));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = block_on(
+            request()
+                .method("DELETE")
+                .path("/cache?key=field1")
+                .reply(&filter),
+        );
+        assert_eq!(body(&response), json!({"purged": 1}));
+        assert!(block_on(cache.get(&String::from("field1"))).is_none());
+        assert!(block_on(cache.get(&String::from("field2"))).is_some());
+
+        let response = block_on(request().method("DELETE").path("/cache").reply(&filter));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(block_on(cache.get(&String::from("field2"))).is_some());
+    }
+
+    #[test]
+    fn admin_routes_purge_tags() {
+        let cache = Arc::new(MemoryCache::new());
+        let filter = routes("secret", cache.clone(), Shutdown::new());
+        block_on(cache.insert(String::from("product_id1"), 60, json!({"a": 1}))).unwrap();
+        block_on(cache.insert(String::from("products"), 60, json!({"b": 2}))).unwrap();
+        block_on(cache.tag(
+            &String::from("product_id1"),
+            60,
+            &[String::from("Product:1")],
+        ))
+        .unwrap();
+        block_on(cache.tag(&String::from("products"), 60, &[String::from("Product:1")])).unwrap();
+
+        let request = || {
+            warp::test::request()
+                .method("DELETE")
+                .header("authorization", "Bearer secret")
+        };
+
+        let response = block_on(
+            request()
+                .path("/cache?tag=Product:1&key=products")
+                .reply(&filter),
+        );
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = block_on(request().path("/cache?tag=Product%3A1").reply(&filter));
+        assert_eq!(body(&response), json!({"purged": 2}));
+        assert!(block_on(cache.get(&String::from("products"))).is_none());
+    }
+
+    #[test]
+    fn admin_token_must_not_be_empty() {
+        let config = |token: &str| {
+            serde_json::from_value::<AdminConfig>(
+                json!({"address": "127.0.0.1:3034", "token": token}),
+            )
+        };
+
+        assert!(config("secret").is_ok());
+        assert!(config("").is_err());
+        assert!(config("  ").is_err());
+    }
+
+    #[test]
+    fn admin_shutdown_stops_readiness() {
+        let shutdown = Shutdown::new();
+        let filter = routes("secret", Arc::new(MemoryCache::new()), shutdown.clone());
+
+        let response = block_on(warp::test::request().path("/ready").reply(&filter));
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = block_on(
+            warp::test::request()
+                .method("POST")
+                .path("/shutdown")
+                .header("authorization", "Bearer secret")
+                .reply(&filter),
+        );
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(shutdown.is_triggered());
+        block_on(shutdown.wait());
+
+        let response = block_on(warp::test::request().path("/ready").reply(&filter));
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
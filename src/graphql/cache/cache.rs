@@ -1,8 +1,70 @@
+use super::error::CacheError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use serde::Serialize;
 use serde_json::Value;
-//use std::future::Future;
 
-/*pub trait Cache {
-    fn insert(&self, key: String, duration_seconds: u16, value: Value); // -> Box<dyn Future<Output = ()>>;
-    fn get(&self, key: &String) -> Option<Vec<Value>>; //Box<dyn Future<Output = Option<Vec<Value>>>>;
-    fn clone_cache(&self) -> Box<dyn Cache>;
-}*/
+/// A value read from the cache, along with the time it was
+/// stored and the time it expires
+#[derive(Debug, Clone)]
+pub struct CachedValue {
+    pub value: Value,
+    pub created: DateTime<Utc>,
+    pub expiry: DateTime<Utc>,
+}
+
+/// Usage counters of the cache, since the proxy started
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    /// Number of keys currently stored
+    pub keys: usize,
+    pub reads: usize,
+    /// Reads which found at least one value
+    pub hits: usize,
+    pub writes: usize,
+}
+
+/// Storage of the cache. A key holds a list of values, each with its own expiry.
+/// The backend is picked at runtime, the handlers share it as `Arc<dyn CacheBackend>`
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Adds a value to the key, expiring after `duration_seconds`
+    async fn insert(
+        &self,
+        key: String,
+        duration_seconds: u16,
+        value: Value,
+    ) -> Result<(), CacheError>;
+
+    /// Returns the values of the key which are not expired, `None` if there are none
+    async fn get(&self, key: &str) -> Option<Vec<CachedValue>>;
+
+    /// Returns the values of each key, in the order of the keys
+    async fn get_many(&self, keys: &[String]) -> Vec<Option<Vec<CachedValue>>> {
+        join_all(keys.iter().map(|key| self.get(key))).await
+    }
+
+    /// Removes all the values stored under `key`. Returns `false` if there were none
+    async fn delete(&self, key: &str) -> Result<bool, CacheError>;
+
+    /// Associates the key with the tags, so that it's removed when one of them is purged.
+    /// `duration_seconds` is the one of the value just inserted under the key
+    async fn tag(
+        &self,
+        key: &str,
+        duration_seconds: u16,
+        tags: &[String],
+    ) -> Result<(), CacheError>;
+
+    /// Removes all the keys with the tag. Returns the number of keys removed
+    async fn purge_tag(&self, tag: &str) -> Result<usize, CacheError>;
+
+    /// Fails when the storage can't be reached
+    async fn ping(&self) -> Result<(), CacheError>;
+
+    /// Releases the resources of the backend, once the pending operations are done
+    async fn close(&self) -> Result<(), CacheError>;
+
+    async fn stats(&self) -> Result<CacheStats, CacheError>;
+}
@@ -1,4 +1,6 @@
+use super::cache::{CacheBackend, CacheStats, CachedValue};
 use super::error::CacheError;
+use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
 use rand::Rng;
 use serde_json::Value;
@@ -7,12 +9,12 @@ use std::convert::TryInto;
 use std::hash::Hash;
 use std::marker::Send;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::{Arc, Mutex, RwLock};
 use std::{thread, time};
 
 pub struct MemoryCache {
-    inner_cache: Arc<InnerCache<String, Value>>,
+    inner_cache: Arc<InnerCache<String, (DateTime<Utc>, Value)>>,
 }
 
 impl MemoryCache {
@@ -21,28 +23,82 @@ impl MemoryCache {
             inner_cache: InnerCache::new(),
         }
     }
+}
 
-    pub async fn insert(
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    async fn insert(
         &self,
         key: String,
         duration_seconds: u16,
         value: Value,
     ) -> Result<(), CacheError> {
-        self.inner_cache.insert(key, duration_seconds, value)
+        self.inner_cache
+            .insert(key, duration_seconds, (Utc::now(), value))
     }
 
-    pub async fn get(&self, key: &String) -> Option<Vec<Value>> {
-        let r = self.inner_cache.get(key);
+    async fn get(&self, key: &str) -> Option<Vec<CachedValue>> {
+        let r = self.inner_cache.get(&key.to_string());
 
         match r {
             None => None,
             Some(v) => {
-                let res = v.into_iter().map(|v| (*v).clone()).collect::<Vec<Value>>();
+                let res = v
+                    .into_iter()
+                    .map(|(expiry, v)| CachedValue {
+                        value: v.1.clone(),
+                        created: v.0,
+                        expiry: expiry,
+                    })
+                    .collect::<Vec<CachedValue>>();
 
                 Some(res)
             }
         }
     }
+
+    async fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        Ok(self.inner_cache.remove(&key.to_string()))
+    }
+
+    /// The key is untagged when it's removed, the duration is not needed
+    async fn tag(
+        &self,
+        key: &str,
+        _duration_seconds: u16,
+        tags: &[String],
+    ) -> Result<(), CacheError> {
+        self.inner_cache.tag(&key.to_string(), tags);
+
+        Ok(())
+    }
+
+    async fn purge_tag(&self, tag: &str) -> Result<usize, CacheError> {
+        Ok(self.inner_cache.purge_tag(tag))
+    }
+
+    /// The memory cache is always available
+    async fn ping(&self) -> Result<(), CacheError> {
+        Ok(())
+    }
+
+    /// Stops the cleanup thread. The values are kept until the cache is dropped
+    async fn close(&self) -> Result<(), CacheError> {
+        self.inner_cache.stop_cleanup_thread();
+
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<CacheStats, CacheError> {
+        let (reads, hits, _expired, writes) = self.inner_cache.get_ops_count();
+
+        Ok(CacheStats {
+            keys: self.inner_cache.len(),
+            reads: reads,
+            hits: hits,
+            writes: writes,
+        })
+    }
 }
 
 impl Clone for MemoryCache {
@@ -53,69 +109,110 @@ impl Clone for MemoryCache {
     }
 }
 
+/// Keys of each tag, and tags of each key. A key is untagged when it's removed from
+/// the store, so that the tags which are never purged don't keep growing
+struct Tags<K: Hash + Eq> {
+    keys: HashMap<String, HashSet<Arc<K>>>,
+    key_tags: HashMap<Arc<K>, HashSet<String>>,
+}
+
+impl<K: Hash + Eq> Tags<K> {
+    fn new() -> Tags<K> {
+        Tags {
+            keys: HashMap::new(),
+            key_tags: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, key: Arc<K>, tags: &[String]) {
+        for tag in tags {
+            self.keys
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        self.key_tags
+            .entry(key)
+            .or_default()
+            .extend(tags.iter().cloned());
+    }
+
+    fn remove_key(&mut self, key: &K) {
+        for tag in self.key_tags.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.keys.get_mut(&tag) {
+                keys.remove(key);
+                if keys.len() == 0 {
+                    self.keys.remove(&tag);
+                }
+            }
+        }
+    }
+
+    /// Returns the keys of the tag
+    fn remove_tag(&mut self, tag: &str) -> Vec<Arc<K>> {
+        let keys = self.keys.remove(tag).unwrap_or_default();
+        for key in keys.iter() {
+            if let Some(tags) = self.key_tags.get_mut(key) {
+                tags.remove(tag);
+                if tags.len() == 0 {
+                    self.key_tags.remove(key);
+                }
+            }
+        }
+
+        keys.into_iter().collect()
+    }
+}
+
 struct InnerCache<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> {
     store: Arc<RwLock<HashMap<Arc<K>, Vec<(DateTime<Utc>, Arc<T>)>>>>,
+    /// Locked after the store when both are needed
+    tags: Arc<Mutex<Tags<K>>>,
     read_ops: AtomicUsize,
+    hit_ops: AtomicUsize,
     write_ops: AtomicUsize,
     expired_ops: AtomicUsize,
     added_entry_sender: Mutex<Sender<Arc<K>>>,
-    thread_completed_receiver: Mutex<Receiver<()>>,
     stop_loop_sender: Mutex<Sender<()>>,
 }
 
 impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> Drop for InnerCache<K, T> {
+    // Dropping must not block, as the cache can be dropped by an async task:
+    // the cleanup thread is signaled, and stops on its own
     fn drop(&mut self) {
-        println!("Dropping");
-
-        match self.stop_loop_sender.lock().unwrap().send(()) {
-            Ok(_) => {}
-            Err(_) => println!("Error while signaling cleanup thread to stop"),
-        }
-
-        match self.thread_completed_receiver.lock().unwrap().recv() {
-            Ok(_) => println!("Cleanup thread terminated"),
-            Err(_) => println!("GAAAAAAAAAAAAAAAAAAAAAAAA"),
-        }
+        self.stop_cleanup_thread();
     }
 }
 
 impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<K, T> {
-    pub fn store(&self) -> Arc<RwLock<HashMap<Arc<K>, Vec<(DateTime<Utc>, Arc<T>)>>>> {
-        self.store.clone()
-    }
-
     pub fn new() -> Arc<InnerCache<K, T>> {
         let store = Arc::new(RwLock::new(HashMap::new()));
-        let (thread_completed_sender, thread_completed_receiver) = channel();
+        let tags = Arc::new(Mutex::new(Tags::new()));
         let (added_entry_sender, added_entry_receiver) = channel();
         let (stop_loop_sender, stop_loop_receiver) = channel();
 
         let cache = InnerCache {
             store: store.clone(),
+            tags: tags.clone(),
             read_ops: AtomicUsize::new(0),
+            hit_ops: AtomicUsize::new(0),
             write_ops: AtomicUsize::new(0),
             expired_ops: AtomicUsize::new(0),
             added_entry_sender: Mutex::new(added_entry_sender),
-            thread_completed_receiver: Mutex::new(thread_completed_receiver),
             stop_loop_sender: Mutex::new(stop_loop_sender),
         };
 
         let result = Arc::new(cache);
 
-        InnerCache::start_cleanup_thread(
-            store,
-            stop_loop_receiver,
-            thread_completed_sender,
-            added_entry_receiver,
-        );
+        InnerCache::start_cleanup_thread(store, tags, stop_loop_receiver, added_entry_receiver);
 
         result
     }
 
     fn start_cleanup_thread(
         store: Arc<RwLock<HashMap<Arc<K>, Vec<(DateTime<Utc>, Arc<T>)>>>>,
+        tags: Arc<Mutex<Tags<K>>>,
         stop_loop_receiver: Receiver<()>,
-        thread_completed_sender: Sender<()>,
         added_entry_receiver: Receiver<Arc<K>>,
     ) {
         thread::spawn(move || {
@@ -124,8 +221,10 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                 let mut rng = rand::thread_rng();
 
                 loop {
-                    if let Ok(_) = stop_loop_receiver.try_recv() {
-                        break;
+                    // The sender is dropped along with the cache
+                    match stop_loop_receiver.try_recv() {
+                        Err(TryRecvError::Empty) => {}
+                        _ => break,
                     }
 
                     let now = Utc::now();
@@ -175,6 +274,7 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                                     vec.retain(|(d, _)| d > &now);
                                     if vec.len() == 0 {
                                         s.remove(key);
+                                        tags.lock().unwrap().remove_key(key);
                                         keys.remove(key);
                                     }
                                 }
@@ -195,7 +295,10 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                     };
 
                     if wait {
-                        thread::sleep(time::Duration::from_millis(5000));
+                        match stop_loop_receiver.recv_timeout(time::Duration::from_millis(5000)) {
+                            Err(RecvTimeoutError::Timeout) => {}
+                            _ => break,
+                        }
                     } else {
                         println!("Cleaned more than 5% of keys, don't wait");
 
@@ -209,18 +312,16 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                     }
                 }
 
-                println!("Start memory free {:?}", Utc::now());
-
-                match thread_completed_sender.send(()) {
-                    Ok(_) => println!("Sent completed"),
-                    Err(_) => println!("Failed to send completed"),
-                };
+                println!("Cleanup thread stopped at {:?}", Utc::now());
             }
-
-            println!("Memory freed 2 {:?}", Utc::now());
         });
     }
 
+    fn stop_cleanup_thread(&self) {
+        // The thread may have already stopped, the error can be ignored
+        let _ = self.stop_loop_sender.lock().unwrap().send(());
+    }
+
     pub fn insert(&self, key: K, duration_seconds: u16, value: T) -> Result<(), CacheError> {
         let now = Utc::now();
         let expiry_date = now + Duration::seconds(duration_seconds.try_into().unwrap());
@@ -247,7 +348,8 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
         Ok(())
     }
 
-    pub fn get(&self, key: &K) -> Option<Vec<Arc<T>>> {
+    /// Returns the values which are not expired, along with their expiry date
+    pub fn get(&self, key: &K) -> Option<Vec<(DateTime<Utc>, Arc<T>)>> {
         let now = Utc::now();
 
         let (result, cleanup) = match self.store.read().unwrap().get(key) {
@@ -255,7 +357,7 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                 let r = vec
                     .iter()
                     .filter(|(d, _)| d > &now)
-                    .map(|(_, v)| v.clone())
+                    .map(|(d, v)| (*d, v.clone()))
                     .collect::<Vec<_>>();
 
                 let new_len = r.len();
@@ -276,6 +378,7 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
                     vec.retain(|(expiry_date, _)| expiry_date > &now);
                     if vec.len() == 0 {
                         cache.remove(key);
+                        self.tags.lock().unwrap().remove_key(key);
                         self.expired_ops.fetch_add(1, Ordering::Relaxed);
                     }
                 }
@@ -284,15 +387,130 @@ impl<K: 'static + Hash + Eq + Send + Sync, T: 'static + Sync + Send> InnerCache<
         }
 
         self.read_ops.fetch_add(1, Ordering::Relaxed);
+        if result.is_some() {
+            self.hit_ops.fetch_add(1, Ordering::Relaxed);
+        }
 
         result
     }
 
-    pub fn get_ops_count(&self) -> (usize, usize, usize) {
+    pub fn remove(&self, key: &K) -> bool {
+        let mut store = self.store.write().unwrap();
+        self.tags.lock().unwrap().remove_key(key);
+        store.remove(key).is_some()
+    }
+
+    /// Keys which are not stored are not tagged
+    pub fn tag(&self, key: &K, tags: &[String]) {
+        let store = self.store.read().unwrap();
+        if let Some((key, _)) = store.get_key_value(key) {
+            self.tags.lock().unwrap().add(key.clone(), tags);
+        }
+    }
+
+    /// Returns the number of keys removed
+    pub fn purge_tag(&self, tag: &str) -> usize {
+        let keys = self.tags.lock().unwrap().remove_tag(tag);
+
+        keys.iter().filter(|key| self.remove(key)).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.read().unwrap().len()
+    }
+
+    /// Returns the number of reads, hits, expired keys and writes
+    pub fn get_ops_count(&self) -> (usize, usize, usize, usize) {
         (
             self.read_ops.fetch_add(0, Ordering::Relaxed),
+            self.hit_ops.fetch_add(0, Ordering::Relaxed),
             self.expired_ops.fetch_add(0, Ordering::Relaxed),
             self.write_ops.fetch_add(0, Ordering::Relaxed),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryCache;
+    use crate::graphql::cache::CacheBackend;
+    use futures::executor::block_on;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn dropping_the_cache_does_not_wait_for_the_cleanup_thread() {
+        let cache = MemoryCache::new();
+        block_on(cache.insert(String::from("key"), 60, json!(1))).unwrap();
+
+        // Let the cleanup thread go to sleep
+        std::thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        block_on(cache.close()).unwrap();
+        drop(cache);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn values_of_multiple_keys_are_read_in_order() {
+        let cache = MemoryCache::new();
+        let key = |k: &str| String::from(k);
+        block_on(cache.insert(key("a"), 60, json!(1))).unwrap();
+        block_on(cache.insert(key("c"), 60, json!(3))).unwrap();
+
+        let values = block_on(cache.get_many(&[key("c"), key("b"), key("a")]))
+            .into_iter()
+            .map(|v| v.map(|v| v.into_iter().map(|v| v.value).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![Some(vec![json!(3)]), None, Some(vec![json!(1)])]
+        );
+        assert!(block_on(cache.get_many(&[])).is_empty());
+    }
+
+    #[test]
+    fn tagged_keys_are_purged() {
+        let cache = MemoryCache::new();
+        let key = |k: &str| String::from(k);
+        block_on(cache.insert(key("product1"), 60, json!(1))).unwrap();
+        block_on(cache.insert(key("product2"), 60, json!(2))).unwrap();
+        block_on(cache.insert(key("user1"), 60, json!(3))).unwrap();
+        block_on(cache.tag(&key("product1"), 60, &[key("Product:1"), key("catalog")])).unwrap();
+        block_on(cache.tag(&key("product2"), 60, &[key("catalog")])).unwrap();
+
+        assert_eq!(block_on(cache.purge_tag("Product:1")).unwrap(), 1);
+        assert!(block_on(cache.get(&key("product1"))).is_none());
+        assert!(block_on(cache.get(&key("product2"))).is_some());
+
+        // product1 is already gone
+        assert_eq!(block_on(cache.purge_tag("catalog")).unwrap(), 1);
+        assert!(block_on(cache.get(&key("product2"))).is_none());
+        assert!(block_on(cache.get(&key("user1"))).is_some());
+        assert_eq!(block_on(cache.purge_tag("catalog")).unwrap(), 0);
+    }
+
+    #[test]
+    fn removed_keys_are_untagged() {
+        let cache = MemoryCache::new();
+        let key = |k: &str| String::from(k);
+        let tags = || cache.inner_cache.tags.lock().unwrap().keys.len();
+        // The expired key must not be removed by the cleanup thread
+        block_on(cache.close()).unwrap();
+        block_on(cache.insert(key("expired"), 0, json!(1))).unwrap();
+        block_on(cache.insert(key("product1"), 60, json!(2))).unwrap();
+        block_on(cache.tag(&key("expired"), 0, &[key("field:expired")])).unwrap();
+        block_on(cache.tag(&key("product1"), 60, &[key("Product:1"), key("catalog")])).unwrap();
+        block_on(cache.tag(&key("missing"), 60, &[key("missing")])).unwrap();
+        assert_eq!(tags(), 3);
+
+        // The expired key is removed when it's read
+        assert!(block_on(cache.get(&key("expired"))).is_none());
+        assert_eq!(tags(), 2);
+
+        assert_eq!(block_on(cache.purge_tag("Product:1")).unwrap(), 1);
+        assert_eq!(tags(), 0);
+        assert!(cache.inner_cache.tags.lock().unwrap().key_tags.is_empty());
+    }
+}
@@ -3,10 +3,106 @@ mod error;
 mod memory_cache;
 mod redis_cache;
 
-#[cfg(not(test))]
-pub type Cache = RedisCache;
-#[cfg(test)]
-pub type Cache = MemoryCache;
+use error::CacheError;
+use serde::Deserialize;
+use std::sync::Arc;
 
+pub use cache::{CacheBackend, CachedValue};
 pub use memory_cache::MemoryCache;
 pub use redis_cache::RedisCache;
+
+/// Storage of the cache, set with the `backend` key of the configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Shared by all the instances of the proxy, and kept when it restarts
+    #[default]
+    Redis,
+    /// In the memory of the process, for a single instance or for development
+    Memory,
+}
+
+/// Creates the cache, connecting to Redis if it's the selected backend
+pub async fn create_backend(
+    backend: Backend,
+    redis_connection_string: &str,
+) -> Result<Arc<dyn CacheBackend>, CacheError> {
+    match backend {
+        Backend::Redis => Ok(Arc::new(RedisCache::new(redis_connection_string).await?)),
+        Backend::Memory => Ok(Arc::new(MemoryCache::new())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheBackend, MemoryCache, RedisCache};
+    use serde_json::json;
+
+    /// Checks the behavior shared by the backends. The keys start with `prefix`,
+    /// so that they don't collide with the data of a shared Redis database
+    async fn check_backend(cache: &dyn CacheBackend, prefix: &str) {
+        let key = |k: &str| format!("{}{}", prefix, k);
+        let values = |v: Option<Vec<super::CachedValue>>| {
+            v.map(|v| v.into_iter().map(|v| v.value).collect::<Vec<_>>())
+        };
+
+        cache.insert(key("a"), 60, json!(1)).await.unwrap();
+        cache.insert(key("a"), 60, json!(2)).await.unwrap();
+        cache.insert(key("b"), 60, json!(3)).await.unwrap();
+        assert_eq!(
+            values(cache.get(&key("a")).await),
+            Some(vec![json!(1), json!(2)])
+        );
+        assert!(cache.get(&key("c")).await.is_none());
+
+        let many = cache
+            .get_many(&[key("b"), key("c"), key("a")])
+            .await
+            .into_iter()
+            .map(values)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            many,
+            vec![Some(vec![json!(3)]), None, Some(vec![json!(1), json!(2)])]
+        );
+
+        assert!(cache.delete(&key("b")).await.unwrap());
+        assert!(!cache.delete(&key("b")).await.unwrap());
+        assert!(cache.get(&key("b")).await.is_none());
+
+        cache.insert(key("c"), 60, json!(4)).await.unwrap();
+        cache.tag(&key("a"), 60, &[key("tag1")]).await.unwrap();
+        cache
+            .tag(&key("c"), 60, &[key("tag1"), key("tag2")])
+            .await
+            .unwrap();
+        assert_eq!(cache.purge_tag(&key("tag1")).await.unwrap(), 2);
+        assert!(cache.get(&key("a")).await.is_none());
+        assert!(cache.get(&key("c")).await.is_none());
+        assert_eq!(cache.purge_tag(&key("tag1")).await.unwrap(), 0);
+        assert_eq!(cache.purge_tag(&key("tag2")).await.unwrap(), 0);
+
+        let stats = cache.stats().await.unwrap();
+        assert_eq!(stats.writes, 4);
+        assert_eq!(stats.reads, 8);
+        assert_eq!(stats.hits, 3);
+
+        cache.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_backend() {
+        check_backend(&MemoryCache::new(), "").await;
+    }
+
+    /// Run with `cargo test -- --ignored`, against `REDIS_URL` (a local server by default)
+    #[tokio::test]
+    #[ignore = "requires a Redis server"]
+    async fn redis_backend() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1/"));
+        let cache = RedisCache::new(&url).await.unwrap();
+        let prefix = format!("test:{}:", rand::random::<u32>());
+
+        check_backend(&cache, &prefix).await;
+    }
+}
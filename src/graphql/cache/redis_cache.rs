@@ -1,14 +1,27 @@
+use super::cache::{CacheBackend, CacheStats, CachedValue};
 use super::error::CacheError;
 use ::redis::aio::MultiplexedConnection;
-use chrono::Utc;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
 use redis::AsyncCommands;
 use redis::{RedisError, RedisResult};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 pub struct RedisCache {
     inner_cache: InternalRedisCache,
+    counters: Arc<Counters>,
+}
+
+/// Operations done by this process. Shared by the clones of the cache
+#[derive(Default)]
+struct Counters {
+    reads: AtomicUsize,
+    hits: AtomicUsize,
+    writes: AtomicUsize,
 }
 
 impl RedisCache {
@@ -22,27 +35,148 @@ impl RedisCache {
 
         Ok(RedisCache {
             inner_cache: inner_cache,
+            counters: Arc::new(Counters::default()),
         })
     }
+}
 
-    pub async fn insert(
+#[async_trait]
+impl CacheBackend for RedisCache {
+    async fn insert(
         &self,
         key: String,
         duration_seconds: u16,
         value: Value,
     ) -> Result<(), CacheError> {
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
         self.inner_cache.insert(key, duration_seconds, value).await
     }
 
-    pub async fn get(&self, key: &String) -> Option<Vec<Value>> {
+    async fn get(&self, key: &str) -> Option<Vec<CachedValue>> {
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
         match self.inner_cache.get(key).await {
             Ok(r) => match r {
                 None => None,
-                s @ Some(_) => s,
+                s @ Some(_) => {
+                    self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                    s
+                }
             },
             _ => None,
         }
     }
+
+    /// The keys are read with a single round trip
+    async fn get_many(&self, keys: &[String]) -> Vec<Option<Vec<CachedValue>>> {
+        self.counters.reads.fetch_add(keys.len(), Ordering::Relaxed);
+        match self.inner_cache.get_many(keys).await {
+            Ok(r) => {
+                let hits = r.iter().filter(|values| values.is_some()).count();
+                self.counters.hits.fetch_add(hits, Ordering::Relaxed);
+                r
+            }
+            _ => keys.iter().map(|_| None).collect(),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        let removed: usize = self.inner_cache.connection.clone().del(key).await?;
+
+        Ok(removed > 0)
+    }
+
+    /// The keys of a tag are stored in a sorted set, scored by the expiry of their values.
+    /// The keys which expired are removed from it on each write, and the set outlives
+    /// all of them: values are kept for `u16::MAX` seconds at most
+    async fn tag(
+        &self,
+        key: &str,
+        duration_seconds: u16,
+        tags: &[String],
+    ) -> Result<(), CacheError> {
+        let now = Utc::now().timestamp();
+        let expiry = now + i64::from(duration_seconds);
+
+        let mut pipe = redis::pipe();
+        for tag in tags {
+            let tag_key = tag_key(tag);
+            // GT keeps the expiry of the value which lives the longest (Redis 6.2+)
+            pipe.zrembyscore(&tag_key, 0, now)
+                .ignore()
+                .cmd("ZADD")
+                .arg(&tag_key)
+                .arg("GT")
+                .arg(expiry)
+                .arg(key)
+                .ignore()
+                .expire(&tag_key, u16::MAX as usize)
+                .ignore();
+        }
+        let _: () = pipe
+            .query_async(&mut self.inner_cache.connection.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    /// The set of the tag is removed as well. Each key is removed by its own command,
+    /// as the keys of a tag may be stored on different nodes of a Redis Cluster
+    async fn purge_tag(&self, tag: &str) -> Result<usize, CacheError> {
+        let tag_key = tag_key(tag);
+        let mut connection = self.inner_cache.connection.clone();
+
+        // The set is read and removed atomically, keys tagged meanwhile are not lost.
+        // The keys which expired are skipped, their values are already gone
+        let (keys,): (Vec<String>,) = redis::pipe()
+            .atomic()
+            .zrangebyscore(&tag_key, Utc::now().timestamp(), "+inf")
+            .del(&tag_key)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+        if keys.len() == 0 {
+            return Ok(0);
+        }
+
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            pipe.del(key);
+        }
+        let removed: Vec<usize> = pipe.query_async(&mut connection).await?;
+
+        Ok(removed.iter().sum())
+    }
+
+    async fn ping(&self) -> Result<(), CacheError> {
+        let _: String = redis::cmd("PING")
+            .query_async(&mut self.inner_cache.connection.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Asks the server to close the connection, once the pending commands are answered.
+    /// The cache can't be used afterwards
+    async fn close(&self) -> Result<(), CacheError> {
+        let _: String = redis::cmd("QUIT")
+            .query_async(&mut self.inner_cache.connection.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<CacheStats, CacheError> {
+        let keys: usize = redis::cmd("DBSIZE")
+            .query_async(&mut self.inner_cache.connection.clone())
+            .await?;
+
+        Ok(CacheStats {
+            keys: keys,
+            reads: self.counters.reads.load(Ordering::Relaxed),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            writes: self.counters.writes.load(Ordering::Relaxed),
+        })
+    }
 }
 
 impl Clone for RedisCache {
@@ -51,6 +185,7 @@ impl Clone for RedisCache {
             inner_cache: InternalRedisCache {
                 connection: self.inner_cache.connection.clone(),
             },
+            counters: self.counters.clone(),
         }
     }
 }
@@ -59,9 +194,10 @@ struct InternalRedisCache {
     pub connection: MultiplexedConnection,
 }
 
+/// The items are stored in a sorted set, scored by their expiry date
 #[derive(Serialize, Deserialize)]
 struct InternalCacheItem {
-    pub expiry_date_utc: u64,
+    pub creation_date_utc: i64,
     pub value: Value,
 }
 
@@ -81,7 +217,11 @@ impl InternalRedisCache {
         let now: isize = Utc::now().timestamp().try_into().unwrap();
         let offset: isize = duration_seconds.try_into().unwrap();
         let score = now + offset;
-        let json = serde_json::to_string(&value).unwrap();
+        let item = InternalCacheItem {
+            creation_date_utc: Utc::now().timestamp(),
+            value: value,
+        };
+        let json = serde_json::to_string(&item).unwrap();
 
         let res: RedisResult<redis::Value> = self.connection.clone().zadd(key, json, score).await;
 
@@ -93,22 +233,99 @@ impl InternalRedisCache {
         Ok(())
     }
 
-    async fn get(&self, key: &String) -> Result<Option<Vec<Value>>, CacheError> {
+    async fn get(&self, key: &str) -> Result<Option<Vec<CachedValue>>, CacheError> {
         let now: isize = Utc::now().timestamp().try_into().unwrap();
-        let (_del_result, get_result): (redis::Value, Vec<String>) = redis::pipe()
+        let (_del_result, get_result): (redis::Value, Vec<(String, i64)>) = redis::pipe()
             .zrembyscore(key, 0isize, now)
-            .zrangebyscore(key, now, "+inf")
+            .zrangebyscore_withscores(key, now, "+inf")
             .query_async(&mut self.connection.clone())
             .await?;
 
-        if get_result.len() > 0 {
-            let result = get_result
-                .iter()
-                .map(|s| serde_json::from_str(s).unwrap())
-                .collect::<Vec<Value>>();
-            Ok(Some(result))
-        } else {
-            Ok(None)
+        Ok(to_cached_values(get_result))
+    }
+
+    /// Pipelines the reads of the keys
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<Option<Vec<CachedValue>>>, CacheError> {
+        if keys.len() == 0 {
+            return Ok(vec![]);
+        }
+
+        let now: isize = Utc::now().timestamp().try_into().unwrap();
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.zrembyscore(key, 0isize, now)
+                .ignore()
+                .zrangebyscore_withscores(key, now, "+inf");
         }
+        let get_results: Vec<Vec<(String, i64)>> =
+            pipe.query_async(&mut self.connection.clone()).await?;
+
+        Ok(get_results.into_iter().map(to_cached_values).collect())
+    }
+}
+
+/// Field names can't contain `:`, the keys don't collide with the ones of the fields
+fn tag_key(tag: &str) -> String {
+    format!("tag:{}", tag)
+}
+
+fn to_cached_values(items: Vec<(String, i64)>) -> Option<Vec<CachedValue>> {
+    let values = items
+        .iter()
+        .filter_map(|(s, expiry)| to_cached_value(s, *expiry))
+        .collect::<Vec<_>>();
+
+    if values.len() > 0 {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+/// Returns None if the item is not JSON, e.g. if it was stored by another application
+fn to_cached_value(json: &str, expiry: i64) -> Option<CachedValue> {
+    let expiry = Utc.timestamp(expiry, 0);
+
+    // Items stored before the creation date was tracked contain the bare value
+    match serde_json::from_str::<InternalCacheItem>(json) {
+        Ok(item) => Some(CachedValue {
+            value: item.value,
+            created: Utc.timestamp(item.creation_date_utc, 0),
+            expiry: expiry,
+        }),
+        Err(_) => match serde_json::from_str(json) {
+            Ok(value) => Some(CachedValue {
+                value: value,
+                created: Utc::now(),
+                expiry: expiry,
+            }),
+            Err(e) => {
+                println!("Skipping invalid cached value: {}", e);
+                None
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_cached_values;
+    use serde_json::json;
+
+    #[test]
+    fn invalid_items_are_skipped() {
+        let items = vec![
+            (String::from("not json"), 0),
+            (String::from(r#"{"creation_date_utc":0,"value":1}"#), 0),
+            (String::from("2"), 0),
+        ];
+        let values = to_cached_values(items)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![json!(1), json!(2)]);
+
+        assert!(to_cached_values(vec![(String::from("not json"), 0)]).is_none());
     }
 }
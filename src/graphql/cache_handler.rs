@@ -1,61 +1,484 @@
-use super::cache::Cache;
-use crate::graphql::json::{extract_mut, merge_json};
+use super::cache::{CacheBackend, CachedValue};
+use crate::graphql::cache_policy::{
+    rule_cache_hints, rule_cache_tags, schema_cache_hints, CacheRule, ResponseCachePolicy,
+};
+use crate::graphql::invalidation::InvalidationRule;
+use crate::graphql::json::{extract_mut, list_index, merge_json};
+use crate::graphql::normalization::{select_fields, NormalizationConfig, REFERENCE_FIELD};
 use crate::graphql::parser::{
-    expand_operation, Error, Field, FragmentDefinition, Operation, OperationType, Parameter,
-    ParameterValue, Traversable,
+    expand_operation, flatten_inline_fragments, Directive, Error, Field, FragmentDefinition,
+    Operation, OperationType, Parameter, ParameterValue, Traversable, Variable,
+};
+use crate::graphql::schema::Schema;
+use crate::graphql_deserializer::{
+    CacheHint, CacheHintDto, CacheScope, GraphQLResponse, UpstreamResponse,
 };
-use crate::graphql_deserializer::{CacheHint, CacheScope, GraphQLResponse};
-use futures::future::join_all;
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use serde_json::map::Map;
 use serde_json::value::Value;
 use serde_json::{from_value, json};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::sync::Arc;
+
+/// The response to an operation, along with the cache policy
+/// of the values it is made of
+#[derive(Debug)]
+pub struct OperationResponse {
+    pub body: Value,
+    pub cache_policy: ResponseCachePolicy,
+}
+
+/// Configuration of the cache shared by the operations: the schema of the
+/// upstream server and the rules deciding what is cached and invalidated
+#[derive(Debug, Default)]
+pub struct CacheContext {
+    pub schema: Option<Schema>,
+    /// Cache policies of the fields the upstream server sends no hint for
+    pub cache_rules: Vec<CacheRule>,
+    /// Cache entries purged by the mutations
+    pub invalidation_rules: Vec<InvalidationRule>,
+    /// Stores the entities once, disabled when missing
+    pub normalization: Option<NormalizationConfig>,
+}
 
 /// Executes an operation against the cache.
 /// Any residual field (which couldn't be solved by the cache) is forwarded to the get_fn() function
+/// The fields the upstream server didn't send a cache hint for use the cache rules
+/// of the configuration and the @cacheControl directives of the schema, if provided
+/// With a normalization config, the entities are stored once and shared by the fields
+/// Mutations remove the cache entries of the invalidation rules, and the entities they return
 pub async fn execute_operation<'a, F, Fut>(
     operation: Operation<'a>,
     fragment_definitions: Vec<FragmentDefinition<'a>>,
     variables: Map<String, Value>,
-    cache: Cache,
+    cache: Arc<dyn CacheBackend>,
+    context: &'a CacheContext,
     user_id: Option<String>,
     get_fn: F,
-) -> Result<Value, Error>
+) -> Result<OperationResponse, Error>
 where
     F: FnOnce(Operation<'a>, Map<String, Value>) -> Fut,
-    Fut: Future<Output = (Result<Value, Error>, Operation<'a>, Map<String, Value>)>,
+    Fut: Future<
+        Output = (
+            Result<UpstreamResponse, Error>,
+            Operation<'a>,
+            Map<String, Value>,
+        ),
+    >,
 {
+    let schema = context.schema.as_ref();
+    let cache_rules = &context.cache_rules;
+    let invalidation_rules = &context.invalidation_rules;
+    let normalization = context.normalization.as_ref();
+
     // If the operation is not a query, forward the whole document to the getfn() function
     if operation.operation_type != OperationType::Query {
-        let (result, _, _) = get_fn(operation, variables).await;
-        return result;
+        let (result, operation, variables) = get_fn(operation, variables).await;
+        if let (Ok(response), OperationType::Mutation) = (&result, operation.operation_type) {
+            let variables = resolve_default_values(&operation.variables, variables);
+            invalidate_cache(
+                cache.as_ref(),
+                &operation,
+                &variables,
+                &response.body,
+                invalidation_rules,
+                normalization,
+            )
+            .await;
+        }
+
+        return result.map(|r| OperationResponse {
+            body: r.body,
+            cache_policy: ResponseCachePolicy::no_store(),
+        });
     }
 
+    // Variables not provided by the client take their default value
+    let variables = resolve_default_values(&operation.variables, variables);
+
+    // Drop the fields excluded by @skip/@include, so that they
+    // are neither looked up in the cache nor sent upstream
+    let (operation, fragment_definitions) =
+        apply_conditional_directives(operation, fragment_definitions, &variables)?;
+
     // Replace all fragments with actual fields
     // Expanded operation does not contain any fragment
     let expanded_operation = expand_operation(operation, fragment_definitions)?;
-    let (residual_operation, data_from_cache) =
-        match_operation_with_cache(expanded_operation, &variables, &user_id, &cache).await;
+    let (residual_operation, data_from_cache, cache_policy) = match_operation_with_cache(
+        expanded_operation,
+        &variables,
+        &user_id,
+        cache.as_ref(),
+        normalization,
+    )
+    .await;
 
     match residual_operation {
         Some(operation) => {
-            let deduplicated_operation = operation.deduplicate_fields()?;
+            // @cacheControl is resolved by the proxy, the upstream server doesn't need to know about it
+            let (operation, directive_hints) = extract_cache_control_directives(operation)?;
+            let deduplicated_operation = match normalization {
+                Some(n) => n.add_key_fields(operation.deduplicate_fields()?, schema),
+                None => operation.deduplicate_fields()?,
+            };
 
             let (response, op, var) = get_fn(deduplicated_operation, variables).await;
-            let result: GraphQLResponse = from_value(response?)?;
+            let response = response?;
+
+            // Without data, the upstream server couldn't execute the operation
+            // (or failed at the root): its response is returned as it is
+            match response.body.get("data") {
+                Some(Value::Object(_)) => {}
+                _ if response.body.is_object() => {
+                    return Ok(OperationResponse {
+                        body: response.body,
+                        cache_policy: ResponseCachePolicy::no_store(),
+                    })
+                }
+                _ => {
+                    return Err(Error::Upstream(String::from(
+                        "Invalid response from the upstream server",
+                    )))
+                }
+            }
+
+            let mut result: GraphQLResponse = match from_value(response.body) {
+                Ok(r) => r,
+                Err(_) => {
+                    return Err(Error::Upstream(String::from(
+                        "Invalid response from the upstream server",
+                    )))
+                }
+            };
+            let errors = std::mem::take(&mut result.errors);
+            if let Some(header) = response.cache_control {
+                result.apply_cache_control_header(&header);
+            }
+            let (rule_hints, override_rule_hints) = rule_cache_hints(cache_rules, schema, &op);
+            result.add_default_cache_hints(rule_hints);
+            if let Some(schema) = schema {
+                result.add_default_cache_hints(schema_cache_hints(schema, &op));
+            }
+            result.override_cache_hints(override_rule_hints);
+            result.restrict_cache_hints(directive_hints);
+            let mut cache_tags = rule_cache_tags(cache_rules, schema, &op);
+            cache_tags.push((vec![], result.cache_tags()));
             let (mut response_data, hints) = result.compress_cache_hints();
 
-            update_cache(cache, &user_id, hints, &op, &var).await;
+            // The fields with errors are null (or missing), they must not be cached
+            let upstream_policy = if errors.len() == 0 {
+                let policy = ResponseCachePolicy::from_cache_hints(&response_data, &hints);
+                update_cache(
+                    cache,
+                    &user_id,
+                    hints,
+                    &cache_tags,
+                    &op,
+                    &var,
+                    normalization,
+                )
+                .await;
+                policy
+            } else {
+                ResponseCachePolicy::no_store()
+            };
             merge_json(&mut response_data, data_from_cache);
 
-            let final_result = expand_response(response_data, &op, &operation);
+            let mut final_result = expand_response(response_data, &op, &operation);
+            // The key fields added to the forwarded operation are not part of the response
+            if normalization.is_some() {
+                select_fields(
+                    &mut final_result,
+                    &flatten_inline_fragments(&operation.fields),
+                );
+            }
+            let body = if errors.len() == 0 {
+                json!({ "data": final_result })
+            } else {
+                json!({ "data": final_result, "errors": errors })
+            };
+
+            Ok(OperationResponse {
+                body: body,
+                cache_policy: match cache_policy {
+                    Some(p) => p.restrict(upstream_policy),
+                    None => upstream_policy,
+                },
+            })
+        }
+        None => Ok(OperationResponse {
+            body: json!({ "data": data_from_cache }),
+            cache_policy: cache_policy.unwrap_or_else(ResponseCachePolicy::no_store),
+        }),
+    }
+}
+
+fn resolve_default_values<'a>(
+    variable_definitions: &[Variable<'a>],
+    mut variables: Map<String, Value>,
+) -> Map<String, Value> {
+    for definition in variable_definitions {
+        match (
+            &definition.default_value,
+            variables.contains_key(definition.name),
+        ) {
+            (Some(default_value), false) => {
+                let value = default_value.to_json(&variables);
+                variables.insert(definition.name.to_string(), value);
+            }
+            _ => {}
+        }
+    }
+
+    variables
+}
+
+/// Evaluates the @skip and @include directives of the operation and of the fragment definitions.
+/// Excluded fields are removed, the directives are stripped from the fields that are kept.
+fn apply_conditional_directives<'a>(
+    operation: Operation<'a>,
+    fragment_definitions: Vec<FragmentDefinition<'a>>,
+    variables: &Map<String, Value>,
+) -> Result<(Operation<'a>, Vec<FragmentDefinition<'a>>), Error> {
+    let fields = filter_conditional_fields(operation.fields, variables)?;
+
+    let mut new_fragment_definitions = Vec::with_capacity(fragment_definitions.len());
+    for fragment in fragment_definitions {
+        new_fragment_definitions.push(FragmentDefinition {
+            name: fragment.name,
+            r#type: fragment.r#type,
+            directives: fragment.directives,
+            fields: filter_conditional_fields(fragment.fields, variables)?,
+        });
+    }
+
+    let new_operation = Operation {
+        operation_type: operation.operation_type,
+        name: operation.name,
+        variables: operation.variables,
+        directives: operation.directives,
+        fields: fields,
+    };
+
+    Ok((new_operation, new_fragment_definitions))
+}
+
+fn filter_conditional_fields<'a>(
+    fields: Vec<Field<'a>>,
+    variables: &Map<String, Value>,
+) -> Result<Vec<Field<'a>>, Error> {
+    let mut result = Vec::with_capacity(fields.len());
+
+    for field in fields {
+        if !is_field_included(field.get_directives(), variables)? {
+            continue;
+        }
+
+        let new_field = match field {
+            Field::Field {
+                alias,
+                name,
+                parameters,
+                directives,
+                fields: subfields,
+            } => Field::Field {
+                alias: alias,
+                name: name,
+                parameters: parameters,
+                directives: remove_conditional_directives(directives),
+                fields: filter_conditional_fields(subfields, variables)?,
+            },
+            Field::Fragment { name, directives } => Field::Fragment {
+                name: name,
+                directives: remove_conditional_directives(directives),
+            },
+            Field::InlineFragment {
+                type_condition,
+                directives,
+                fields: subfields,
+            } => {
+                let directives = remove_conditional_directives(directives);
+                let subfields = filter_conditional_fields(subfields, variables)?;
+
+                // Expanded operations keep the conditions of the fragment spreads in
+                // inline fragments, which are equivalent to their fields once applied
+                if type_condition.is_none() && directives.len() == 0 {
+                    result.extend(subfields);
+                    continue;
+                }
+
+                Field::InlineFragment {
+                    type_condition: type_condition,
+                    directives: directives,
+                    fields: subfields,
+                }
+            }
+        };
+
+        result.push(new_field);
+    }
+
+    Ok(result)
+}
+
+fn remove_conditional_directives<'a>(mut directives: Vec<Directive<'a>>) -> Vec<Directive<'a>> {
+    directives.retain(|d| d.name != "skip" && d.name != "include");
+    directives
+}
+
+fn is_field_included<'a>(
+    directives: &[Directive<'a>],
+    variables: &Map<String, Value>,
+) -> Result<bool, Error> {
+    for directive in directives {
+        let included = match directive.name {
+            "skip" => !evaluate_if_argument(directive, variables)?,
+            "include" => evaluate_if_argument(directive, variables)?,
+            _ => continue,
+        };
+
+        if !included {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn evaluate_if_argument<'a>(
+    directive: &Directive<'a>,
+    variables: &Map<String, Value>,
+) -> Result<bool, Error> {
+    let value = directive
+        .parameters
+        .iter()
+        .find(|p| p.name == "if")
+        .map(|p| &p.value);
+
+    match value {
+        Some(ParameterValue::Boolean(b)) => Ok(*b),
+        Some(ParameterValue::Variable(v)) if variables.get(*v).is_some_and(Value::is_boolean) => {
+            Ok(variables[*v] == Value::Bool(true))
+        }
+        _ => Err(Error::new(format!(
+            "Argument \"if\" of @{} must be a Boolean",
+            directive.name
+        ))),
+    }
+}
+
+/// Removes the @cacheControl directives from the operation and returns
+/// them as cache hints, which restrict the ones sent by the upstream server
+fn extract_cache_control_directives<'a>(
+    operation: Operation<'a>,
+) -> Result<(Operation<'a>, Vec<CacheHintDto>), Error> {
+    let mut hints = Vec::new();
+    let fields =
+        extract_cache_control_directives_recursive(operation.fields, &mut Vec::new(), &mut hints)?;
+
+    let new_operation = Operation {
+        operation_type: operation.operation_type,
+        name: operation.name,
+        variables: operation.variables,
+        directives: operation.directives,
+        fields: fields,
+    };
+
+    Ok((new_operation, hints))
+}
+
+fn extract_cache_control_directives_recursive<'a>(
+    fields: Vec<Field<'a>>,
+    path: &mut Vec<String>,
+    hints: &mut Vec<CacheHintDto>,
+) -> Result<Vec<Field<'a>>, Error> {
+    let mut result = Vec::with_capacity(fields.len());
+
+    for field in fields {
+        let new_field = match field {
+            Field::Field {
+                alias,
+                name,
+                parameters,
+                mut directives,
+                fields: subfields,
+            } => {
+                path.push(String::from(alias.unwrap_or(name)));
+
+                for directive in directives.iter().filter(|d| d.name == "cacheControl") {
+                    hints.push(directive_to_cache_hint(directive, path.clone())?);
+                }
+                directives.retain(|d| d.name != "cacheControl");
+
+                let new_subfields =
+                    extract_cache_control_directives_recursive(subfields, path, hints)?;
+                path.pop();
+
+                Field::Field {
+                    alias: alias,
+                    name: name,
+                    parameters: parameters,
+                    directives: directives,
+                    fields: new_subfields,
+                }
+            }
+            // Inline fragments do not appear in the response path
+            Field::InlineFragment {
+                type_condition,
+                directives,
+                fields: subfields,
+            } => Field::InlineFragment {
+                type_condition: type_condition,
+                directives: directives,
+                fields: extract_cache_control_directives_recursive(subfields, path, hints)?,
+            },
+            f => f,
+        };
+
+        result.push(new_field);
+    }
+
+    Ok(result)
+}
+
+fn directive_to_cache_hint<'a>(
+    directive: &Directive<'a>,
+    path: Vec<String>,
+) -> Result<CacheHintDto, Error> {
+    let mut hint = CacheHintDto {
+        path: path,
+        max_age: None,
+        scope: None,
+    };
 
-            Ok(json!({ "data": final_result }))
+    for parameter in directive.parameters.iter() {
+        let is_valid = match (parameter.name, &parameter.value) {
+            ("maxAge", ParameterValue::Int(s)) => {
+                hint.max_age = s.parse::<u16>().ok();
+                hint.max_age.is_some()
+            }
+            ("scope", ParameterValue::Enum("PUBLIC")) => {
+                hint.scope = Some(CacheScope::PUBLIC);
+                true
+            }
+            ("scope", ParameterValue::Enum("PRIVATE")) => {
+                hint.scope = Some(CacheScope::PRIVATE);
+                true
+            }
+            _ => false,
+        };
+
+        if !is_valid {
+            return Err(Error::new(format!(
+                "Invalid argument \"{}\" for @cacheControl",
+                parameter.name
+            )));
         }
-        None => Ok(json!({ "data": data_from_cache })),
     }
+
+    Ok(hint)
 }
 
 fn expand_response(
@@ -68,17 +491,7 @@ fn expand_response(
         _ => return json,
     };
 
-    for f in operation.fields.iter() {
-        let df = deduplicated_operation
-            .fields
-            .iter()
-            .filter(|ff| ff.is_same_field(f))
-            .nth(0)
-            .unwrap();
-        let v = map[df.get_alias()].clone();
-
-        map.insert(f.get_alias().to_string(), expand_response_field(v, df, f));
-    }
+    expand_response_selection(&mut map, &deduplicated_operation.fields, &operation.fields);
 
     Value::Object(map)
 }
@@ -86,50 +499,201 @@ fn expand_response(
 fn expand_response_field(json: Value, deduplicated_field: &Field, field: &Field) -> Value {
     let mut map = match json {
         Value::Object(map) => map,
+        Value::Array(elements) => {
+            return Value::Array(
+                elements
+                    .into_iter()
+                    .map(|e| expand_response_field(e, deduplicated_field, field))
+                    .collect(),
+            )
+        }
         _ => return json,
     };
 
-    for f in field.get_subfields().iter() {
-        let df = deduplicated_field
-            .get_subfields()
+    expand_response_selection(
+        &mut map,
+        deduplicated_field.get_subfields(),
+        field.get_subfields(),
+    );
+
+    Value::Object(map)
+}
+
+fn expand_response_selection(
+    map: &mut Map<String, Value>,
+    deduplicated_fields: &[Field],
+    fields: &[Field],
+) {
+    for f in fields.iter() {
+        let df = match deduplicated_fields
             .iter()
             .filter(|ff| ff.is_same_field(f))
             .nth(0)
-            .unwrap();
-        let v = map[df.get_alias()].clone();
+        {
+            Some(df) => df,
+            None => continue,
+        };
 
-        map.insert(f.get_alias().to_string(), expand_response_field(v, df, f));
+        match f {
+            // The fields of an inline fragment are at the same level of the parent's fields
+            Field::InlineFragment { .. } => {
+                expand_response_selection(map, df.get_subfields(), f.get_subfields())
+            }
+            _ => {
+                // Fields from type conditioned fragments may be missing from the response
+                if let Some(v) = map.get(df.get_alias()).cloned() {
+                    map.insert(f.get_alias().to_string(), expand_response_field(v, df, f));
+                }
+            }
+        }
     }
-
-    Value::Object(map)
 }
 
 async fn update_cache<'a>(
-    cache: Cache,
+    cache: Arc<dyn CacheBackend>,
     user_id: &Option<String>,
     cache_hints: Vec<(Value, CacheHint)>,
+    cache_tags: &[(Vec<String>, Vec<String>)],
     query: &Operation<'a>,
     variables: &Map<String, Value>,
+    normalization: Option<&NormalizationConfig>,
 ) {
-    for (value, hint) in cache_hints.into_iter().filter(|h| h.1.path.len() > 0) {
+    // The entities are tagged with their key even if the cache is not normalized
+    let default_normalization = NormalizationConfig::default();
+    let entities = normalization.unwrap_or(&default_normalization);
+
+    // Values with a max age of 0 must not be cached
+    for (value, hint) in cache_hints
+        .into_iter()
+        .filter(|h| h.1.path.len() > 0 && h.1.max_age > 0)
+    {
         if let Some((traversed_fields, cached_field)) = query.traverse(&hint.path) {
-            for (cache_key, cache_value) in
+            // The entries of a root field, whatever their depth, are invalidated together
+            let root_field = traversed_fields.first().copied().unwrap_or(cached_field);
+            let field_tag = to_field_tag(&field_to_cache_key(root_field, variables));
+
+            let mut cache_values =
                 get_cache_values(traversed_fields, cached_field, variables, value)
-            {
+                    .into_iter()
+                    .map(|(cache_key, cache_value)| (cache_key, cache_value, false))
+                    .collect::<Vec<_>>();
+
+            // The entities are stored under their own key, with the policy of the field
+            if let Some(normalization) = normalization {
+                cache_values = cache_values
+                    .into_iter()
+                    .flat_map(|(cache_key, cache_value, _)| {
+                        let (cache_value, entities) = normalization.normalize(cache_value);
+                        entities
+                            .into_iter()
+                            .map(|(key, entity)| (key, entity, true))
+                            .chain(std::iter::once((cache_key, cache_value, false)))
+                    })
+                    .collect();
+            }
+
+            // The tags of the fields the value contains, or is part of
+            let hint_path = hint
+                .path
+                .iter()
+                .filter(|s| list_index(s).is_none())
+                .cloned()
+                .collect::<Vec<_>>();
+            let hint_tags = cache_tags
+                .iter()
+                .filter(|(path, _)| path.starts_with(&hint_path) || hint_path.starts_with(path))
+                .flat_map(|(_, tags)| tags.iter().cloned())
+                .collect::<Vec<_>>();
+
+            for (cache_key, cache_value, is_entity) in cache_values {
                 let cache_key = match (hint.scope, user_id) {
                     (CacheScope::PUBLIC, _) => cache_key,
                     (CacheScope::PRIVATE, Some(u)) => to_private_cache_key(u, &cache_key),
                     (CacheScope::PRIVATE, None) => continue,
                 };
-
-                if let Err(err) = cache.insert(cache_key, hint.max_age, cache_value).await {
+                let tags = entities
+                    .entity_keys(&cache_value)
+                    .into_iter()
+                    .chain(hint_tags.iter().cloned())
+                    // The entities are shared by the fields, they outlive the field entries
+                    .chain((!is_entity).then(|| field_tag.clone()))
+                    .unique()
+                    .collect::<Vec<_>>();
+
+                if let Err(err) = cache
+                    .insert(cache_key.clone(), hint.max_age, cache_value)
+                    .await
+                {
                     println!("Cache Error");
+                    continue;
+                }
+
+                if tags.len() > 0 {
+                    if let Err(e) = cache.tag(&cache_key, hint.max_age, &tags).await {
+                        println!("Unable to tag {}: {:?}", cache_key, e);
+                    }
                 }
             }
         }
     }
 }
 
+/// Removes the cache entries invalidated by the fields of a mutation which succeeded:
+/// the entries tagged with the fields and entities of the matching rules, or with the
+/// entities returned. The entries are purged by tag, for all the users.
+async fn invalidate_cache<'a>(
+    cache: &dyn CacheBackend,
+    mutation: &Operation<'a>,
+    variables: &Map<String, Value>,
+    response: &Value,
+    invalidation_rules: &[InvalidationRule],
+    normalization: Option<&NormalizationConfig>,
+) {
+    let data = match response.get("data") {
+        Some(Value::Object(data)) => data,
+        _ => return,
+    };
+
+    // The entries are tagged with the entities they contain even if the cache is not normalized
+    let default_normalization = NormalizationConfig::default();
+    let entities = normalization.unwrap_or(&default_normalization);
+
+    let mut cache_tags = Vec::new();
+    for field in flatten_inline_fragments(&mutation.fields) {
+        // The field failed
+        let value = match data.get(field.get_alias()) {
+            Some(Value::Null) | None => continue,
+            Some(v) => v,
+        };
+
+        let arguments = field
+            .get_parameters()
+            .iter()
+            .map(|p| (p.name.to_string(), p.value.to_json(variables)))
+            .collect::<Map<String, Value>>();
+        for rule in invalidation_rules
+            .iter()
+            .filter(|r| r.mutation == field.get_name())
+        {
+            cache_tags.extend(rule.cache_tags(&arguments));
+        }
+
+        cache_tags.extend(entities.entity_keys(value));
+    }
+
+    for cache_tag in cache_tags.into_iter().unique() {
+        if let Err(e) = cache.purge_tag(&cache_tag).await {
+            println!("Unable to invalidate {}: {:?}", cache_tag, e);
+        }
+    }
+}
+
+/// Returns the tag of the entries of a root field. The prefix keeps the
+/// tags of the fields apart from the ones of the entities, `Type:id`
+pub fn to_field_tag(cache_key: &str) -> String {
+    format!("field:{}", cache_key)
+}
+
 fn to_private_cache_key(user_id: &str, cache_key: &str) -> String {
     [user_id, cache_key].join("")
 }
@@ -199,12 +763,19 @@ fn dealias_path_recursive(json_value: &mut Value, path: &[&Field], variables: &M
 
     let map = match json_value {
         Value::Object(map) => map,
+        Value::Array(elements) => {
+            for element in elements {
+                dealias_path_recursive(element, path, variables);
+            }
+            return;
+        }
         _ => return,
     };
-    let mut v = map.remove(alias).unwrap();
-    dealias_path_recursive(&mut v, path_remainder, variables);
 
-    map.insert(String::from(name), v);
+    if let Some(mut v) = map.remove(alias) {
+        dealias_path_recursive(&mut v, path_remainder, variables);
+        map.insert(String::from(name), v);
+    }
 }
 
 fn dealias_field(json_value: &mut Value, current_field: &Field, variables: &Map<String, Value>) {
@@ -215,12 +786,18 @@ fn dealias_field(json_value: &mut Value, current_field: &Field, variables: &Map<
 
     let map = match json_value {
         Value::Object(map) => map,
+        Value::Array(elements) => {
+            for element in elements {
+                dealias_field(element, current_field, variables);
+            }
+            return;
+        }
         _ => return,
     };
 
     match map.remove(alias) {
         Some(mut v) => {
-            for subfield in current_field.get_subfields() {
+            for subfield in flatten_inline_fragments(current_field.get_subfields()) {
                 dealias_field(&mut v, subfield, variables);
             }
 
@@ -250,7 +827,7 @@ fn cacheable_fields<'a>(field: &'a Field<'a>) -> Vec<Vec<&'a Field<'a>>> {
     let mut stack = vec![field];
     let mut result = vec![stack.clone()];
 
-    for subfield in field.get_subfields().into_iter() {
+    for subfield in flatten_inline_fragments(field.get_subfields()) {
         cacheable_fields_int(subfield, &mut stack, &mut result);
     }
 
@@ -268,7 +845,7 @@ fn cacheable_fields_int<'a>(
         result.push(stack.clone());
     }
 
-    for subfield in field.get_subfields().into_iter() {
+    for subfield in flatten_inline_fragments(field.get_subfields()) {
         cacheable_fields_int(subfield, stack, result);
     }
 
@@ -279,8 +856,9 @@ async fn match_operation_with_cache<'a>(
     operation: Operation<'a>,
     variables: &Map<String, Value>,
     user_id: &Option<String>,
-    cache: &Cache,
-) -> (Option<Operation<'a>>, Value) {
+    cache: &dyn CacheBackend,
+    normalization: Option<&NormalizationConfig>,
+) -> (Option<Operation<'a>>, Value, Option<ResponseCachePolicy>) {
     let mut residual_fields = Vec::<Field>::new();
     let mut cached_result = Map::new();
     let mut cached_value = json!({});
@@ -288,25 +866,53 @@ async fn match_operation_with_cache<'a>(
     let cache_keys = operation
         .fields
         .iter()
+        .filter(|f| !matches!(f, Field::InlineFragment { .. }))
         .map(cacheable_fields)
         .flatten()
         .map(|f| fields_to_cache_key(&f, &variables))
         .unique()
         .collect::<Vec<_>>();
 
-    let cache_requests = cache_keys
-        .iter()
-        .map(|key| get_cached_item(&key, &user_id, &cache));
-    let cache_items = join_all(cache_requests).await;
+    let cache_items = get_cached_items(&cache_keys, &user_id, cache).await;
 
+    // The policy includes all the values found in the cache, whether they are used or not
+    let mut cache_policy: Option<ResponseCachePolicy> = None;
     for item in cache_items {
         match item {
-            Some(x) => merge_json(&mut cached_value, x),
+            Some((x, policy)) => {
+                merge_json(&mut cached_value, x);
+                cache_policy = Some(match cache_policy {
+                    Some(p) => p.restrict(policy),
+                    None => policy,
+                });
+            }
             None => {}
         }
     }
 
+    if normalization.is_some() {
+        let entities_policy = resolve_references(
+            &mut cached_value,
+            &operation.fields,
+            variables,
+            user_id,
+            cache,
+        )
+        .await;
+
+        cache_policy = match (cache_policy, entities_policy) {
+            (Some(p), Some(e)) => Some(p.restrict(e)),
+            (p, e) => p.or(e),
+        };
+    }
+
     for field in operation.fields {
+        // Inline fragments at the root of the operation are always forwarded
+        if let Field::InlineFragment { .. } = field {
+            residual_fields.push(field);
+            continue;
+        }
+
         let alias = String::from(field.get_alias());
         let v = cached_value.get(field_to_cache_key(&field, &variables));
 
@@ -335,6 +941,7 @@ async fn match_operation_with_cache<'a>(
             name: operation.name,
             fields: residual_fields,
             variables: operation.variables,
+            directives: operation.directives,
             operation_type: operation.operation_type,
         };
 
@@ -343,7 +950,108 @@ async fn match_operation_with_cache<'a>(
         None
     };
 
-    (residual_operation, Value::Object(cached_result))
+    (
+        residual_operation,
+        Value::Object(cached_result),
+        cache_policy,
+    )
+}
+
+/// Replaces the references of the cached values with the entities of the normalized
+/// cache. Only the references of the fields of the operation are resolved, the entities
+/// are fetched level by level. Returns the policy of the entities.
+async fn resolve_references(
+    cached_value: &mut Value,
+    fields: &[Field<'_>],
+    variables: &Map<String, Value>,
+    user_id: &Option<String>,
+    cache: &dyn CacheBackend,
+) -> Option<ResponseCachePolicy> {
+    let fields = flatten_inline_fragments(fields);
+    // Null for the entities missing from the cache
+    let mut entities = HashMap::<String, Value>::new();
+    let mut cache_policy: Option<ResponseCachePolicy> = None;
+
+    loop {
+        let mut missing_entities = HashSet::new();
+        replace_references(
+            cached_value,
+            &fields,
+            variables,
+            &entities,
+            &mut missing_entities,
+        );
+
+        if missing_entities.len() == 0 {
+            return cache_policy;
+        }
+
+        let keys = missing_entities.into_iter().collect::<Vec<_>>();
+        let items = get_cached_items(&keys, user_id, cache).await;
+        for (key, item) in keys.into_iter().zip(items) {
+            match item {
+                Some((entity, policy)) => {
+                    entities.insert(key, entity);
+                    cache_policy = Some(match cache_policy {
+                        Some(p) => p.restrict(policy),
+                        None => policy,
+                    });
+                }
+                None => {
+                    entities.insert(key, Value::Null);
+                }
+            }
+        }
+    }
+}
+
+fn replace_references(
+    value: &mut Value,
+    fields: &[&Field],
+    variables: &Map<String, Value>,
+    entities: &HashMap<String, Value>,
+    missing_entities: &mut HashSet<String>,
+) {
+    let map = match value {
+        Value::Object(map) => map,
+        Value::Array(elements) => {
+            for element in elements {
+                replace_references(element, fields, variables, entities, missing_entities);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    // The fields cached along with the reference take precedence over the entity
+    if let Some(Value::String(key)) = map.get(REFERENCE_FIELD) {
+        match entities.get(key) {
+            Some(entity @ Value::Object(_)) => {
+                let mut resolved = entity.clone();
+                map.remove(REFERENCE_FIELD);
+                merge_json(&mut resolved, Value::Object(std::mem::take(map)));
+                *value = resolved;
+            }
+            Some(_) => {}
+            None => {
+                missing_entities.insert(key.clone());
+            }
+        }
+    }
+
+    let map = match value {
+        Value::Object(map) => map,
+        _ => return,
+    };
+
+    for field in fields {
+        if let Some(v) = map.get_mut(&field_to_cache_key(field, variables)) {
+            let subfields = flatten_inline_fragments(field.get_subfields());
+            if subfields.len() > 0 {
+                replace_references(v, &subfields, variables, entities, missing_entities);
+            }
+        }
+    }
 }
 
 fn match_field_with_cache_recursive<'a>(
@@ -363,34 +1071,140 @@ fn match_field_with_cache_recursive<'a>(
         };
     }
 
-    let mut cache_map = match cached_value {
+    // The selection of a list applies to each of its elements. The list is
+    // served from the cache only if all its elements are, to keep its order and length
+    if let Some(Value::Array(elements)) = cached_value {
+        return match match_list_with_cache(field.get_subfields(), variables, elements) {
+            Some(v) => (None, Some(v)),
+            None => (Some(field), None),
+        };
+    }
+
+    let cache_map = match cached_value {
         Some(Value::Object(map)) => map,
         _ => Map::new(),
     };
 
-    let (alias, name, subfields, parameters) = match field {
+    let (alias, name, subfields, parameters, directives) = match field {
         Field::Field {
             alias,
             name,
             fields,
             parameters,
-            ..
-        } => (alias, name, fields, parameters),
+            directives,
+        } => (alias, name, fields, parameters, directives),
         _ => return (Some(field), None),
     };
 
+    let (residual_subfields, value_from_cache) =
+        match_selection_with_cache(subfields, variables, cache_map);
+
+    let residual_field_result = if residual_subfields.len() > 0 {
+        Some(Field::Field {
+            alias: alias,
+            name: name,
+            parameters: parameters,
+            directives: directives,
+            fields: residual_subfields,
+        })
+    } else {
+        None
+    };
+
+    let cache_result = if value_from_cache.len() > 0 {
+        Some(Value::Object(value_from_cache))
+    } else {
+        None
+    };
+
+    (residual_field_result, cache_result)
+}
+
+fn match_list_with_cache(
+    subfields: &[Field],
+    variables: &Map<String, Value>,
+    elements: Vec<Value>,
+) -> Option<Value> {
+    elements
+        .into_iter()
+        .map(|element| match element {
+            Value::Null => Some(Value::Null),
+            Value::Array(a) => match_list_with_cache(subfields, variables, a),
+            Value::Object(map) => {
+                let (residual_subfields, value_from_cache) =
+                    match_selection_with_cache(subfields.to_vec(), variables, map);
+
+                match residual_subfields.len() {
+                    0 => Some(Value::Object(value_from_cache)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(Value::Array)
+}
+
+fn match_selection_with_cache<'a>(
+    subfields: Vec<Field<'a>>,
+    variables: &Map<String, Value>,
+    mut cache_map: Map<String, Value>,
+) -> (Vec<Field<'a>>, Map<String, Value>) {
     // produce a map of parameterless fields with the same name
     // we use this in the next loop to get fields from the cache
     // if a field is unique, then we can remove it from cached_value
     // if a field is not unique, then we have to clone it
     let mut subfield_map = HashMap::<String, i8>::new();
-    for s in subfields.iter().filter(|s| !s.has_parameters()) {
+    for s in flatten_inline_fragments(&subfields)
+        .into_iter()
+        .filter(|s| !s.has_parameters())
+    {
         *subfield_map.entry(s.get_name().to_string()).or_insert(0) += 1;
     }
 
+    let cached_typename = match cache_map.get("__typename") {
+        Some(Value::String(t)) => Some(t.clone()),
+        _ => None,
+    };
+
     let mut value_from_cache = Map::new();
     let mut residual_subfields = Vec::<Field>::new();
     for subfield in subfields {
+        if let Field::InlineFragment {
+            type_condition,
+            directives,
+            fields,
+        } = subfield
+        {
+            // A type conditioned selection can be resolved from the cache
+            // only if the cached value is known to be of that type.
+            // Without a schema, a different type name may still be
+            // an interface or a union including the cached type.
+            match (type_condition, &cached_typename) {
+                (Some(t), Some(cached_type)) if directives.len() == 0 && t == cached_type => {
+                    let (residual_fragment_fields, fragment_value) =
+                        match_selection_with_cache(fields, variables, cache_map.clone());
+
+                    if residual_fragment_fields.len() > 0 {
+                        residual_subfields.push(Field::InlineFragment {
+                            type_condition: type_condition,
+                            directives: directives,
+                            fields: residual_fragment_fields,
+                        });
+                    }
+
+                    merge_json_map(&mut value_from_cache, fragment_value);
+                }
+                _ => residual_subfields.push(Field::InlineFragment {
+                    type_condition: type_condition,
+                    directives: directives,
+                    fields: fields,
+                }),
+            }
+
+            continue;
+        }
+
         let temp_subf: String;
         let subfield_name = if subfield.has_parameters() {
             temp_subf = field_to_cache_key(&subfield, &variables);
@@ -405,7 +1219,7 @@ fn match_field_with_cache_recursive<'a>(
         let field_from_cache = match subfield_map.get_mut(subfield_name) {
             Some(v) if v > &mut 1 => {
                 *v -= 1;
-                Some(cache_map[subfield_name].clone())
+                cache_map.get(subfield_name).cloned()
             }
             _ => cache_map.remove(subfield_name),
         };
@@ -426,27 +1240,16 @@ fn match_field_with_cache_recursive<'a>(
         };
     }
 
-    let residual_field_result = if residual_subfields.len() > 0 {
-        Some(Field::new_field(
-            alias,
-            name,
-            parameters,
-            residual_subfields,
-        ))
-    } else {
-        None
-    };
-
-    let cache_result = if value_from_cache.len() > 0 {
-        Some(Value::Object(value_from_cache))
-    } else {
-        None
-    };
+    (residual_subfields, value_from_cache)
+}
 
-    (residual_field_result, cache_result)
+fn merge_json_map(map: &mut Map<String, Value>, other: Map<String, Value>) {
+    for (k, v) in other {
+        merge_json(map.entry(k).or_insert(Value::Null), v);
+    }
 }
 
-fn field_to_cache_key<'a>(field: &Field<'a>, variables: &Map<String, Value>) -> String {
+pub fn field_to_cache_key<'a>(field: &Field<'a>, variables: &Map<String, Value>) -> String {
     let result = field.get_name().to_string();
     let parameters = field.get_parameters();
 
@@ -459,26 +1262,30 @@ fn field_to_cache_key<'a>(field: &Field<'a>, variables: &Map<String, Value>) ->
     }
 }
 
+/// Literals and variables with the same value produce the same cache key,
+/// e.g. `user(id: 1)` and `user(id: $id)` with `{"id": 1}`
 fn append_parameter_to_cache_key<'a>(
     cache_key: String,
     parameter: &Parameter<'a>,
     variables: &Map<String, Value>,
 ) -> String {
-    let result = cache_key + "_" + parameter.name;
-
-    match &parameter.value {
-        ParameterValue::Nil => result + "NIL",
-        ParameterValue::Scalar(s) => result + s,
-        ParameterValue::Variable(v) => match &variables[*v] {
-            Value::Bool(b) => result + &format!("{}", b),
-            Value::Number(n) => result + &format!("{}", n),
-            Value::String(s) => result + &format!("\"{}\"", s),
-            Value::Object(o) => result + &format!("OBJ{:?}", o),
-            Value::Array(o) => result + &format!("LST{:?}", o),
-            Value::Null => result + "NIL",
-        },
-        ParameterValue::Object(obj) => result + &format!("OBJ{:?}", obj),
-        ParameterValue::List(lst) => result + &format!("LST{:?}", lst),
+    let value = parameter.value.to_json(variables);
+
+    cache_key + "_" + parameter.name + &canonical_json(&value)
+}
+
+/// Serializes a JSON value with the object keys sorted
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(o) => format!(
+            "{{{}}}",
+            o.iter()
+                .sorted_by(|(k1, _), (k2, _)| k1.cmp(k2))
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical_json(v)))
+                .join(",")
+        ),
+        Value::Array(a) => format!("[{}]", a.iter().map(canonical_json).join(",")),
+        v => v.to_string(),
     }
 }
 
@@ -490,33 +1297,68 @@ fn fields_to_cache_key<'a>(fields: &[&Field<'a>], variables: &Map<String, Value>
         .join("+")
 }
 
-async fn get_cached_item<'a>(
-    cache_key: &String,
+/// Reads the public and private values of the keys with a single request to the cache
+async fn get_cached_items(
+    cache_keys: &[String],
     user_id: &Option<String>,
-    cache: &Cache,
-) -> Option<Value> {
-    let public_cache = cache.get(&cache_key).await;
-    let private_cache = match user_id {
-        Some(uid) => cache.get(&to_private_cache_key(uid, cache_key)).await,
-        None => None,
-    };
+    cache: &dyn CacheBackend,
+) -> Vec<Option<(Value, ResponseCachePolicy)>> {
+    let mut keys = cache_keys.to_vec();
+    if let Some(uid) = user_id {
+        keys.extend(cache_keys.iter().map(|k| to_private_cache_key(uid, k)));
+    }
 
-    let cached_fields = match (public_cache, private_cache) {
-        (Some(mut p), Some(r)) => {
-            p.extend_from_slice(&r);
-            p
-        }
-        (Some(p), None) => p,
-        (None, Some(r)) => r,
-        (None, None) => return None,
+    let mut values = cache.get_many(&keys).await.into_iter();
+    let public_values = values.by_ref().take(cache_keys.len()).collect::<Vec<_>>();
+    let private_values = values.chain(std::iter::repeat(None));
+
+    public_values
+        .into_iter()
+        .zip(private_values)
+        .map(|(public_cache, private_cache)| to_cached_item(public_cache, private_cache))
+        .collect()
+}
+
+fn to_cached_item(
+    public_cache: Option<Vec<CachedValue>>,
+    private_cache: Option<Vec<CachedValue>>,
+) -> Option<(Value, ResponseCachePolicy)> {
+    let scoped_values = |values: Option<Vec<CachedValue>>, scope: CacheScope| {
+        values
+            .unwrap_or_default()
+            .into_iter()
+            .map(move |v| (v, scope))
     };
+    let cached_fields = scoped_values(public_cache, CacheScope::PUBLIC)
+        .chain(scoped_values(private_cache, CacheScope::PRIVATE))
+        .collect::<Vec<_>>();
 
+    if cached_fields.len() == 0 {
+        return None;
+    }
+
+    let now = Utc::now();
     let mut cached_value = json!({});
-    for x in cached_fields.into_iter() {
-        merge_json(&mut cached_value, x)
+    let mut cache_policy: Option<ResponseCachePolicy> = None;
+    for (x, scope) in cached_fields.into_iter() {
+        let policy = ResponseCachePolicy::new(
+            seconds_between(now, x.expiry),
+            seconds_between(x.created, now),
+            scope,
+        );
+        cache_policy = Some(match cache_policy {
+            Some(p) => p.restrict(policy),
+            None => policy,
+        });
+
+        merge_json(&mut cached_value, x.value)
     }
 
-    Some(cached_value)
+    cache_policy.map(|p| (cached_value, p))
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> u32 {
+    (to - from).num_seconds().clamp(0, u32::MAX as i64) as u32
 }
 
 fn get_cacheable_fields<'a>(
@@ -545,7 +1387,7 @@ fn extract_fields_with_parameters_recursive<'a>(
         accumulator.push(vec![stack[0]]);
     }
 
-    for subfield in field.get_subfields() {
+    for subfield in flatten_inline_fragments(field.get_subfields()) {
         extract_fields_with_parameters_recursive(subfield, stack, accumulator);
     }
 
@@ -555,13 +1397,14 @@ fn extract_fields_with_parameters_recursive<'a>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::graphql::cache::MemoryCache;
     use crate::graphql::parser::*;
     use serde_json::json;
     use serde_json::value::Value;
     use std::pin::Pin;
 
-    pub fn create_cache() -> Cache {
-        Cache::new()
+    pub fn create_cache() -> Arc<dyn CacheBackend> {
+        Arc::new(MemoryCache::new())
     }
 
     #[tokio::test]
@@ -578,22 +1421,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(result1, result2);
     }
@@ -628,22 +1475,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             create_send_request(expected_result_1.clone(), cache_hints),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(result1, json!({ "data": expected_result_1 }));
         assert_eq!(result2, json!({"data":{"field1":{"subfield1":55}}}));
@@ -664,22 +1515,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -708,22 +1563,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -751,22 +1610,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request_new_param,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -793,22 +1656,26 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u2")),
             create_send_request(json!({"field1": {"subfield3":999}}), vec![]),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -835,6 +1702,7 @@ mod tests {
             parsed_query.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             create_send_request(
                 json!({"field1": {"subfield1":{ "subsubfield1": 123, "subsubfield2": 234 }}}),
@@ -858,18 +1726,21 @@ mod tests {
             ),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             Map::new(),
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -902,6 +1773,7 @@ mod tests {
             parsed_query.fragment_definitions,
             variables,
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             create_send_request(
                 json!({"field1": {"subfield1":{ "subsubfield1": 123, "subsubfield2": 234 }}}),
@@ -925,18 +1797,21 @@ mod tests {
             ),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         let result2 = execute_operation(
             parsed_query2.operations.into_iter().nth(0).unwrap(),
             parsed_query2.fragment_definitions,
             variables2,
             cache.clone(),
+            &CacheContext::default(),
             Some(String::from("u1")),
             fake_not_called_send_request,
         )
         .await
-        .unwrap();
+        .unwrap()
+        .body;
 
         assert_eq!(
             result1,
@@ -948,105 +1823,1584 @@ mod tests {
         );
     }
 
-    fn create_send_request<'a>(
-        data: Value,
-        cache_hints: Vec<(Vec<String>, i16, bool)>,
-    ) -> Box<
-        dyn Fn(
-            Operation<'a>,
-            Map<String, Value>,
-        ) -> Pin<
-            Box<
-                dyn Future<Output = (Result<Value, Error>, Operation<'a>, Map<String, Value>)> + '_,
-            >,
-        >,
-    > {
-        Box::new(move |d, v| Box::pin(fake_send_request_p(data.clone(), cache_hints.clone(), d, v)))
+    #[tokio::test]
+    async fn execute_operation_does_not_request_skipped_fields() {
+        let cache = create_cache();
+
+        let query = "query($withSub2: Boolean!){field1{subfield1 subfield2 @include(if: $withSub2) subfield3 @skip(if: true)}}";
+        let parsed_query = parse_query(query).unwrap();
+
+        let mut variables = Map::new();
+        variables.insert(String::from("withSub2"), json!(false));
+
+        let result = execute_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+            variables,
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            |operation, variables| async move {
+                assert_eq!("{field1{subfield1}}", serialize_operation(&operation));
+                (
+                    Ok(json!({"data": {"field1": {"subfield1": 55}}}).into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(result, json!({"data":{"field1":{"subfield1":55}}}));
     }
 
-    async fn fake_not_called_send_request<'a>(
-        _: Operation<'a>,
-        _: Map<String, Value>,
-    ) -> (Result<Value, Error>, Operation<'a>, Map<String, Value>) {
-        panic!("This method should never be called")
+    #[tokio::test]
+    async fn execute_operation_applies_conditions_of_expanded_fragments() {
+        let cache = create_cache();
+
+        let query = "query($flag: Boolean!){field1{subfield1 ...frag1 @include(if: $flag) ...frag2 @skip(if: $flag)}} fragment frag1 on T { subfield2 } fragment frag2 on T { subfield3 }";
+        let parsed_query = parse_query(query).unwrap();
+        let (operation, fragment_definitions) = parsed_query.select_operation(None).unwrap();
+        // Operations are expanded before the variables are known when they are cached
+        let operation = expand_operation(operation, fragment_definitions).unwrap();
+
+        let mut variables = Map::new();
+        variables.insert(String::from("flag"), json!(true));
+
+        let result = execute_operation(
+            operation,
+            vec![],
+            variables,
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            |operation, variables| async move {
+                assert_eq!(
+                    "{field1{subfield1 subfield2}}",
+                    serialize_operation(&operation)
+                );
+                (
+                    Ok(json!({"data": {"field1": {"subfield1": 55, "subfield2": 56}}}).into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(
+            result,
+            json!({"data":{"field1":{"subfield1":55,"subfield2":56}}})
+        );
     }
 
-    async fn fake_send_request_p<'a>(
-        data: Value,
-        cache_hints: Vec<(Vec<String>, i16, bool)>,
-        document: Operation<'a>,
-        variables: Map<String, Value>,
-    ) -> (Result<Value, Error>, Operation<'a>, Map<String, Value>) {
-        let cache_hints = cache_hints
-            .iter()
-            .map(|(path, max_age, is_private)| {
-                if *is_private {
-                    json!({"path": path, "maxAge": max_age, "scope": "PRIVATE"})
-                } else {
-                    json!({"path": path, "maxAge": max_age})
-                }
-            })
-            .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn execute_operation_does_not_lookup_cache_for_excluded_fields() {
+        let cache = create_cache();
 
-        let result = Ok(json!(
-            {
-                "data": data,
-                "extensions": {
-                    "cacheControl": {
-                        "version": 1,
-                        "hints": cache_hints
-                    }
-                }
-            }
-        ));
+        let query = "{field1{subfield1 subfield2 aliased_subfield: subfield3(id: 13) aliased_private_subfield: subfield3(id: 11)}}";
+        let query2 = "query($flag: Boolean = false){field1{subfield1 notcached @include(if: $flag)} other @skip(if: true) { id } ...frag @include(if: $flag)} fragment frag on Query { another }";
+
+        let parsed_query = parse_query(query).unwrap();
+        let parsed_query2 = parse_query(query2).unwrap();
+
+        execute_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            fake_send_request,
+        )
+        .await
+        .unwrap();
+
+        let result2 = execute_operation(
+            parsed_query2.operations.into_iter().nth(0).unwrap(),
+            parsed_query2.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
 
-        (result, document, variables)
+        assert_eq!(result2, json!({"data":{"field1":{"subfield1":55}}}));
     }
 
-    async fn fake_send_request<'a>(
-        document: Operation<'a>,
-        variables: Map<String, Value>,
-    ) -> (Result<Value, Error>, Operation<'a>, Map<String, Value>) {
-        let result = Ok(json!(
-            {
-                "data": {
-                    "field1": {
-                        "subfield1": 55,
-                        "subfield2": 777,
-                        "aliased_subfield": 123,
-                        "aliased_private_subfield": 111
-                    }
-                },
-                "extensions": {
-                    "cacheControl": {
-                        "version": 1,
-                        "hints": [
-                            {
-                                "path": ["field1"],
-                                "maxAge": 2000
-                            },
-                            {
-                                "path": ["field1", "subfield1"],
-                                "maxAge": 1000
-                            },
-                            {
-                                "path": ["field1", "aliased_private_subfield"],
-                                "maxAge": 1000,
-                                "scope": "PRIVATE"
-                            }
-                        ]
-                    }
-                }
-            }
-        ));
+    #[tokio::test]
+    async fn execute_operation_uses_cache_control_directives() {
+        let cache = create_cache();
+
+        let query = "{field1{subfield1 subfield2 @cacheControl(maxAge: 500, scope: PRIVATE)}}";
+        let query2 = "{field1{subfield2}}";
+
+        let parsed_query = parse_query(query).unwrap();
+        let parsed_query2 = parse_query(query2).unwrap();
+
+        execute_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            |operation, variables| async move {
+                assert_eq!(
+                    "{field1{subfield1 subfield2}}",
+                    serialize_operation(&operation)
+                );
+                (
+                    Ok(json!({
+                        "data": {"field1": {"subfield1": 55, "subfield2": 777}},
+                        "extensions": {"cacheControl": {"version": 1, "hints": [{"path": ["field1"], "maxAge": 1000}]}}
+                    })
+                    .into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap();
+
+        let result_same_user = execute_operation(
+            parse_query(query2)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        let result_other_user = execute_operation(
+            parsed_query2.operations.into_iter().nth(0).unwrap(),
+            parsed_query2.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u2")),
+            create_send_request(json!({"field1": {"subfield2": 999}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
 
-        (result, document, variables)
+        assert_eq!(
+            result_same_user,
+            json!({"data":{"field1":{"subfield2":777}}})
+        );
+        assert_eq!(
+            result_other_user,
+            json!({"data":{"field1":{"subfield2":999}}})
+        );
     }
 
-    async fn fake_send_request_new_param<'a>(
-        document: Operation<'a>,
-        variables: Map<String, Value>,
-    ) -> (Result<Value, Error>, Operation<'a>, Map<String, Value>) {
+    #[tokio::test]
+    async fn execute_operation_cache_control_directives_only_restrict_the_policy() {
+        let cache = create_cache();
+
+        let query = "{field1 @cacheControl(maxAge: 3600, scope: PUBLIC) {subfield1}}";
+        let parsed_query = parse_query(query).unwrap();
+
+        let result = execute_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"field1": {"subfield1": 55}}),
+                vec![(vec![String::from("field1")], 1000, true)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.cache_policy.scope, CacheScope::PRIVATE);
+        assert_eq!(result.cache_policy.remaining, 1000);
+        assert!(cache.get("field1").await.is_none());
+        assert!(cache
+            .get(&to_private_cache_key("u1", "field1"))
+            .await
+            .is_some());
+
+        // Another user can't read the private value
+        let parsed_query = parse_query(query).unwrap();
+        let result_other_user = execute_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u2")),
+            create_send_request(json!({"field1": {"subfield1": 999}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(
+            result_other_user,
+            json!({"data":{"field1":{"subfield1":999}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_uses_schema_cache_policies() {
+        let cache = create_cache();
+        let schema = Schema::from_sdl(
+            "directive @cacheControl(maxAge: Int, scope: CacheControlScope, inheritMaxAge: Boolean) on FIELD_DEFINITION | OBJECT
+            enum CacheControlScope { PUBLIC PRIVATE }
+            type Query { user(id: ID!): User @cacheControl(maxAge: 60) version: String }
+            type User { id: ID! name: String email: String @cacheControl(scope: PRIVATE) }",
+        )
+        .unwrap();
+
+        let query = "{user(id: 1){id name email} version}";
+        let query2 = "{user(id: 1){id name email}}";
+
+        let context = CacheContext {
+            schema: Some(schema),
+            ..CacheContext::default()
+        };
+
+        execute_operation(
+            parse_query(query).unwrap().operations.into_iter().nth(0).unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"user": {"id": "1", "name": "John", "email": "john@example.com"}, "version": "1.0"}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let result_same_user = execute_operation(
+            parse_query(query2)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        // The version field has a max age of 0, so it must be requested again
+        let result_version = execute_operation(
+            parse_query("{version}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(json!({"version": "1.1"}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(
+            result_same_user,
+            json!({"data": {"user": {"id": "1", "name": "John", "email": "john@example.com"}}})
+        );
+        assert_eq!(result_version, json!({"data": {"version": "1.1"}}));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_uses_cache_rules() {
+        let cache = create_cache();
+        let cache_rules: Vec<CacheRule> = from_value(json!([
+            {"path": "Query.field1", "maxAge": 300},
+            {"path": "Query.field2", "maxAge": 0, "override": true}
+        ]))
+        .unwrap();
+
+        let query = "{field1{subfield1} field2{subfield1}}";
+
+        let context = CacheContext {
+            cache_rules,
+            ..CacheContext::default()
+        };
+
+        execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            create_send_request(
+                json!({"field1": {"subfield1": 1}, "field2": {"subfield1": 2}}),
+                vec![(vec![String::from("field2")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let result_field1 = execute_operation(
+            parse_query("{field1{subfield1}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        // The rule overrides the hint of the upstream server, field2 is not cached
+        let result_field2 = execute_operation(
+            parse_query("{field2{subfield1}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            create_send_request(json!({"field2": {"subfield1": 3}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(result_field1, json!({"data": {"field1": {"subfield1": 1}}}));
+        assert_eq!(result_field2, json!({"data": {"field2": {"subfield1": 3}}}));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_uses_cache_control_header() {
+        let cache = create_cache();
+
+        let query = "{field1{subfield1} field2{subfield1}}";
+
+        execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            |operation, variables| async move {
+                let response = UpstreamResponse {
+                    body: json!({"data": {"field1": {"subfield1": 1}, "field2": {"subfield1": 2}}}),
+                    cache_control: Some(String::from("max-age=60, private")),
+                };
+
+                (Ok(response), operation, variables)
+            },
+        )
+        .await
+        .unwrap();
+
+        let result_same_user = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        let result_other_user = execute_operation(
+            parse_query("{field1{subfield1}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u2")),
+            create_send_request(json!({"field1": {"subfield1": 3}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(
+            result_same_user,
+            json!({"data": {"field1": {"subfield1": 1}, "field2": {"subfield1": 2}}})
+        );
+        assert_eq!(
+            result_other_user,
+            json!({"data": {"field1": {"subfield1": 3}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_does_not_cache_no_store_responses() {
+        let cache = create_cache();
+
+        let query = "{field1{subfield1}}";
+
+        execute_operation(
+            parse_query(query).unwrap().operations.into_iter().nth(0).unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                let response = UpstreamResponse {
+                    body: json!({
+                        "data": {"field1": {"subfield1": 1}},
+                        "extensions": {"cacheControl": {"version": 1, "hints": [{"path": ["field1"], "maxAge": 1000}]}}
+                    }),
+                    cache_control: Some(String::from("no-store")),
+                };
+
+                (Ok(response), operation, variables)
+            },
+        )
+        .await
+        .unwrap();
+
+        let result = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(json!({"field1": {"subfield1": 2}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(result, json!({"data": {"field1": {"subfield1": 2}}}));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_forwards_upstream_errors() {
+        let cache = create_cache();
+
+        let query = "{field1{subfield1 subfield2}}";
+
+        let result = execute_operation(
+            parse_query(query).unwrap().operations.into_iter().nth(0).unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                let response = json!({
+                    "data": {"field1": {"subfield1": 1, "subfield2": null}},
+                    "errors": [{"message": "Failure", "path": ["field1", "subfield2"]}],
+                    "extensions": {"cacheControl": {"version": 1, "hints": [{"path": ["field1"], "maxAge": 1000}]}}
+                });
+
+                (Ok(response.into()), operation, variables)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            result.body,
+            json!({
+                "data": {"field1": {"subfield1": 1, "subfield2": null}},
+                "errors": [{"message": "Failure", "path": ["field1", "subfield2"]}]
+            })
+        );
+        assert_eq!(result.cache_policy.cache_control_header(), "no-store");
+        assert!(cache.get(&String::from("field1")).await.is_none());
+
+        let result = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                let response = json!({"errors": [{"message": "Cannot query field"}]});
+
+                (Ok(response.into()), operation, variables)
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            result.body,
+            json!({"errors": [{"message": "Cannot query field"}]})
+        );
+
+        let result = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                (Ok(json!("Bad gateway").into()), operation, variables)
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::Upstream(_))));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_returns_cache_policy() {
+        let cache = create_cache();
+
+        let query = "{field1{subfield1 subfield2}}";
+
+        let result1 = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"field1": {"subfield1": 1, "subfield2": 2}}),
+                vec![
+                    (vec![String::from("field1")], 2000, false),
+                    (
+                        vec![String::from("field1"), String::from("subfield2")],
+                        1000,
+                        true,
+                    ),
+                ],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let result2 = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap();
+
+        let uncached_result = execute_operation(
+            parse_query("{field2}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            Some(String::from("u1")),
+            create_send_request(json!({"field2": 1}), vec![]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            result1.cache_policy,
+            ResponseCachePolicy::new(1000, 0, CacheScope::PRIVATE)
+        );
+        assert_eq!(result2.cache_policy.scope, CacheScope::PRIVATE);
+        assert!(result2.cache_policy.remaining <= 1000 && result2.cache_policy.remaining > 990);
+        assert_eq!(
+            uncached_result.cache_policy.cache_control_header(),
+            "no-store"
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_matches_literals_and_variables_with_same_value() {
+        let cache = create_cache();
+
+        let query = "{field1(id: 1, filter: {b: \"x\", a: ACTIVE}){subfield1}}";
+        let query2 = "query($id: Int, $filter: Filter = {a: ACTIVE, b: \"\\u0078\"}){field1(id: $id, filter: $filter){subfield1}}";
+
+        execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(
+                json!({"field1": {"subfield1": 55}}),
+                vec![(vec![String::from("field1")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let mut variables = Map::new();
+        variables.insert(String::from("id"), json!(1));
+
+        let result2 = execute_operation(
+            parse_query(query2)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            variables,
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(result2, json!({"data":{"field1":{"subfield1":55}}}));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_resolves_inline_fragments_matching_cached_type() {
+        let cache = create_cache();
+
+        let query = "{field1(id: 1){__typename ... on User { name }}}";
+
+        let result1 = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(
+                json!({"field1": {"__typename": "User", "name": "the name"}}),
+                vec![(vec![String::from("field1")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+
+        let result2 = execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+
+        let expected = json!({"data":{"field1":{"__typename": "User", "name": "the name"}}});
+        assert_eq!(result1, expected);
+        assert_eq!(result2, expected);
+    }
+
+    #[tokio::test]
+    async fn execute_operation_does_not_resolve_inline_fragments_of_other_types() {
+        let cache = create_cache();
+
+        let query = "{field1(id: 1){__typename ... on User { name }}}";
+        let query2 = "{field1(id: 1){__typename ... on Company { name }}}";
+
+        execute_operation(
+            parse_query(query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(
+                json!({"field1": {"__typename": "User", "name": "the name"}}),
+                vec![(vec![String::from("field1")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let result2 = execute_operation(
+            parse_query(query2)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                assert_eq!(
+                    "{field1(id:1){... on Company{name}}}",
+                    serialize_operation(&operation)
+                );
+                (
+                    Ok(json!({"data": {"field1": {}}}).into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap()
+        .body;
+
+        assert_eq!(result2, json!({"data":{"field1":{"__typename": "User"}}}));
+    }
+
+    #[tokio::test]
+    async fn execute_operation_caches_lists_of_objects() {
+        let cache = create_cache();
+        let users = json!({"users": [
+            {"id": 1, "name": "a", "posts": [{"title": "t1"}]},
+            null,
+            {"id": 3, "name": "c", "posts": []}
+        ]});
+
+        let result1 = execute_operation(
+            parse_query("{users{id name posts(first: 2){title}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(
+                users.clone(),
+                vec![(vec![String::from("users")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result1, json!({ "data": users }));
+
+        let result2 = execute_operation(
+            parse_query("{users{name posts(first: 2){title}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result2,
+            json!({"data": {"users": [
+                {"name": "a", "posts": [{"title": "t1"}]},
+                null,
+                {"name": "c", "posts": []}
+            ]}})
+        );
+
+        // A field missing from one of the elements requests the whole list
+        let result3 = execute_operation(
+            parse_query("{users{id email}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            |operation, variables| async move {
+                assert_eq!("{users{id email}}", serialize_operation(&operation));
+                (
+                    Ok(json!({"data": {"users": [{"id": 1, "email": "e"}]}}).into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result3,
+            json!({"data": {"users": [{"id": 1, "email": "e"}]}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_applies_hints_of_list_elements() {
+        let cache = create_cache();
+
+        execute_operation(
+            parse_query("{users{id name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            // The upstream server sends the indices of list elements as numbers
+            |operation, variables| async move {
+                (
+                    Ok(json!({
+                        "data": {"users": [{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]},
+                        "extensions": {"cacheControl": {"version": 1, "hints": [
+                            {"path": ["users"], "maxAge": 1000},
+                            {"path": ["users", 1, "name"], "maxAge": 0}
+                        ]}}
+                    })
+                    .into()),
+                    operation,
+                    variables,
+                )
+            },
+        )
+        .await
+        .unwrap();
+
+        let result2 = execute_operation(
+            parse_query("{users{id}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result2, json!({"data": {"users": [{"id": 1}, {"id": 2}]}}));
+
+        let result3 = execute_operation(
+            parse_query("{users{id name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &CacheContext::default(),
+            None,
+            create_send_request(
+                json!({"users": [{"id": 1, "name": "a"}, {"id": 2, "name": "new"}]}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result3,
+            json!({"data": {"users": [{"id": 1, "name": "a"}, {"id": 2, "name": "new"}]}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_shares_normalized_entities() {
+        let cache = create_cache();
+        let context = CacheContext {
+            normalization: Some(NormalizationConfig::default()),
+            ..CacheContext::default()
+        };
+
+        let result1 = execute_operation(
+            parse_query("{user(id: 42){id name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            create_send_request(
+                json!({"user": {"__typename": "User", "id": 42, "name": "old"}}),
+                vec![(vec![String::from("user")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        // The __typename added to identify the entity is not returned
+        assert_eq!(
+            result1,
+            json!({"data": {"user": {"id": 42, "name": "old"}}})
+        );
+
+        execute_operation(
+            parse_query("{post(id: 1){title author{id name}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            create_send_request(
+                json!({"post": {
+                    "__typename": "Post",
+                    "id": 1,
+                    "title": "t",
+                    "author": {"__typename": "User", "id": 42, "name": "new"}
+                }}),
+                vec![(vec![String::from("post")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        let result3 = execute_operation(
+            parse_query("{user(id: 42){name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result3, json!({"data": {"user": {"name": "new"}}}));
+
+        let result4 = execute_operation(
+            parse_query("{post(id: 1){title author{name}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            None,
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result4,
+            json!({"data": {"post": {"title": "t", "author": {"name": "new"}}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_applies_invalidation_rules_of_mutations() {
+        let cache = create_cache();
+        let rules = vec![InvalidationRule {
+            mutation: String::from("updateUser"),
+            fields: vec![String::from("user(id: $id)")],
+            entities: vec![],
+        }];
+
+        let context = CacheContext {
+            invalidation_rules: rules,
+            ..CacheContext::default()
+        };
+
+        execute_operation(
+            parse_query("{user(id: 42){id name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"user": {"id": 42, "name": "old"}}),
+                vec![(vec![String::from("user")], 1000, true)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        // Updating another user leaves the entry in place
+        execute_operation(
+            parse_query("mutation { updateUser(id: 43, name: \"new\") { id } }")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(json!({"updateUser": {"id": 42}}), vec![]),
+        )
+        .await
+        .unwrap();
+        let result2 = execute_operation(
+            parse_query("{user(id: 42){name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            fake_not_called_send_request,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result2, json!({"data": {"user": {"name": "old"}}}));
+
+        execute_operation(
+            parse_query("mutation { updateUser(id: 42, name: \"new\") { id } }")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(json!({"updateUser": {"id": 42}}), vec![]),
+        )
+        .await
+        .unwrap();
+        let result3 = execute_operation(
+            parse_query("{user(id: 42){id name}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(json!({"user": {"id": 42, "name": "new"}}), vec![]),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result3,
+            json!({"data": {"user": {"id": 42, "name": "new"}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_invalidates_the_entries_of_all_users() {
+        let cache = create_cache();
+        let rules = vec![InvalidationRule {
+            mutation: String::from("updateUser"),
+            fields: vec![String::from("user(id: $id)")],
+            entities: vec![String::from("User:$id")],
+        }];
+        let user_query = "{user(id: 42){name posts(first: 2){title}}}";
+        let post_query = "{post(id: 1){author{__typename id name}}}";
+
+        let context = CacheContext {
+            invalidation_rules: rules,
+            ..CacheContext::default()
+        };
+
+        for (query, data, private) in [
+            (
+                user_query,
+                json!({"user": {"name": "old", "posts": [{"title": "t"}]}}),
+                true,
+            ),
+            (
+                post_query,
+                json!({"post": {"author": {"__typename": "User", "id": 42, "name": "old"}}}),
+                false,
+            ),
+        ] {
+            execute_operation(
+                parse_query(query)
+                    .unwrap()
+                    .operations
+                    .into_iter()
+                    .nth(0)
+                    .unwrap(),
+                vec![],
+                Map::new(),
+                cache.clone(),
+                &context,
+                Some(String::from("u1")),
+                create_send_request(data, vec![(vec![query[1..5].to_string()], 1000, private)]),
+            )
+            .await
+            .unwrap();
+        }
+
+        // Another user updates the user, the cache isn't normalized
+        execute_operation(
+            parse_query("mutation { updateUser(id: 42, name: \"new\") { name } }")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u2")),
+            create_send_request(json!({"updateUser": {"name": "new"}}), vec![]),
+        )
+        .await
+        .unwrap();
+
+        let user = execute_operation(
+            parse_query(user_query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"user": {"name": "new", "posts": [{"title": "t"}]}}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            user,
+            json!({"data": {"user": {"name": "new", "posts": [{"title": "t"}]}}})
+        );
+
+        let post = execute_operation(
+            parse_query(post_query)
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"post": {"author": {"__typename": "User", "id": 42, "name": "new"}}}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            post,
+            json!({"data": {"post": {"author": {"__typename": "User", "id": 42, "name": "new"}}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_invalidates_entities_returned_by_mutations() {
+        let cache = create_cache();
+        let context = CacheContext {
+            normalization: Some(NormalizationConfig::default()),
+            ..CacheContext::default()
+        };
+
+        execute_operation(
+            parse_query("{post(id: 1){author{id name}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"post": {
+                    "__typename": "Post",
+                    "id": 1,
+                    "author": {"__typename": "User", "id": 42, "name": "old"}
+                }}),
+                vec![(vec![String::from("post")], 1000, false)],
+            ),
+        )
+        .await
+        .unwrap();
+
+        execute_operation(
+            parse_query("mutation { updateUser(id: 42, name: \"new\") { id } }")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"updateUser": {"__typename": "User", "id": 42}}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap();
+        let result2 = execute_operation(
+            parse_query("{post(id: 1){author{id name}}}")
+                .unwrap()
+                .operations
+                .into_iter()
+                .nth(0)
+                .unwrap(),
+            vec![],
+            Map::new(),
+            cache.clone(),
+            &context,
+            Some(String::from("u1")),
+            create_send_request(
+                json!({"post": {"author": {"__typename": "User", "id": 42, "name": "new"}}}),
+                vec![],
+            ),
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(
+            result2,
+            json!({"data": {"post": {"author": {"id": 42, "name": "new"}}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_operation_tags_cache_entries() {
+        let cache = create_cache();
+        let rules: Vec<CacheRule> =
+            serde_json::from_value(json!([{"path": "Query.product", "tags": ["catalog"]}]))
+                .unwrap();
+
+        let context = CacheContext {
+            cache_rules: rules,
+            ..CacheContext::default()
+        };
+
+        // Tags of the rules, of the upstream response and of the entities
+        for tag in ["catalog", "cms", "Product:1"] {
+            execute_operation(
+                parse_query("{product(id: 1){id name}}")
+                    .unwrap()
+                    .operations
+                    .into_iter()
+                    .nth(0)
+                    .unwrap(),
+                vec![],
+                Map::new(),
+                cache.clone(),
+                &context,
+                None,
+                |operation, variables| async move {
+                    let response = json!({
+                        "data": {"product": {"__typename": "Product", "id": 1, "name": "p"}},
+                        "extensions": {
+                            "cacheControl": {"version": 1, "hints": [{"path": ["product"], "maxAge": 1000}]},
+                            "cacheTags": ["cms"]
+                        }
+                    });
+                    (Ok(response.into()), operation, variables)
+                },
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(cache.purge_tag(tag).await.unwrap(), 1);
+            assert!(cache.get(&String::from("product_id1")).await.is_none());
+        }
+    }
+
+    fn create_send_request<'a>(
+        data: Value,
+        cache_hints: Vec<(Vec<String>, i16, bool)>,
+    ) -> Box<
+        dyn Fn(
+            Operation<'a>,
+            Map<String, Value>,
+        ) -> Pin<
+            Box<
+                dyn Future<
+                        Output = (
+                            Result<UpstreamResponse, Error>,
+                            Operation<'a>,
+                            Map<String, Value>,
+                        ),
+                    > + '_,
+            >,
+        >,
+    > {
+        Box::new(move |d, v| Box::pin(fake_send_request_p(data.clone(), cache_hints.clone(), d, v)))
+    }
+
+    async fn fake_not_called_send_request<'a>(
+        _: Operation<'a>,
+        _: Map<String, Value>,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
+        panic!("This method should never be called")
+    }
+
+    async fn fake_send_request_p<'a>(
+        data: Value,
+        cache_hints: Vec<(Vec<String>, i16, bool)>,
+        document: Operation<'a>,
+        variables: Map<String, Value>,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
+        let cache_hints = cache_hints
+            .iter()
+            .map(|(path, max_age, is_private)| {
+                if *is_private {
+                    json!({"path": path, "maxAge": max_age, "scope": "PRIVATE"})
+                } else {
+                    json!({"path": path, "maxAge": max_age})
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let result = Ok(json!(
+            {
+                "data": data,
+                "extensions": {
+                    "cacheControl": {
+                        "version": 1,
+                        "hints": cache_hints
+                    }
+                }
+            }
+        ));
+
+        (result.map(UpstreamResponse::from), document, variables)
+    }
+
+    async fn fake_send_request<'a>(
+        document: Operation<'a>,
+        variables: Map<String, Value>,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
+        let result = Ok(json!(
+            {
+                "data": {
+                    "field1": {
+                        "subfield1": 55,
+                        "subfield2": 777,
+                        "aliased_subfield": 123,
+                        "aliased_private_subfield": 111
+                    }
+                },
+                "extensions": {
+                    "cacheControl": {
+                        "version": 1,
+                        "hints": [
+                            {
+                                "path": ["field1"],
+                                "maxAge": 2000
+                            },
+                            {
+                                "path": ["field1", "subfield1"],
+                                "maxAge": 1000
+                            },
+                            {
+                                "path": ["field1", "aliased_private_subfield"],
+                                "maxAge": 1000,
+                                "scope": "PRIVATE"
+                            }
+                        ]
+                    }
+                }
+            }
+        ));
+
+        (result.map(UpstreamResponse::from), document, variables)
+    }
+
+    async fn fake_send_request_new_param<'a>(
+        document: Operation<'a>,
+        variables: Map<String, Value>,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
         let result = Ok(json!(
             {
                 "data": {
@@ -1068,6 +3422,6 @@ mod tests {
             }
         ));
 
-        (result, document, variables)
+        (result.map(UpstreamResponse::from), document, variables)
     }
 }
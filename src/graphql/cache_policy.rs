@@ -0,0 +1,621 @@
+use crate::graphql::parser::{Field, Operation, OperationType};
+use crate::graphql::schema::{FieldDefinition, Schema, SchemaDirective, TypeDefinition};
+use crate::graphql_deserializer::{CacheHint, CacheHintDto, CacheScope};
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The overall cache policy of a response: the most restrictive scope and the
+/// shortest remaining lifetime among the values it is made of. The age is the
+/// age of the oldest value served from the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponseCachePolicy {
+    pub remaining: u32,
+    pub age: u32,
+    pub scope: CacheScope,
+}
+
+impl ResponseCachePolicy {
+    pub fn new(remaining: u32, age: u32, scope: CacheScope) -> ResponseCachePolicy {
+        ResponseCachePolicy {
+            remaining: remaining,
+            age: age,
+            scope: scope,
+        }
+    }
+
+    /// The policy of responses which must not be cached
+    pub fn no_store() -> ResponseCachePolicy {
+        ResponseCachePolicy::new(0, 0, CacheScope::PUBLIC)
+    }
+
+    /// Computes the policy of the data returned by the upstream server from its cache
+    /// hints. If any root field is not covered by a hint, the data can't be cached.
+    pub fn from_cache_hints(data: &Value, hints: &[(Value, CacheHint)]) -> ResponseCachePolicy {
+        let fields = match data {
+            Value::Object(map) => map,
+            _ => return ResponseCachePolicy::no_store(),
+        };
+
+        let is_covered = |field: &String| hints.iter().any(|(_, h)| h.path.first() == Some(field));
+        if fields.len() == 0 || !fields.keys().all(is_covered) {
+            return ResponseCachePolicy::no_store();
+        }
+
+        hints
+            .iter()
+            .map(|(_, h)| ResponseCachePolicy::new(h.max_age as u32, 0, h.scope))
+            .reduce(ResponseCachePolicy::restrict)
+            .unwrap_or_else(ResponseCachePolicy::no_store)
+    }
+
+    pub fn restrict(self, other: ResponseCachePolicy) -> ResponseCachePolicy {
+        ResponseCachePolicy {
+            remaining: self.remaining.min(other.remaining),
+            age: self.age.max(other.age),
+            scope: match (self.scope, other.scope) {
+                (CacheScope::PUBLIC, CacheScope::PUBLIC) => CacheScope::PUBLIC,
+                _ => CacheScope::PRIVATE,
+            },
+        }
+    }
+
+    /// Returns the value of the `Cache-Control` header. The max age includes
+    /// the age, so that downstream caches expire the response together with
+    /// the first of its values.
+    pub fn cache_control_header(&self) -> String {
+        if self.remaining == 0 {
+            return String::from("no-store");
+        }
+
+        let scope = match self.scope {
+            CacheScope::PUBLIC => "public",
+            CacheScope::PRIVATE => "private",
+        };
+
+        format!("max-age={}, {}", self.remaining + self.age, scope)
+    }
+}
+
+/// The policy of a field or a type, as set by the @cacheControl directive
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct CachePolicy {
+    max_age: Option<u16>,
+    scope: Option<CacheScope>,
+    inherit_max_age: bool,
+}
+
+impl CachePolicy {
+    fn from_directives(directives: &[SchemaDirective]) -> Option<CachePolicy> {
+        let directive = directives.iter().find(|d| d.name == "cacheControl")?;
+
+        Some(CachePolicy {
+            max_age: directive
+                .arguments
+                .get("maxAge")
+                .and_then(|v| v.as_u64())
+                .map(|v| v.min(u16::MAX as u64) as u16),
+            scope: match directive.arguments.get("scope").and_then(|v| v.as_str()) {
+                Some("PUBLIC") => Some(CacheScope::PUBLIC),
+                Some("PRIVATE") => Some(CacheScope::PRIVATE),
+                _ => None,
+            },
+            inherit_max_age: directive.arguments.get("inheritMaxAge") == Some(&Value::Bool(true)),
+        })
+    }
+}
+
+/// A cache rule declared in the configuration file. Rules either match
+/// a path from the root type (`Query.user.company`) or the type returned by
+/// a field (`Company`). `*` matches any sequence of characters in a segment
+/// of the path or in a type name. Rules with `tags` tag the cache entries
+/// containing the matching fields, to purge them together.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheRule {
+    pub path: Option<String>,
+    pub r#type: Option<String>,
+    pub max_age: Option<u16>,
+    pub scope: Option<CacheScope>,
+    /// When set, the rule replaces the hints sent by the upstream server
+    #[serde(default)]
+    pub r#override: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl CacheRule {
+    fn matches(&self, field: &FieldContext) -> bool {
+        let path_matches = match &self.path {
+            Some(pattern) => {
+                let segments = pattern.split('.').collect::<Vec<_>>();
+
+                segments.len() == field.schema_path.len()
+                    && segments
+                        .iter()
+                        .zip(field.schema_path.iter())
+                        .all(|(p, s)| matches_pattern(p, s))
+            }
+            None => true,
+        };
+
+        let type_matches = match (&self.r#type, field.field_type) {
+            (Some(pattern), Some(t)) => matches_pattern(pattern, &t.name),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        (self.path.is_some() || self.r#type.is_some()) && path_matches && type_matches
+    }
+
+    fn has_policy(&self) -> bool {
+        self.max_age.is_some() || self.scope.is_some()
+    }
+}
+
+/// A field of an operation, along with its position in the response and in the schema
+struct FieldContext<'s> {
+    /// Path of the field in the response, built from the aliases
+    path: Vec<String>,
+    /// Path of the field from the root type, built from the names
+    schema_path: Vec<String>,
+    definition: Option<&'s FieldDefinition>,
+    field_type: Option<&'s TypeDefinition>,
+}
+
+/// Computes the cache hints of an operation from the @cacheControl directives
+/// of the schema, following the rules of Apollo Server 3:
+/// - the policy of a field overrides the policy of the type it returns
+/// - root fields and fields returning composite types default to a maxAge of 0,
+///   unless `inheritMaxAge` is set on the field or on the type
+/// - leaf fields inherit the policy of their parent
+///
+/// The path of the hints is built from the aliases of the fields, as in the
+/// hints sent by the upstream server
+pub fn schema_cache_hints(schema: &Schema, operation: &Operation) -> Vec<CacheHintDto> {
+    let mut hints = Vec::<CacheHintDto>::new();
+
+    visit_fields(Some(schema), operation, |field| {
+        let (field_definition, field_type) = match (field.definition, field.field_type) {
+            (Some(d), Some(t)) => (d, t),
+            _ => return,
+        };
+
+        let type_policy = match field_type.is_composite() {
+            true => CachePolicy::from_directives(&field_type.directives),
+            false => None,
+        };
+        let field_policy = CachePolicy::from_directives(&field_definition.directives);
+        let policy = merge_policies(type_policy, field_policy);
+
+        let max_age = match policy.max_age {
+            Some(max_age) => Some(max_age),
+            None if policy.inherit_max_age => None,
+            None if field.path.len() == 1 || field_type.is_composite() => Some(0),
+            None => None,
+        };
+
+        // Hints with no max age and no scope are redundant, the
+        // field inherits the policy of its parent anyway
+        if (max_age.is_some() || policy.scope.is_some())
+            && !hints.iter().any(|h| h.path == field.path)
+        {
+            hints.push(CacheHintDto {
+                path: field.path.clone(),
+                max_age: max_age,
+                scope: policy.scope,
+            });
+        }
+    });
+
+    hints
+}
+
+/// Computes the cache hints of an operation from the rules of the configuration.
+/// The first rule with a policy matching a field is applied. Two lists of hints are returned:
+/// the hints to use when the upstream server doesn't send any, and the ones
+/// overriding the hints of the upstream server.
+pub fn rule_cache_hints(
+    rules: &[CacheRule],
+    schema: Option<&Schema>,
+    operation: &Operation,
+) -> (Vec<CacheHintDto>, Vec<CacheHintDto>) {
+    let mut default_hints = Vec::<CacheHintDto>::new();
+    let mut override_hints = Vec::<CacheHintDto>::new();
+
+    if rules.len() == 0 {
+        return (default_hints, override_hints);
+    }
+
+    visit_fields(schema, operation, |field| {
+        let rule = match rules.iter().find(|r| r.has_policy() && r.matches(field)) {
+            Some(r) => r,
+            None => return,
+        };
+
+        let hints = match rule.r#override {
+            true => &mut override_hints,
+            false => &mut default_hints,
+        };
+
+        if !hints.iter().any(|h| h.path == field.path) {
+            hints.push(CacheHintDto {
+                path: field.path.clone(),
+                max_age: rule.max_age,
+                scope: rule.scope,
+            });
+        }
+    });
+
+    (default_hints, override_hints)
+}
+
+/// Returns the tags of the fields of an operation, along with their path
+/// in the response. The tags of all the rules matching a field are applied.
+pub fn rule_cache_tags(
+    rules: &[CacheRule],
+    schema: Option<&Schema>,
+    operation: &Operation,
+) -> Vec<(Vec<String>, Vec<String>)> {
+    let mut tags = Vec::new();
+
+    visit_fields(schema, operation, |field| {
+        let field_tags = rules
+            .iter()
+            .filter(|r| r.tags.len() > 0 && r.matches(field))
+            .flat_map(|r| r.tags.iter().cloned())
+            .unique()
+            .collect::<Vec<_>>();
+
+        if field_tags.len() > 0 {
+            tags.push((field.path.clone(), field_tags));
+        }
+    });
+
+    tags
+}
+
+/// Calls the visitor for every field of the operation. Without a schema,
+/// the root type is named after the operation type and the fields have
+/// neither a definition nor a type.
+fn visit_fields<'s, F>(schema: Option<&'s Schema>, operation: &Operation, mut visitor: F)
+where
+    F: FnMut(&FieldContext<'s>),
+{
+    let root_type = schema.and_then(|s| s.get_root_type(operation.operation_type));
+    let root_type_name = match (root_type, operation.operation_type) {
+        (Some(t), _) => t.name.clone(),
+        (None, OperationType::Query) => String::from("Query"),
+        (None, OperationType::Mutation) => String::from("Mutation"),
+        (None, OperationType::Subscription) => String::from("Subscription"),
+    };
+
+    let mut context = FieldContext {
+        path: vec![],
+        schema_path: vec![root_type_name],
+        definition: None,
+        field_type: None,
+    };
+
+    visit_fields_recursive(
+        schema,
+        root_type,
+        &operation.fields,
+        &mut context,
+        &mut visitor,
+    );
+}
+
+fn visit_fields_recursive<'s, F>(
+    schema: Option<&'s Schema>,
+    parent_type: Option<&'s TypeDefinition>,
+    fields: &[Field],
+    context: &mut FieldContext<'s>,
+    visitor: &mut F,
+) where
+    F: FnMut(&FieldContext<'s>),
+{
+    for field in fields {
+        match field {
+            Field::Field {
+                alias,
+                name,
+                fields: subfields,
+                ..
+            } => {
+                let definition = parent_type.and_then(|t| t.get_field(name));
+                let field_type = definition.and_then(|d| schema?.get_type(d.r#type.get_name()));
+
+                let (parent_definition, parent_field_type) =
+                    (context.definition, context.field_type);
+                context.path.push(String::from(alias.unwrap_or(name)));
+                context.schema_path.push(String::from(*name));
+                context.definition = definition;
+                context.field_type = field_type;
+
+                visitor(context);
+                visit_fields_recursive(schema, field_type, subfields, context, visitor);
+
+                context.path.pop();
+                context.schema_path.pop();
+                context.definition = parent_definition;
+                context.field_type = parent_field_type;
+            }
+            // Inline fragments do not appear in the response path
+            Field::InlineFragment {
+                type_condition,
+                fields: subfields,
+                ..
+            } => {
+                let fragment_type = match type_condition {
+                    Some(t) => schema.and_then(|s| s.get_type(t)),
+                    None => parent_type,
+                };
+
+                visit_fields_recursive(schema, fragment_type, subfields, context, visitor);
+            }
+            Field::Fragment { .. } => {}
+        }
+    }
+}
+
+/// Matches a name against a pattern, where `*` matches any sequence of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => match name.strip_prefix(prefix) {
+            Some(name_rest) => (0..=name_rest.len())
+                .filter(|i| name_rest.is_char_boundary(*i))
+                .any(|i| matches_pattern(rest, &name_rest[i..])),
+            None => false,
+        },
+    }
+}
+
+/// The policy of a field replaces the policy of its type.
+/// `inheritMaxAge` on the field ignores the max age of the type.
+fn merge_policies(
+    type_policy: Option<CachePolicy>,
+    field_policy: Option<CachePolicy>,
+) -> CachePolicy {
+    match (type_policy, field_policy) {
+        (None, None) => CachePolicy::default(),
+        (Some(p), None) | (None, Some(p)) => p,
+        (Some(type_policy), Some(field_policy)) => CachePolicy {
+            max_age: match field_policy.inherit_max_age {
+                true => field_policy.max_age,
+                false => field_policy.max_age.or(type_policy.max_age),
+            },
+            scope: field_policy.scope.or(type_policy.scope),
+            inherit_max_age: field_policy.inherit_max_age
+                || (field_policy.max_age.is_none() && type_policy.inherit_max_age),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::parser::parse_query;
+
+    type Hint = (Vec<String>, Option<u16>, Option<CacheScope>);
+
+    static SCHEMA: &'static str = r#"
+        enum CacheControlScope { PUBLIC PRIVATE }
+        directive @cacheControl(maxAge: Int, scope: CacheControlScope, inheritMaxAge: Boolean) on FIELD_DEFINITION | OBJECT | INTERFACE | UNION
+
+        type Query {
+            user(id: ID!): User @cacheControl(maxAge: 60)
+            company(id: ID!): Company
+            me: User @cacheControl(maxAge: 10, scope: PRIVATE)
+            version: String
+            node(id: ID!): Node
+        }
+
+        interface Node { id: ID! }
+
+        type User implements Node {
+            id: ID!
+            name: String
+            email: String @cacheControl(scope: PRIVATE)
+            company: Company
+            manager: User @cacheControl(inheritMaxAge: true)
+        }
+
+        type Company implements Node @cacheControl(maxAge: 3600) {
+            id: ID!
+            name: String
+            ceo: User
+        }
+    "#;
+
+    fn hints(query: &str) -> Vec<Hint> {
+        let schema = Schema::from_sdl(SCHEMA).unwrap();
+        let document = parse_query(query).unwrap();
+
+        schema_cache_hints(&schema, &document.operations[0])
+            .into_iter()
+            .map(|h| (h.path, h.max_age, h.scope))
+            .collect()
+    }
+
+    fn path(path: &[&str]) -> Vec<String> {
+        path.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn field_policies_override_type_policies() {
+        let result = hints(r#"{ user(id: 1) { id name company { name } } company(id: 2) { id } }"#);
+
+        assert_eq!(
+            result,
+            vec![
+                (path(&["user"]), Some(60), None),
+                (path(&["user", "company"]), Some(3600), None),
+                (path(&["company"]), Some(3600), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn root_and_composite_fields_default_to_zero_max_age() {
+        let result = hints(r#"{ version user(id: 1) { company { ceo { name } } } }"#);
+
+        assert_eq!(
+            result,
+            vec![
+                (path(&["version"]), Some(0), None),
+                (path(&["user"]), Some(60), None),
+                (path(&["user", "company"]), Some(3600), None),
+                (path(&["user", "company", "ceo"]), Some(0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn inherit_max_age_and_scope_are_applied() {
+        let result = hints(r#"{ me { email manager { name } } u: user(id: 1) { email } }"#);
+
+        assert_eq!(
+            result,
+            vec![
+                (path(&["me"]), Some(10), Some(CacheScope::PRIVATE)),
+                (path(&["me", "email"]), None, Some(CacheScope::PRIVATE)),
+                (path(&["u"]), Some(60), None),
+                (path(&["u", "email"]), None, Some(CacheScope::PRIVATE)),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_fragments_use_the_type_condition() {
+        let result = hints(r#"{ node(id: 1) { id ... on User { company { id } } } }"#);
+
+        assert_eq!(
+            result,
+            vec![
+                (path(&["node"]), Some(0), None),
+                (path(&["node", "company"]), Some(3600), None),
+            ]
+        );
+    }
+
+    fn rule_hints(
+        rules: serde_json::Value,
+        schema: Option<&Schema>,
+        query: &str,
+    ) -> (Vec<Hint>, Vec<Hint>) {
+        let rules: Vec<CacheRule> = serde_json::from_value(rules).unwrap();
+        let document = parse_query(query).unwrap();
+        let (default_hints, override_hints) =
+            rule_cache_hints(&rules, schema, &document.operations[0]);
+
+        let to_tuples = |hints: Vec<CacheHintDto>| {
+            hints
+                .into_iter()
+                .map(|h| (h.path, h.max_age, h.scope))
+                .collect::<Vec<_>>()
+        };
+
+        (to_tuples(default_hints), to_tuples(override_hints))
+    }
+
+    #[test]
+    fn rules_match_paths_without_schema() {
+        let (default_hints, override_hints) = rule_hints(
+            serde_json::json!([
+                {"path": "Query.user", "maxAge": 300, "scope": "PRIVATE"},
+                {"path": "Query.*.company", "maxAge": 60, "override": true},
+                {"type": "Company", "maxAge": 3600}
+            ]),
+            None,
+            r#"{ u: user(id: 1) { name company { name } } company(id: 1) { name } }"#,
+        );
+
+        assert_eq!(
+            default_hints,
+            vec![(path(&["u"]), Some(300), Some(CacheScope::PRIVATE))]
+        );
+        assert_eq!(
+            override_hints,
+            vec![(path(&["u", "company"]), Some(60), None)]
+        );
+    }
+
+    #[test]
+    fn rules_match_types_with_schema() {
+        let schema = Schema::from_sdl(SCHEMA).unwrap();
+        let (default_hints, override_hints) = rule_hints(
+            serde_json::json!([
+                {"path": "User.name", "maxAge": 10},
+                {"type": "Comp*", "maxAge": 3600},
+                {"path": "Query.me", "scope": "PRIVATE"}
+            ]),
+            Some(&schema),
+            r#"{ me { name company { name } } node(id: 1) { ... on User { company { id } } } }"#,
+        );
+
+        assert_eq!(
+            default_hints,
+            vec![
+                (path(&["me"]), None, Some(CacheScope::PRIVATE)),
+                (path(&["me", "company"]), Some(3600), None),
+                (path(&["node", "company"]), Some(3600), None),
+            ]
+        );
+        assert_eq!(override_hints, vec![]);
+    }
+
+    #[test]
+    fn rules_tag_fields() {
+        let rules: Vec<CacheRule> = serde_json::from_value(serde_json::json!([
+            {"path": "Query.product", "tags": ["catalog"]},
+            {"path": "Query.*", "maxAge": 60, "tags": ["catalog", "all"]}
+        ]))
+        .unwrap();
+        let document = parse_query("{ p: product(id: 1) { name } user { name } }").unwrap();
+
+        assert_eq!(
+            rule_cache_tags(&rules, None, &document.operations[0]),
+            vec![
+                (
+                    path(&["p"]),
+                    vec![String::from("catalog"), String::from("all")]
+                ),
+                (
+                    path(&["user"]),
+                    vec![String::from("catalog"), String::from("all")]
+                ),
+            ]
+        );
+
+        // Rules without a policy don't hide the next ones
+        let (default_hints, _) = rule_cache_hints(&rules, None, &document.operations[0]);
+        assert_eq!(default_hints[0].max_age, Some(60));
+    }
+
+    #[test]
+    fn patterns_match_wildcards() {
+        assert!(matches_pattern("user", "user"));
+        assert!(!matches_pattern("user", "users"));
+        assert!(matches_pattern("*", "users"));
+        assert!(matches_pattern("user*", "users"));
+        assert!(matches_pattern("*Connection", "UserConnection"));
+        assert!(matches_pattern("U*r*n", "UserConnection"));
+        assert!(!matches_pattern("*Edge", "UserConnection"));
+    }
+
+    #[test]
+    fn response_policies_are_restricted() {
+        let policy = ResponseCachePolicy::new(60, 0, CacheScope::PUBLIC)
+            .restrict(ResponseCachePolicy::new(30, 20, CacheScope::PUBLIC));
+        assert_eq!(policy.cache_control_header(), "max-age=50, public");
+        assert_eq!(policy.age, 20);
+
+        let policy = policy.restrict(ResponseCachePolicy::new(100, 0, CacheScope::PRIVATE));
+        assert_eq!(policy.cache_control_header(), "max-age=50, private");
+
+        let policy = policy.restrict(ResponseCachePolicy::no_store());
+        assert_eq!(policy.cache_control_header(), "no-store");
+    }
+}
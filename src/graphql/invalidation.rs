@@ -0,0 +1,126 @@
+use crate::graphql::cache_handler::{field_to_cache_key, to_field_tag};
+use crate::graphql::parser::parse_query;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Cache entries purged when a mutation field succeeds. The patterns reference the
+/// arguments of the mutation field with `$`, nested values with a dotted path:
+/// `{"mutation": "updateUser", "fields": ["user(id: $input.id)"], "entities": ["User:$input.id"]}`
+#[derive(Deserialize, Debug, Clone)]
+pub struct InvalidationRule {
+    /// Name of the mutation field
+    pub mutation: String,
+    /// Root fields of the queries, with their arguments. The entries of their
+    /// subfields and the private entries of all the users are purged as well
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Entities of the normalized cache, `Type:key`
+    #[serde(default)]
+    pub entities: Vec<String>,
+}
+
+impl InvalidationRule {
+    /// Returns the cache tags invalidated by the mutation field, called with the given
+    /// arguments. The patterns referencing a missing or non scalar argument are ignored
+    pub fn cache_tags(&self, arguments: &Map<String, Value>) -> Vec<String> {
+        let mut tags = Vec::new();
+
+        for pattern in &self.fields {
+            let query = match substitute_arguments(pattern, arguments, true) {
+                Some(f) => format!("{{ {} }}", f),
+                None => continue,
+            };
+
+            match parse_query(&query) {
+                Ok(document) => tags.extend(
+                    document
+                        .operations
+                        .iter()
+                        .flat_map(|o| &o.fields)
+                        .map(|f| to_field_tag(&field_to_cache_key(f, &Map::new()))),
+                ),
+                Err(e) => println!("Invalid invalidation rule {}: {}", pattern, e),
+            }
+        }
+
+        tags.extend(
+            self.entities
+                .iter()
+                .filter_map(|pattern| substitute_arguments(pattern, arguments, false)),
+        );
+
+        tags
+    }
+}
+
+/// Replaces the `$argument` references of a pattern with the values of the arguments.
+/// Strings are quoted in fields, where they are GraphQL literals
+fn substitute_arguments(
+    pattern: &str,
+    arguments: &Map<String, Value>,
+    quote_strings: bool,
+) -> Option<String> {
+    let mut result = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        let path = rest[..end].trim_end_matches('.');
+        rest = &rest[path.len()..];
+
+        let mut value = Value::Object(arguments.clone());
+        for segment in path.split('.') {
+            value = value.get(segment)?.clone();
+        }
+
+        match value {
+            Value::String(s) if quote_strings => result.push_str(&Value::String(s).to_string()),
+            Value::String(s) => result.push_str(&s),
+            v @ (Value::Number(_) | Value::Bool(_)) => result.push_str(&v.to_string()),
+            _ => return None,
+        }
+    }
+    result.push_str(rest);
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvalidationRule;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn cache_tags_are_built_from_the_arguments() {
+        let rule = InvalidationRule {
+            mutation: String::from("updateUser"),
+            fields: vec![
+                String::from("user(id: $id)"),
+                String::from("userByEmail(email: $input.email)"),
+                String::from("users"),
+                String::from("company(id: $companyId)"),
+            ],
+            entities: vec![String::from("User:$id"), String::from("Email:$input.email")],
+        };
+        let arguments = match json!({"id": 42, "input": {"email": "a@b.c"}}) {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            rule.cache_tags(&arguments),
+            vec![
+                "field:user_id42",
+                "field:userByEmail_email\"a@b.c\"",
+                "field:users",
+                "User:42",
+                "Email:a@b.c"
+            ]
+        );
+    }
+}
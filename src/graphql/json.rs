@@ -1,7 +1,10 @@
 mod json;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
+use std::mem;
 
 // https://stackoverflow.com/questions/47070876/how-can-i-merge-two-json-objects-with-rust
+/// Lists of the same length are merged element by element, so that the
+/// fields of the elements cached separately are reassembled
 pub fn merge_json(a: &mut Value, b: Value) {
     match (a, b) {
         (a @ &mut Value::Object(_), Value::Object(b)) => {
@@ -10,11 +13,79 @@ pub fn merge_json(a: &mut Value, b: Value) {
                 merge_json(a.entry(k).or_insert(Value::Null), v);
             }
         }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (x, y) in a.iter_mut().zip(b) {
+                merge_json(x, y);
+            }
+        }
         (a, b) => *a = b,
     }
 }
 
+/// Returns the index of a list element, for a segment of a path made of digits.
+/// Field names can't start with a digit.
+pub fn list_index(segment: &str) -> Option<usize> {
+    if segment.len() > 0 && segment.bytes().all(|b| b.is_ascii_digit()) {
+        segment.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The value of a list element nothing was extracted from. An empty object doesn't
+/// contain any field, so it's never mistaken for a value when it's merged or cached
+fn empty_element(element: &Value) -> Value {
+    match element {
+        Value::Null => Value::Null,
+        _ => Value::Object(Map::new()),
+    }
+}
+
+/// The path applies to each element of a list, unless it starts with the
+/// index of an element. The list keeps its length, the elements the path
+/// doesn't match are replaced with empty objects.
+fn split_list_path(path: &[String]) -> (Option<usize>, &[String]) {
+    match list_index(&path[0]) {
+        Some(index) => (Some(index), &path[1..]),
+        None => (None, path),
+    }
+}
+
+/// Extracts the path from a JSON value. The value of the path is removed from
+/// `json_value`, and returned along with the hierarchy of its parents:
+/// `{"f1": {"subf3": {"subsubf1": 31}}}` for the path `f1.subf3.subsubf1`.
+/// Paths traverse lists, the value is extracted from each element.
 pub fn extract_mut(json_value: &mut Value, path: &[String]) -> Option<Value> {
+    if let Value::Array(elements) = json_value {
+        let (index, path) = split_list_path(path);
+        let mut found = false;
+        let values = elements
+            .iter_mut()
+            .enumerate()
+            .map(|(i, element)| {
+                let value = match index {
+                    Some(index) if index != i => None,
+                    _ if path.len() == 0 => Some(mem::replace(element, empty_element(element))),
+                    _ => extract_mut(element, path),
+                };
+
+                match value {
+                    Some(v) => {
+                        found = true;
+                        v
+                    }
+                    None => empty_element(element),
+                }
+            })
+            .collect();
+
+        return if found {
+            Some(Value::Array(values))
+        } else {
+            None
+        };
+    }
+
     if path.len() == 1 {
         match json_value {
             Value::Object(v) => match v.remove(&path[0]) {
@@ -83,11 +154,42 @@ pub fn extract_mut(json_value: &mut Value, path: &[String]) -> Option<Value> {
 }
 */
 
+/// Returns the value of the path, along with the hierarchy of its parents.
+/// Paths traverse lists, the value is extracted from each element.
 pub fn extract(json_value: &Value, path: &[String]) -> Option<Value> {
     if path.len() == 0 {
         return Some(json_value.clone());
     }
 
+    if let Value::Array(elements) = json_value {
+        let (index, path) = split_list_path(path);
+        let mut found = false;
+        let values = elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| {
+                let value = match index {
+                    Some(index) if index != i => None,
+                    _ => extract(element, path),
+                };
+
+                match value {
+                    Some(v) => {
+                        found = true;
+                        v
+                    }
+                    None => empty_element(element),
+                }
+            })
+            .collect();
+
+        return if found {
+            Some(Value::Array(values))
+        } else {
+            None
+        };
+    }
+
     let field = match json_value {
         Value::Object(v) if v.contains_key(&path[0]) => extract(&json_value[&path[0]], &path[1..]),
         _ => return None,
@@ -99,11 +201,29 @@ pub fn extract(json_value: &Value, path: &[String]) -> Option<Value> {
     }
 }
 
+/// Removes the path from a JSON value. Paths traverse lists, the
+/// value is removed from each element
 pub fn remove_field(json_value: Value, path: &[String]) -> Value {
     if path.len() == 0 {
         return json_value;
     }
 
+    if let Value::Array(elements) = json_value {
+        let (index, path) = split_list_path(path);
+
+        return Value::Array(
+            elements
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| match index {
+                    Some(index) if index != i => element,
+                    _ if path.len() == 0 => empty_element(&element),
+                    _ => remove_field(element, path),
+                })
+                .collect(),
+        );
+    }
+
     if path.len() == 1 {
         return match json_value {
             Value::Object(mut v) => {
@@ -147,4 +267,56 @@ mod tests {
         assert_eq!(expected_extracted, extracted);
         assert_eq!(expected_json, json);
     }
+
+    fn path(path: &[&str]) -> Vec<String> {
+        path.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn paths_traverse_lists() {
+        let json = json!({"users": [{"id": 1, "name": "a"}, null, {"id": 3}]});
+
+        assert_eq!(
+            extract(&json, &path(&["users", "name"])),
+            Some(json!({"users": [{"name": "a"}, null, {}]}))
+        );
+        assert_eq!(
+            extract(&json, &path(&["users", "2", "id"])),
+            Some(json!({"users": [{}, null, {"id": 3}]}))
+        );
+        assert_eq!(extract(&json, &path(&["users", "email"])), None);
+
+        assert_eq!(
+            remove_field(json.clone(), &path(&["users", "id"])),
+            json!({"users": [{"name": "a"}, null, {}]})
+        );
+        assert_eq!(
+            remove_field(json.clone(), &path(&["users", "0"])),
+            json!({"users": [{}, null, {"id": 3}]})
+        );
+
+        let mut extracted_json = json.clone();
+        assert_eq!(
+            extract_mut(&mut extracted_json, &path(&["users", "name"])),
+            Some(json!({"users": [{"name": "a"}, null, {}]}))
+        );
+        assert_eq!(
+            extracted_json,
+            json!({"users": [{"id": 1}, null, {"id": 3}]})
+        );
+    }
+
+    #[test]
+    fn lists_are_merged_by_element() {
+        let mut json = json!({"users": [{"id": 1}, {"id": 2}], "tags": ["a", "b"]});
+        merge_json(
+            &mut json,
+            json!({"users": [{"name": "a"}, {}], "tags": ["c"]}),
+        );
+
+        assert_eq!(
+            json,
+            json!({"users": [{"id": 1, "name": "a"}, {"id": 2}], "tags": ["c"]})
+        );
+    }
 }
@@ -1,4 +1,10 @@
 pub mod cache;
 pub mod cache_handler;
+pub mod cache_policy;
+pub mod invalidation;
 pub mod json;
+pub mod normalization;
+pub mod operation_cache;
 pub mod parser;
+pub mod schema;
+pub mod validation;
@@ -0,0 +1,338 @@
+use crate::graphql::parser::{flatten_inline_fragments, Field, Operation};
+use crate::graphql::schema::Schema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Field of the cached values referencing an entity. Names starting
+/// with `__` are reserved for introspection, it can't collide with a field
+pub const REFERENCE_FIELD: &str = "__ref";
+
+/// Normalized cache: the objects with a `__typename` and key fields (entities) are
+/// stored once under `Type:key`, the cached fields reference them. An entity
+/// fetched through different fields is shared by all of them.
+/// The key fields are only added to the forwarded selections with a schema, without
+/// one the entities are identified when the query selects their key fields.
+#[derive(Debug, Default, Deserialize)]
+pub struct NormalizationConfig {
+    /// Fields identifying the entities of a type, `id` for the types not listed
+    #[serde(default)]
+    pub key_fields: HashMap<String, Vec<String>>,
+}
+
+impl NormalizationConfig {
+    fn get_key_fields(&self, type_name: &str) -> Vec<&str> {
+        match self.key_fields.get(type_name) {
+            Some(fields) => fields.iter().map(|f| f.as_str()).collect(),
+            None => vec!["id"],
+        }
+    }
+
+    /// Returns the cache key of an entity, `Type:id`. The values of
+    /// multiple key fields are separated by `:`
+    pub fn entity_key(&self, object: &Map<String, Value>) -> Option<String> {
+        let type_name = match object.get("__typename") {
+            Some(Value::String(t)) => t,
+            _ => return None,
+        };
+
+        let mut key = type_name.clone();
+        for key_field in self.get_key_fields(type_name) {
+            match object.get(key_field) {
+                Some(Value::String(s)) => key.push_str(&format!(":{}", s)),
+                Some(Value::Number(n)) => key.push_str(&format!(":{}", n)),
+                _ => return None,
+            }
+        }
+
+        Some(key)
+    }
+
+    /// Replaces the entities of a cached value with references. Returns the value
+    /// along with the entities, which are normalized as well
+    pub fn normalize(&self, value: Value) -> (Value, Vec<(String, Value)>) {
+        let mut entities = Vec::new();
+
+        // The value is the hierarchy of a cached field, from the root of the operation
+        let value = match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| (k, self.normalize_value(v, &mut entities)))
+                    .collect(),
+            ),
+            v => v,
+        };
+
+        (value, entities)
+    }
+
+    fn normalize_value(&self, value: Value, entities: &mut Vec<(String, Value)>) -> Value {
+        match value {
+            Value::Array(elements) => Value::Array(
+                elements
+                    .into_iter()
+                    .map(|e| self.normalize_value(e, entities))
+                    .collect(),
+            ),
+            Value::Object(map) => {
+                let map = map
+                    .into_iter()
+                    .map(|(k, v)| (k, self.normalize_value(v, entities)))
+                    .collect::<Map<String, Value>>();
+
+                match self.entity_key(&map) {
+                    Some(key) => {
+                        entities.push((key.clone(), Value::Object(map)));
+                        json!({ REFERENCE_FIELD: key })
+                    }
+                    None => Value::Object(map),
+                }
+            }
+            v => v,
+        }
+    }
+
+    /// Returns the keys of the entities contained in a value, or referenced by it
+    pub fn entity_keys(&self, value: &Value) -> Vec<String> {
+        match value {
+            Value::Array(elements) => elements.iter().flat_map(|e| self.entity_keys(e)).collect(),
+            Value::Object(map) => self
+                .entity_key(map)
+                .or_else(|| map.get(REFERENCE_FIELD)?.as_str().map(String::from))
+                .into_iter()
+                .chain(map.values().flat_map(|v| self.entity_keys(v)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Adds `__typename` and the key fields to the selections of an operation,
+    /// so that the entities of the response can be identified. The key fields
+    /// are only added when the schema tells the type of the selection defines them.
+    pub fn add_key_fields<'a>(
+        &'a self,
+        mut operation: Operation<'a>,
+        schema: Option<&Schema>,
+    ) -> Operation<'a> {
+        let root_type = schema
+            .and_then(|s| s.get_root_type(operation.operation_type))
+            .map(|t| t.name.as_str());
+        self.add_key_fields_to_selection(&mut operation.fields, root_type, schema, false);
+
+        operation
+    }
+
+    fn add_key_fields_to_selection<'a>(
+        &'a self,
+        fields: &mut Vec<Field<'a>>,
+        type_name: Option<&str>,
+        schema: Option<&Schema>,
+        is_object: bool,
+    ) {
+        for field in fields.iter_mut() {
+            match field {
+                Field::Field {
+                    name,
+                    fields: subfields,
+                    ..
+                } if subfields.len() > 0 => {
+                    let field_type = match (schema, type_name) {
+                        (Some(s), Some(t)) => s.get_field(t, name).map(|d| d.r#type.get_name()),
+                        _ => None,
+                    };
+                    self.add_key_fields_to_selection(subfields, field_type, schema, true);
+                }
+                // The key fields are added to the selection containing the fragment
+                Field::InlineFragment {
+                    type_condition,
+                    fields: subfields,
+                    ..
+                } => {
+                    let fragment_type = type_condition.or(type_name);
+                    self.add_key_fields_to_selection(subfields, fragment_type, schema, false);
+                }
+                _ => {}
+            }
+        }
+
+        if !is_object {
+            return;
+        }
+
+        let selected = flatten_inline_fragments(fields)
+            .into_iter()
+            .filter(|f| !f.has_parameters())
+            .map(|f| f.get_name().to_string())
+            .collect::<HashSet<_>>();
+
+        let mut key_fields = vec!["__typename"];
+        if let (Some(schema), Some(type_name)) = (schema, type_name) {
+            let type_key_fields = self.get_key_fields(type_name);
+            if type_key_fields
+                .iter()
+                .all(|k| schema.get_field(type_name, k).is_some())
+            {
+                key_fields.extend(type_key_fields);
+            }
+        }
+
+        for key_field in key_fields {
+            if !selected.contains(key_field) {
+                fields.push(Field::new_field(None, key_field, vec![], vec![]));
+            }
+        }
+    }
+}
+
+/// Removes the fields of the response which are not part of the selection
+/// (the fields added to identify the entities)
+pub fn select_fields(value: &mut Value, fields: &[&Field]) {
+    match value {
+        Value::Array(elements) => {
+            for element in elements {
+                select_fields(element, fields);
+            }
+        }
+        Value::Object(map) => {
+            // Fields with the same alias are merged, their subfields are combined
+            let mut subfields = HashMap::<&str, Vec<&Field>>::new();
+            for field in fields {
+                subfields
+                    .entry(field.get_alias())
+                    .or_default()
+                    .extend(flatten_inline_fragments(field.get_subfields()));
+            }
+
+            map.retain(|k, _| subfields.contains_key(k.as_str()));
+            for (alias, subfields) in subfields {
+                if let Some(v) = map.get_mut(alias) {
+                    if subfields.len() > 0 {
+                        select_fields(v, &subfields);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select_fields, NormalizationConfig};
+    use crate::graphql::parser::{flatten_inline_fragments, parse_query, serialize_operation};
+    use crate::graphql::schema::Schema;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn config() -> NormalizationConfig {
+        let mut key_fields = HashMap::new();
+        key_fields.insert(
+            String::from("Product"),
+            vec![String::from("upc"), String::from("sku")],
+        );
+
+        NormalizationConfig {
+            key_fields: key_fields,
+        }
+    }
+
+    #[test]
+    fn entities_are_replaced_with_references() {
+        let value = json!({"post_id1": {
+            "title": "t",
+            "author": {"__typename": "User", "id": 42, "name": "n"},
+            "products": [
+                {"__typename": "Product", "upc": "1", "sku": "a"},
+                {"__typename": "Product", "upc": "2"}
+            ]
+        }});
+
+        let (value, entities) = config().normalize(value);
+
+        assert_eq!(
+            value,
+            json!({"post_id1": {
+                "title": "t",
+                "author": {"__ref": "User:42"},
+                "products": [
+                    {"__ref": "Product:1:a"},
+                    {"__typename": "Product", "upc": "2"}
+                ]
+            }})
+        );
+        assert_eq!(
+            entities,
+            vec![
+                (
+                    String::from("User:42"),
+                    json!({"__typename": "User", "id": 42, "name": "n"})
+                ),
+                (
+                    String::from("Product:1:a"),
+                    json!({"__typename": "Product", "upc": "1", "sku": "a"})
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn entity_keys_are_collected() {
+        let value = json!({"user": {"__typename": "User", "id": 42, "friends": [
+            {"__typename": "User", "id": "43"},
+            {"__typename": "Product", "upc": "1"},
+            {"__ref": "User:44"}
+        ]}});
+
+        assert_eq!(
+            config().entity_keys(&value),
+            vec!["User:42", "User:43", "User:44"]
+        );
+    }
+
+    #[test]
+    fn key_fields_are_added_to_selections() {
+        let schema = Schema::from_sdl(
+            "type Query { post(id: ID): Post } \
+             type Post { id: ID title: String author: User products: [Product] } \
+             type User { id: ID name: String } \
+             type Product { upc: String sku: String name: String }",
+        )
+        .unwrap();
+        let config = config();
+        let document = parse_query(
+            "{ post(id: 1) { title author { name } products { ... on Product { name } } } }",
+        )
+        .unwrap();
+        let operation = document.operations.into_iter().nth(0).unwrap();
+
+        let with_schema = config.add_key_fields(operation.clone(), Some(&schema));
+        assert_eq!(
+            serialize_operation(&with_schema),
+            "{post(id:1){title author{name __typename id} products{... on Product{name} __typename upc sku} __typename id}}"
+        );
+
+        let without_schema = config.add_key_fields(operation, None);
+        assert_eq!(
+            serialize_operation(&without_schema),
+            "{post(id:1){title author{name __typename} products{... on Product{name} __typename} __typename}}"
+        );
+    }
+
+    #[test]
+    fn fields_out_of_the_selection_are_removed() {
+        let document = parse_query("{ a: post { title } a: post { id } users { name } }").unwrap();
+        let operation = document.operations.into_iter().nth(0).unwrap();
+        let mut value = json!({
+            "a": {"__typename": "Post", "id": 1, "title": "t"},
+            "users": [{"__typename": "User", "name": "n"}, null],
+            "__typename": "Query"
+        });
+
+        select_fields(&mut value, &flatten_inline_fragments(&operation.fields));
+
+        assert_eq!(
+            value,
+            json!({"a": {"id": 1, "title": "t"}, "users": [{"name": "n"}, null]})
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::graphql::parser::{expand_operation, Error, Operation};
+use crate::graphql::schema::Schema;
+use crate::graphql::validation::parse_and_validate;
+use lru::LruCache;
+use self_cell::self_cell;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+self_cell!(
+    /// The operation borrows from the query text, which is stored along with it
+    struct OperationCell {
+        owner: String,
+
+        #[covariant]
+        dependent: Operation,
+    }
+);
+
+/// A parsed, validated and expanded operation (without fragments)
+pub struct ParsedOperation(OperationCell);
+
+impl ParsedOperation {
+    /// Parses and validates the query, selects the operation and expands its fragments
+    pub fn parse(
+        query: String,
+        operation_name: Option<&str>,
+        schema: Option<&Schema>,
+    ) -> Result<ParsedOperation, Vec<Error>> {
+        let cell = OperationCell::try_new(query, |query| {
+            let document = parse_and_validate(query, schema)?;
+            let (operation, fragment_definitions) = document
+                .select_operation(operation_name)
+                .map_err(|e| vec![e])?;
+
+            expand_operation(operation, fragment_definitions).map_err(|e| vec![e])
+        })?;
+
+        Ok(ParsedOperation(cell))
+    }
+
+    pub fn operation(&self) -> &Operation<'_> {
+        self.0.borrow_dependent()
+    }
+}
+
+/// LRU of the parsed operations, keyed by the hash of the query and the operation name.
+/// Clients send the same queries over and over, they are parsed once.
+/// Errors are not cached.
+pub struct OperationCache {
+    operations: Option<Mutex<LruCache<[u8; 32], Arc<ParsedOperation>>>>,
+}
+
+impl OperationCache {
+    /// A capacity of 0 disables the cache
+    pub fn new(capacity: usize) -> OperationCache {
+        OperationCache {
+            operations: NonZeroUsize::new(capacity).map(|c| Mutex::new(LruCache::new(c))),
+        }
+    }
+
+    pub fn get(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        schema: Option<&Schema>,
+    ) -> Result<Arc<ParsedOperation>, Vec<Error>> {
+        let operations = match &self.operations {
+            Some(o) => o,
+            None => {
+                return ParsedOperation::parse(query.to_string(), operation_name, schema)
+                    .map(Arc::new)
+            }
+        };
+
+        let key = cache_key(query, operation_name);
+        if let Some(operation) = operations.lock().unwrap().get(&key) {
+            return Ok(operation.clone());
+        }
+
+        // The lock is not held while parsing, concurrent requests
+        // for a new query may parse it more than once
+        let operation = Arc::new(ParsedOperation::parse(
+            query.to_string(),
+            operation_name,
+            schema,
+        )?);
+        operations.lock().unwrap().put(key, operation.clone());
+
+        Ok(operation)
+    }
+
+    #[cfg(test)]
+    fn count(&self) -> usize {
+        match &self.operations {
+            Some(o) => o.lock().unwrap().len(),
+            None => 0,
+        }
+    }
+}
+
+fn cache_key(query: &str, operation_name: Option<&str>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(query.as_bytes());
+    // The separator can't be part of an operation name
+    hasher.update(b"#");
+    hasher.update(operation_name.unwrap_or("").as_bytes());
+
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OperationCache, ParsedOperation};
+    use std::sync::Arc;
+
+    #[test]
+    fn operations_are_expanded() {
+        let query = String::from(
+            "query Op1 { field1 { ...fields } } query Op2 { field2 } fragment fields on T { id }",
+        );
+        let parsed = ParsedOperation::parse(query.clone(), Some("Op1"), None).unwrap();
+        let operation = parsed.operation();
+        assert_eq!(operation.name, Some("Op1"));
+        assert_eq!(operation.fields[0].get_subfields()[0].get_name(), "id");
+
+        assert!(ParsedOperation::parse(query.clone(), None, None).is_err());
+        assert!(ParsedOperation::parse(query, Some("Op3"), None).is_err());
+    }
+
+    #[test]
+    fn operations_are_parsed_once() {
+        let cache = OperationCache::new(2);
+        let query = "query Op1 { field1 } query Op2 { field2 }";
+
+        let op1 = cache.get(query, Some("Op1"), None).unwrap();
+        assert!(Arc::ptr_eq(
+            &op1,
+            &cache.get(query, Some("Op1"), None).unwrap()
+        ));
+
+        let op2 = cache.get(query, Some("Op2"), None).unwrap();
+        assert_eq!(op2.operation().name, Some("Op2"));
+        assert_eq!(cache.count(), 2);
+
+        assert!(cache.get("{ field3 ", None, None).is_err());
+        assert_eq!(cache.count(), 2);
+
+        cache.get("{ field3 }", None, None).unwrap();
+        assert_eq!(cache.count(), 2);
+        // Op1 was the least recently used
+        assert!(!Arc::ptr_eq(
+            &op1,
+            &cache.get(query, Some("Op1"), None).unwrap()
+        ));
+
+        let disabled = OperationCache::new(0);
+        disabled.get("{ field1 }", None, None).unwrap();
+        assert_eq!(disabled.count(), 0);
+    }
+}
@@ -1,5 +1,10 @@
+pub mod sdl;
 mod tokenizer;
+use crate::graphql::json::list_index;
+use serde_json::{json, Map, Value};
 use std::collections::HashSet;
+use std::fmt;
+pub use tokenizer::Location;
 use tokenizer::Tokenizer;
 
 pub fn parse_query<'a>(query: &'a str) -> Result<Document<'a>, Error> {
@@ -57,24 +62,26 @@ pub fn parse_query<'a>(query: &'a str) -> Result<Document<'a>, Error> {
                 )?)
             }
             Some("{") => {
-                return Err(Error {
-                    error: String::from("Operation type is required when not in shorthand mode"),
-                })
+                return Err(Error::syntax(
+                    "Operation type is required when not in shorthand mode",
+                    tokens.location(),
+                ))
             }
-            Some(s) => return Err(Error::new(format!("invalid token \"{}\"", s))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
             None if operations.len() > 0 => {
                 return Ok(Document {
                     operations: operations,
                     fragment_definitions: fragment_definitions,
                 })
             }
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
         if query_shorthand && operations.len() > 1 {
-            return Err(Error {
-                error: String::from("Only one operation allowed in shorthand mode"),
-            });
+            return Err(Error::syntax(
+                "Only one operation allowed in shorthand mode",
+                tokens.location(),
+            ));
         }
     }
 }
@@ -101,7 +108,13 @@ pub fn serialize_operation<'a>(operation: &Operation<'a>) -> String {
 fn internal_serialize_operation<'a>(operation: &Operation<'a>, disable_shorthand: bool) -> String {
     let mut serialized_operation = String::with_capacity(500)
         + match operation.operation_type {
-            OperationType::Query if operation.variables.len() == 0 && !disable_shorthand => "",
+            OperationType::Query
+                if operation.variables.len() == 0
+                    && operation.directives.len() == 0
+                    && !disable_shorthand =>
+            {
+                ""
+            }
             OperationType::Query => "query",
             OperationType::Mutation => "mutation",
             OperationType::Subscription => "subscription",
@@ -117,6 +130,8 @@ fn internal_serialize_operation<'a>(operation: &Operation<'a>, disable_shorthand
         serialized_operation.push(')');
     }
 
+    serialize_directives(&operation.directives, &mut serialized_operation);
+
     serialized_operation.push('{');
     append_element(
         &mut serialized_operation,
@@ -147,6 +162,7 @@ fn serialize_fragment<'a>(fragment: &FragmentDefinition<'a>, s1: &mut String) {
     s1.push_str(fragment.name);
     s1.push_str(" on ");
     s1.push_str(fragment.r#type);
+    serialize_directives(&fragment.directives, s1);
     s1.push('{');
 
     append_element(s1, &fragment.fields, serialize_field);
@@ -160,6 +176,7 @@ fn serialize_field<'a>(field: &Field<'a>, s1: &mut String) {
             alias,
             name,
             parameters,
+            directives,
             fields,
         } => {
             if let Some(a) = alias {
@@ -176,15 +193,47 @@ fn serialize_field<'a>(field: &Field<'a>, s1: &mut String) {
                 s1.push(')');
             }
 
+            serialize_directives(directives, s1);
+
             if fields.len() > 0 {
                 s1.push('{');
                 append_element(s1, &fields, serialize_field);
                 s1.push('}');
             }
         }
-        Field::Fragment { name } => {
+        Field::Fragment { name, directives } => {
             s1.push_str("...");
             s1.push_str(name);
+            serialize_directives(directives, s1);
+        }
+        Field::InlineFragment {
+            type_condition,
+            directives,
+            fields,
+        } => {
+            s1.push_str("...");
+            if let Some(t) = type_condition {
+                s1.push_str(" on ");
+                s1.push_str(t);
+            }
+            serialize_directives(directives, s1);
+
+            s1.push('{');
+            append_element(s1, &fields, serialize_field);
+            s1.push('}');
+        }
+    }
+}
+
+fn serialize_directives<'a>(directives: &[Directive<'a>], s1: &mut String) {
+    for directive in directives {
+        s1.push('@');
+        s1.push_str(directive.name);
+
+        if directive.parameters.len() > 0 {
+            s1.push('(');
+            append_element(s1, &directive.parameters, serialize_parameter);
+            s1.push(')');
         }
     }
 }
@@ -193,7 +242,7 @@ fn serialize_variable<'a>(variable: &Variable<'a>, s1: &mut String) {
     s1.push('$');
     s1.push_str(variable.name);
     s1.push(':');
-    s1.push_str(variable.r#type);
+    s1.push_str(&variable.r#type.to_string());
 
     match &variable.default_value {
         Some(value) => {
@@ -202,6 +251,8 @@ fn serialize_variable<'a>(variable: &Variable<'a>, s1: &mut String) {
         }
         _ => {}
     }
+
+    serialize_directives(&variable.directives, s1);
 }
 
 fn serialize_parameter<'a>(parameter: &Parameter<'a>, s1: &mut String) {
@@ -213,9 +264,13 @@ fn serialize_parameter<'a>(parameter: &Parameter<'a>, s1: &mut String) {
 
 fn serialize_parameter_value<'a>(value: &ParameterValue<'a>, s1: &mut String) {
     match value {
-        ParameterValue::Scalar(s) => s1.push_str(s),
+        ParameterValue::Int(s)
+        | ParameterValue::Float(s)
+        | ParameterValue::String(s)
+        | ParameterValue::Enum(s) => s1.push_str(s),
+        ParameterValue::Boolean(b) => s1.push_str(if *b { "true" } else { "false" }),
         ParameterValue::Variable(v) => s1.push_str(&format!("${}", v)),
-        ParameterValue::Nil => s1.push_str("null"),
+        ParameterValue::Null => s1.push_str("null"),
         ParameterValue::Object(o) => {
             s1.push('{');
             append_element(s1, o, serialize_parameter_field);
@@ -257,6 +312,7 @@ pub fn expand_operation<'a>(
         name: operation.name,
         fields: new_fields,
         variables: operation.variables,
+        directives: operation.directives,
     });
 }
 
@@ -266,12 +322,21 @@ fn expand_fragment<'a, 'b>(
     fragment_stack: &mut Vec<&'b FragmentDefinition<'a>>,
 ) -> Result<Vec<Field<'a>>, Error> {
     let fields = match field {
-        Field::Fragment { name } => {
+        Field::Fragment { name, directives } => {
             let mut res = Vec::new();
-            let fragment = fragments.iter().filter(|f| f.name == name).nth(0).unwrap();
+            let fragment = match fragments.iter().filter(|f| f.name == name).nth(0) {
+                Some(f) => f,
+                None => {
+                    return Err(Error::UnknownFragment {
+                        name: name.to_string(),
+                    })
+                }
+            };
 
             if fragment_stack.contains(&fragment) {
-                return Err(Error::new("Recursive fragment structure".to_string()));
+                return Err(Error::RecursiveFragment {
+                    name: name.to_string(),
+                });
             }
 
             fragment_stack.push(fragment);
@@ -286,12 +351,45 @@ fn expand_fragment<'a, 'b>(
 
             fragment_stack.pop();
 
-            res
+            // Directives can't be applied to the fields of the fragment,
+            // they are preserved in an inline fragment
+            if directives.len() > 0 {
+                vec![Field::InlineFragment {
+                    type_condition: None,
+                    directives: directives,
+                    fields: res,
+                }]
+            } else {
+                res
+            }
+        }
+        Field::InlineFragment {
+            type_condition,
+            directives,
+            fields: subfields,
+        } => {
+            let mut new_subfields = vec![];
+            for subfield in subfields {
+                new_subfields.append(&mut expand_fragment(subfield, fragments, fragment_stack)?);
+            }
+
+            // An inline fragment without type condition and directives
+            // is equivalent to its list of fields
+            if type_condition.is_none() && directives.len() == 0 {
+                new_subfields
+            } else {
+                vec![Field::InlineFragment {
+                    type_condition: type_condition,
+                    directives: directives,
+                    fields: new_subfields,
+                }]
+            }
         }
         Field::Field {
             alias,
             name,
             parameters,
+            directives,
             fields: subfields,
         } => {
             let mut new_subfields = vec![];
@@ -303,6 +401,7 @@ fn expand_fragment<'a, 'b>(
                 alias: alias,
                 name: name,
                 parameters: parameters,
+                directives: directives,
                 fields: new_subfields,
             }]
         }
@@ -311,63 +410,61 @@ fn expand_fragment<'a, 'b>(
     Ok(fields)
 }
 
-fn parse_fragment_definition<'a, I>(
-    tokens: &mut I,
+fn parse_fragment_definition<'a>(
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<FragmentDefinition<'a>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+) -> Result<FragmentDefinition<'a>, Error> {
     let name = match tokens.next() {
         Some(name) if is_valid_name(&name) => name,
-        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
     };
 
     match tokens.next() {
         Some("on") => {}
-        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
     };
 
     let type_name = match tokens.next() {
         Some(type_name) if is_valid_name(&type_name) => type_name,
-        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
     };
 
-    let fields = match tokens.next() {
+    let (directives, next_token) = parse_directives(tokens.next(), tokens, parser_state)?;
+
+    let fields = match next_token {
         Some("{") => {
             parser_state.hierarchy.push("{");
             parse_fields(tokens, parser_state)?
         }
-        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
     };
 
     Ok(FragmentDefinition {
         name: name,
         r#type: type_name,
+        directives: directives,
         fields: fields,
     })
 }
 
-fn parse_operation<'a, I>(
+fn parse_operation<'a>(
     current_token: Option<&'a str>,
     query_shorthand: bool,
     operation_type: OperationType,
-    tokens: &mut I,
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<Operation<'a>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+) -> Result<Operation<'a>, Error> {
     let (next_token, operation_name, variables) = match current_token {
         curly_bracket @ Some("{") => (curly_bracket, None, Vec::<Variable>::new()),
         Some(_) if query_shorthand => {
-            return Err(Error {
-                error: String::from("Operation name is not allowed in shorthand mode"),
-            })
+            return Err(Error::syntax(
+                "Operation name is not allowed in shorthand mode",
+                tokens.location(),
+            ))
         }
         Some(name) if is_valid_name(&name) => match tokens.next() {
             Some("(") => {
@@ -377,8 +474,9 @@ where
                 (tokens.next(), Some(name), variables)
             }
             curly_bracket @ Some("{") => (curly_bracket, Some(name), Vec::<Variable>::new()),
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            directive @ Some("@") => (directive, Some(name), Vec::<Variable>::new()),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         },
         Some("(") => {
             parser_state.hierarchy.push("(");
@@ -386,10 +484,13 @@ where
 
             (tokens.next(), None, variables)
         }
-        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
+        directive @ Some("@") => (directive, None, Vec::<Variable>::new()),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
     };
 
+    let (directives, next_token) = parse_directives(next_token, tokens, parser_state)?;
+
     match next_token {
         Some("{") => {
             parser_state.hierarchy.push("{");
@@ -400,25 +501,19 @@ where
                 name: operation_name,
                 fields: fields,
                 variables: variables,
+                directives: directives,
             });
         }
-        Some("}") => {
-            return Err(Error {
-                error: String::from("Unmatched parenteses"),
-            })
-        }
-        None => return Err(Error::new(String::from("Unexpected end of string"))),
-        Some(t) => return Err(Error::new(format!("invalid token {}", t))),
+        Some("}") => return Err(Error::unmatched_bracket("}", tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
+        Some(t) => return Err(Error::unexpected_token(t, tokens.location())),
     }
 }
 
-fn parse_variables<'a, I>(
-    tokens: &mut I,
+fn parse_variables<'a>(
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<Vec<Variable<'a>>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+) -> Result<Vec<Variable<'a>>, Error> {
     let mut variables = Vec::<Variable>::new();
 
     let mut next_token = tokens.next();
@@ -427,78 +522,95 @@ where
             Some(")") if is_matching_close_parenteses(")", parser_state.hierarchy.pop()) => {
                 return Ok(variables)
             }
-            Some(")") => {
-                return Err(Error {
-                    error: String::from("Unmatched parenteses"),
-                })
-            }
+            Some(")") => return Err(Error::unmatched_bracket(")", tokens.location())),
             Some("$") => {
                 let name = match tokens.next() {
                     Some(n) if is_valid_name(&n) => n,
-                    Some(n) => return Err(Error::new(format!("invalid variable name {}", n))),
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
+                    Some(n) => return Err(Error::unexpected_token(n, tokens.location())),
+                    None => return Err(Error::unexpected_eof(tokens.location())),
                 };
 
                 match tokens.next() {
                     Some(":") => {}
-                    Some(n) => return Err(Error::new(format!("invalid token {}", n))),
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
+                    Some(n) => return Err(Error::unexpected_token(n, tokens.location())),
+                    None => return Err(Error::unexpected_eof(tokens.location())),
                 };
 
-                let variable_type = match tokens.next() {
-                    Some(n) if is_valid_type(&n, tokens, parser_state) => n,
-                    Some(n) => return Err(Error::new(format!("invalid type {}", n))),
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
-                };
+                let (variable_type, token_after_type) =
+                    parse_type(tokens.next(), tokens, parser_state)?;
 
-                let default_value = match tokens.next() {
-                    Some("=") => match tokens.next() {
-                        Some(v) if is_valid_value(&v) => {
-                            next_token = tokens.next();
-                            Some(ParameterValue::Scalar(v))
-                        }
-                        Some(n) => return Err(Error::new(format!("invalid variable value {}", n))),
-                        None => return Err(Error::new(String::from("Unexpected end of string"))),
-                    },
-                    Some(n) => {
-                        next_token = Some(n);
-                        None
+                let (default_value, token_after_default) = match token_after_type {
+                    Some("=") => {
+                        // Default values must be constant
+                        let value = parse_value(tokens.next(), tokens, parser_state, false)?;
+                        (Some(value), tokens.next())
                     }
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
+                    Some(n) => (None, Some(n)),
+                    None => return Err(Error::unexpected_eof(tokens.location())),
                 };
 
+                let (directives, token_after_directives) =
+                    parse_directives(token_after_default, tokens, parser_state)?;
+                next_token = token_after_directives;
+
                 variables.push(Variable {
                     name: name,
                     r#type: variable_type,
                     default_value: default_value,
+                    directives: directives,
                 });
             }
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
     }
 }
 
-fn parse_fields<'a, I>(
-    tokens: &mut I,
+fn parse_fields<'a>(
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<Vec<Field<'a>>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+) -> Result<Vec<Field<'a>>, Error> {
     let mut fields = Vec::<Field>::new();
     let mut next_token = tokens.next();
 
     loop {
         let new_field = match next_token {
             Some("...") => match tokens.next() {
-                Some(fragment_name) if is_valid_name(&fragment_name) => {
+                Some("on") => {
+                    let type_condition = match tokens.next() {
+                        Some(t) if is_valid_name(&t) => t,
+                        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+                        None => return Err(Error::unexpected_eof(tokens.location())),
+                    };
+
+                    let inline_fragment = parse_inline_fragment(
+                        Some(type_condition),
+                        tokens.next(),
+                        tokens,
+                        parser_state,
+                    )?;
                     next_token = tokens.next();
 
-                    Field::new_fragment(fragment_name)
+                    inline_fragment
+                }
+                token @ Some("@") | token @ Some("{") => {
+                    let inline_fragment = parse_inline_fragment(None, token, tokens, parser_state)?;
+                    next_token = tokens.next();
+
+                    inline_fragment
+                }
+                Some(fragment_name) if is_valid_name(&fragment_name) => {
+                    let (directives, token_after_directives) =
+                        parse_directives(tokens.next(), tokens, parser_state)?;
+                    next_token = token_after_directives;
+
+                    Field::Fragment {
+                        name: fragment_name,
+                        directives: directives,
+                    }
                 }
-                Some(t) => return Err(Error::new(format!("invalid token {}", t))),
-                None => return Err(Error::new(String::from("Unexpected end of string"))),
+                Some(t) => return Err(Error::unexpected_token(t, tokens.location())),
+                None => return Err(Error::unexpected_eof(tokens.location())),
             },
             Some(candidate_name) if is_valid_name(&candidate_name) => {
                 next_token = tokens.next();
@@ -509,10 +621,10 @@ where
                             next_token = tokens.next();
                             (Some(candidate_name), n)
                         }
-                        Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-                        None => return Err(Error::new(String::from("Unexpected end of string"))),
+                        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+                        None => return Err(Error::unexpected_eof(tokens.location())),
                     },
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
+                    None => return Err(Error::unexpected_eof(tokens.location())),
                     _ => (None, candidate_name),
                 };
 
@@ -524,10 +636,14 @@ where
 
                         params
                     }
-                    None => return Err(Error::new(String::from("Unexpected end of string"))),
+                    None => return Err(Error::unexpected_eof(tokens.location())),
                     _ => Vec::<Parameter>::new(),
                 };
 
+                let (directives, token_after_directives) =
+                    parse_directives(next_token, tokens, parser_state)?;
+                next_token = token_after_directives;
+
                 let subfields = match next_token {
                     Some("{") => {
                         parser_state.hierarchy.push("{");
@@ -539,10 +655,16 @@ where
                     _ => Vec::<Field>::new(),
                 };
 
-                Field::new_field(alias, name, parameters, subfields)
+                Field::Field {
+                    alias: alias,
+                    name: name,
+                    parameters: parameters,
+                    directives: directives,
+                    fields: subfields,
+                }
             }
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
         fields.push(new_field);
@@ -551,69 +673,105 @@ where
             Some("}") if is_matching_close_parenteses("}", parser_state.hierarchy.pop()) => {
                 return Ok(fields)
             }
-            Some("}") => {
-                return Err(Error {
-                    error: String::from("Unmatched parenteses"),
-                })
-            }
+            Some("}") => return Err(Error::unmatched_bracket("}", tokens.location())),
             _ => {}
         };
     }
 }
 
-fn parse_parameters<'a, I>(
-    tokens: &mut I,
+/// Parses an inline fragment, starting from the token following the type condition
+fn parse_inline_fragment<'a>(
+    type_condition: Option<&'a str>,
+    next_token: Option<&'a str>,
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<Vec<Parameter<'a>>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+) -> Result<Field<'a>, Error> {
+    let (directives, next_token) = parse_directives(next_token, tokens, parser_state)?;
+
+    match next_token {
+        Some("{") => {
+            parser_state.hierarchy.push("{");
+            let fields = parse_fields(tokens, parser_state)?;
+
+            Ok(Field::InlineFragment {
+                type_condition: type_condition,
+                directives: directives,
+                fields: fields,
+            })
+        }
+        Some(s) => Err(Error::unexpected_token(s, tokens.location())),
+        None => Err(Error::unexpected_eof(tokens.location())),
+    }
+}
+
+/// Parses a (possibly empty) list of directives. `next_token` is the
+/// token following the element the directives are attached to; the
+/// first token after the last directive is returned along with them.
+fn parse_directives<'a>(
+    mut next_token: Option<&'a str>,
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<(Vec<Directive<'a>>, Option<&'a str>), Error> {
+    let mut directives = Vec::<Directive>::new();
+
+    while let Some("@") = next_token {
+        let name = match tokens.next() {
+            Some(n) if is_valid_name(&n) => n,
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
+        };
+
+        next_token = tokens.next();
+        let parameters = match next_token {
+            Some("(") => {
+                parser_state.hierarchy.push("(");
+                let params = parse_parameters(tokens, parser_state)?;
+                next_token = tokens.next();
+
+                params
+            }
+            _ => Vec::<Parameter>::new(),
+        };
+
+        directives.push(Directive {
+            name: name,
+            parameters: parameters,
+        });
+    }
+
+    Ok((directives, next_token))
+}
+
+fn parse_parameters<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<Vec<Parameter<'a>>, Error> {
     let mut parameters = Vec::<Parameter>::new();
 
     loop {
         let name = match tokens.next() {
             Some(")") if parameters.len() == 0 => {
-                return Err(Error::new(format!("list of parameters can't be empty")))
+                return Err(Error::syntax(
+                    "List of parameters can't be empty",
+                    tokens.location(),
+                ))
             }
             Some(")") if is_matching_close_parenteses(")", parser_state.hierarchy.pop()) => {
                 return Ok(parameters)
             }
-            Some(")") => {
-                return Err(Error {
-                    error: String::from("Unmatched parenteses"),
-                })
-            }
+            Some(")") => return Err(Error::unmatched_bracket(")", tokens.location())),
             Some(s) if is_valid_name(&s) => s,
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
         match tokens.next() {
             Some(":") => {}
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
-        let value = match tokens.next() {
-            Some("{") => {
-                parser_state.hierarchy.push("{");
-                parse_object(tokens, parser_state)?
-            }
-            Some("[") => {
-                parser_state.hierarchy.push("[");
-                parse_list(tokens, parser_state)?
-            }
-            Some("$") => match tokens.next() {
-                Some(variable_name) if is_valid_name(&variable_name) => {
-                    ParameterValue::Variable(variable_name)
-                }
-                Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-                None => return Err(Error::new(String::from("Unexpected end of string"))),
-            },
-            Some(s) if is_valid_value(&s) => ParameterValue::Scalar(s),
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
-        };
+        let value = parse_value(tokens.next(), tokens, parser_state, true)?;
 
         parameters.push(Parameter {
             name: name,
@@ -622,56 +780,80 @@ where
     }
 }
 
-fn parse_object<'a, I>(
-    tokens: &mut I,
+/// Parses a value, starting from its first token. Variables are
+/// not accepted when parsing constant values (e.g. default values)
+fn parse_value<'a>(
+    token: Option<&'a str>,
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<ParameterValue<'a>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+    accept_variables: bool,
+) -> Result<ParameterValue<'a>, Error> {
+    match token {
+        Some("{") => {
+            parser_state.hierarchy.push("{");
+            parse_object(tokens, parser_state, accept_variables)
+        }
+        Some("[") => {
+            parser_state.hierarchy.push("[");
+            parse_list(tokens, parser_state, accept_variables)
+        }
+        Some("$") if accept_variables => match tokens.next() {
+            Some(variable_name) if is_valid_name(&variable_name) => {
+                Ok(ParameterValue::Variable(variable_name))
+            }
+            Some(s) => Err(Error::unexpected_token(s, tokens.location())),
+            None => Err(Error::unexpected_eof(tokens.location())),
+        },
+        Some(s @ (")" | "]" | "}")) => Err(Error::unmatched_bracket(s, tokens.location())),
+        Some(s) => match parse_scalar_value(s) {
+            Some(value) => Ok(value),
+            None if s.starts_with(|c: char| c == '"' || c == '-' || c.is_ascii_digit()) => {
+                Err(Error::invalid_value(s, tokens.location()))
+            }
+            None => Err(Error::unexpected_token(s, tokens.location())),
+        },
+        None => Err(Error::unexpected_eof(tokens.location())),
+    }
+}
+
+fn parse_scalar_value<'a>(token: &'a str) -> Option<ParameterValue<'a>> {
+    match token {
+        "true" => Some(ParameterValue::Boolean(true)),
+        "false" => Some(ParameterValue::Boolean(false)),
+        "null" => Some(ParameterValue::Null),
+        t if is_valid_name(t) => Some(ParameterValue::Enum(t)),
+        t if t.starts_with('"') => string_value(t).map(|_| ParameterValue::String(t)),
+        t if is_valid_int(t) => Some(ParameterValue::Int(t)),
+        t if is_valid_float(t) => Some(ParameterValue::Float(t)),
+        _ => None,
+    }
+}
+
+fn parse_object<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+    accept_variables: bool,
+) -> Result<ParameterValue<'a>, Error> {
     let mut fields = Vec::<ParameterField>::new();
 
     loop {
         let name = match tokens.next() {
-            Some(s)
-                if (s == "}" || s == ")")
-                    && is_matching_close_parenteses(&s, parser_state.hierarchy.pop()) =>
-            {
+            Some("}") if is_matching_close_parenteses("}", parser_state.hierarchy.pop()) => {
                 return Ok(ParameterValue::Object(fields))
             }
-            Some(s) if s == "}" || s == ")" => {
-                return Err(Error {
-                    error: String::from("Unmatched parenteses"),
-                })
-            }
-            Some(s)
-                if s == "]" && is_matching_close_parenteses(&s, parser_state.hierarchy.pop()) =>
-            {
-                return Ok(ParameterValue::Nil)
-            }
-            Some(s) => s,
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some("}") => return Err(Error::unmatched_bracket("}", tokens.location())),
+            Some(s) if is_valid_name(&s) => s,
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
         match tokens.next() {
             Some(":") => {}
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
         };
 
-        let value = match tokens.next() {
-            Some("{") => {
-                parser_state.hierarchy.push("{");
-                parse_object(tokens, parser_state)?
-            }
-            Some("[") => {
-                parser_state.hierarchy.push("[");
-                parse_list(tokens, parser_state)?
-            }
-            Some(s) if is_valid_value(&s) => ParameterValue::Scalar(s),
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
-        };
+        let value = parse_value(tokens.next(), tokens, parser_state, accept_variables)?;
 
         fields.push(ParameterField {
             name: name,
@@ -680,38 +862,53 @@ where
     }
 }
 
-fn parse_list<'a, I>(
-    tokens: &mut I,
+fn parse_list<'a>(
+    tokens: &mut Tokenizer<'a>,
     parser_state: &mut ParserState<'a>,
-) -> Result<ParameterValue<'a>, Error>
-where
-    I: Iterator<Item = &'a str>,
-{
+    accept_variables: bool,
+) -> Result<ParameterValue<'a>, Error> {
     let mut objs = Vec::<ParameterValue>::new();
 
     loop {
         match tokens.next() {
-            Some("{") => {
-                parser_state.hierarchy.push("{");
-                objs.push(parse_object(tokens, parser_state)?);
-            }
             Some("]") if is_matching_close_parenteses("]", parser_state.hierarchy.pop()) => {
                 return Ok(ParameterValue::List(objs))
             }
-            Some(s) if is_valid_value(&s) => objs.push(ParameterValue::Scalar(s)),
-            Some(s) => return Err(Error::new(format!("invalid token {}", s))),
-            None => return Err(Error::new(String::from("Unexpected end of string"))),
+            Some("]") => return Err(Error::unmatched_bracket("]", tokens.location())),
+            token => objs.push(parse_value(token, tokens, parser_state, accept_variables)?),
         }
     }
 }
 
-fn is_valid_variable_type(string: &str) -> bool {
-    let mut chars = string.chars();
+/// Parses a type reference (e.g. `[ID!]!`), starting from its first token.
+/// The token following the type is returned along with it.
+fn parse_type<'a>(
+    token: Option<&'a str>,
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<(TypeRef<'a>, Option<&'a str>), Error> {
+    let type_ref = match token {
+        Some("[") => {
+            parser_state.hierarchy.push("[");
+            let (inner_type, next_token) = parse_type(tokens.next(), tokens, parser_state)?;
+
+            match next_token {
+                Some("]") if is_matching_close_parenteses("]", parser_state.hierarchy.pop()) => {
+                    TypeRef::List(Box::new(inner_type))
+                }
+                Some("]") => return Err(Error::unmatched_bracket("]", tokens.location())),
+                Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+                None => return Err(Error::unexpected_eof(tokens.location())),
+            }
+        }
+        Some(name) if is_valid_name(&name) => TypeRef::Named(name),
+        Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+        None => return Err(Error::unexpected_eof(tokens.location())),
+    };
 
-    match chars.next_back() {
-        Some(c) if c == '!' || c.is_alphabetic() => chars.all(|c| c.is_alphanumeric() || c == '_'),
-        Some(_) => false,
-        None => false,
+    match tokens.next() {
+        Some("!") => Ok((TypeRef::NonNull(Box::new(type_ref)), tokens.next())),
+        next_token => Ok((type_ref, next_token)),
     }
 }
 
@@ -719,27 +916,170 @@ fn is_valid_name(string: &str) -> bool {
     let mut chars = string.chars();
 
     return match chars.next() {
-        Some(c) if !c.is_alphabetic() => false,
-        Some(_) => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        Some(c) if !c.is_ascii_alphabetic() && c != '_' => false,
+        Some(_) => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
         None => false,
     };
 }
 
-fn is_valid_value(string: &str) -> bool {
-    let mut chars = string.chars();
+fn is_valid_int(string: &str) -> bool {
+    let digits = string.strip_prefix('-').unwrap_or(string);
 
-    return match chars.next() {
-        Some('"') => chars.last().unwrap_or(' ') == '"',
-        Some(c) if c.is_alphanumeric() => chars.all(|c| c.is_alphanumeric()),
-        _ => false,
+    match digits.as_bytes() {
+        [] => false,
+        [b'0', _, ..] => false,
+        d => d.iter().all(|b| b.is_ascii_digit()),
+    }
+}
+
+fn is_valid_float(string: &str) -> bool {
+    let (mantissa, exponent) = match string.find(['e', 'E']) {
+        Some(ix) => (&string[..ix], Some(&string[ix + 1..])),
+        None => (string, None),
+    };
+
+    let (integer_part, fractional_part) = match mantissa.find('.') {
+        Some(ix) => (&mantissa[..ix], Some(&mantissa[ix + 1..])),
+        None => (mantissa, None),
     };
+
+    let is_valid_fractional_part = match fractional_part {
+        Some(f) => f.len() > 0 && f.bytes().all(|b| b.is_ascii_digit()),
+        None => true,
+    };
+
+    let is_valid_exponent = match exponent {
+        Some(e) => {
+            let digits = e.strip_prefix(|c| c == '+' || c == '-').unwrap_or(e);
+            digits.len() > 0 && digits.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => true,
+    };
+
+    (fractional_part.is_some() || exponent.is_some())
+        && is_valid_int(integer_part)
+        && is_valid_fractional_part
+        && is_valid_exponent
 }
 
-fn is_valid_type<'a, I>(string: &str, _tokens: &mut I, _parser_state: &mut ParserState<'a>) -> bool
-where
-    I: Iterator<Item = &'a str>,
-{
-    is_valid_variable_type(string)
+/// Returns the value of a string token (quotes included), with
+/// escape sequences and block string indentation processed.
+/// Returns None if the token is not a valid string.
+pub fn string_value(token: &str) -> Option<String> {
+    if token.len() >= 6 && token.starts_with("\"\"\"") && token.ends_with("\"\"\"") {
+        return Some(block_string_value(&token[3..token.len() - 3]));
+    }
+
+    if token.len() < 2 || !token.starts_with('"') || !token.ends_with('"') {
+        return None;
+    }
+
+    let mut result = String::with_capacity(token.len());
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '"' => result.push('"'),
+                '\\' => result.push('\\'),
+                '/' => result.push('/'),
+                'b' => result.push('\u{0008}'),
+                'f' => result.push('\u{000C}'),
+                'n' => result.push('\n'),
+                'r' => result.push('\r'),
+                't' => result.push('\t'),
+                'u' => {
+                    let code_unit = parse_unicode_escape(&mut chars)?;
+
+                    // Characters outside the BMP are encoded as a surrogate pair
+                    let code_point = if (0xD800..0xDC00).contains(&code_unit) {
+                        match (chars.next(), chars.next()) {
+                            (Some('\\'), Some('u')) => {
+                                let low_surrogate = parse_unicode_escape(&mut chars)?;
+                                if !(0xDC00..0xE000).contains(&low_surrogate) {
+                                    return None;
+                                }
+                                0x10000 + ((code_unit - 0xD800) << 10) + (low_surrogate - 0xDC00)
+                            }
+                            _ => return None,
+                        }
+                    } else {
+                        code_unit
+                    };
+
+                    result.push(char::from_u32(code_point)?);
+                }
+                _ => return None,
+            },
+            '"' | '\n' | '\r' => return None,
+            c => result.push(c),
+        }
+    }
+
+    Some(result)
+}
+
+fn parse_unicode_escape(chars: &mut std::str::Chars) -> Option<u32> {
+    let hex = chars.take(4).collect::<String>();
+
+    // `from_str_radix` would accept a leading sign
+    if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        u32::from_str_radix(&hex, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Implements the BlockStringValue() algorithm from the specs:
+/// removes the common indentation and the leading and trailing blank lines
+fn block_string_value(raw_value: &str) -> String {
+    let unescaped = raw_value.replace("\\\"\"\"", "\"\"\"");
+    let lines = unescaped
+        .split("\r\n")
+        .flat_map(|l| l.split(['\n', '\r']))
+        .collect::<Vec<_>>();
+
+    let is_whitespace = |c: char| c == ' ' || c == '\t';
+    let common_indent = lines
+        .iter()
+        .skip(1)
+        .filter_map(|l| {
+            let indent = l.chars().take_while(|c| is_whitespace(*c)).count();
+            if indent < l.chars().count() {
+                Some(indent)
+            } else {
+                None
+            }
+        })
+        .min()
+        .unwrap_or(0);
+
+    let mut result_lines = lines
+        .iter()
+        .enumerate()
+        .map(|(ix, l)| {
+            if ix == 0 {
+                l.to_string()
+            } else {
+                l.chars().skip(common_indent).collect::<String>()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    while result_lines
+        .first()
+        .is_some_and(|l| l.chars().all(is_whitespace))
+    {
+        result_lines.remove(0);
+    }
+
+    while result_lines
+        .last()
+        .is_some_and(|l| l.chars().all(is_whitespace))
+    {
+        result_lines.pop();
+    }
+
+    result_lines.join("\n")
 }
 
 fn is_matching_close_parenteses(close: &str, open_option: Option<&str>) -> bool {
@@ -762,7 +1102,11 @@ impl<'a> Document<'a> {
             .nth(0)
         {
             Some(o) => o,
-            None => return Err(Error::new(format!("operationName ist gulen"))),
+            None => {
+                return Err(Error::UnknownOperation {
+                    name: operation_name.to_string(),
+                })
+            }
         };
 
         return Ok(Document {
@@ -770,6 +1114,31 @@ impl<'a> Document<'a> {
             fragment_definitions: self.fragment_definitions,
         });
     }
+
+    /// Returns the operation to execute, along with the fragment definitions.
+    /// The name is required when the document contains multiple operations
+    pub fn select_operation(
+        self,
+        operation_name: Option<&str>,
+    ) -> Result<(Operation<'a>, Vec<FragmentDefinition<'a>>), Error> {
+        let document = if self.operations.len() > 1 {
+            match operation_name {
+                Some(operation_name) => self.filter_operation(operation_name)?,
+                None => {
+                    return Err(Error::new(String::from(
+                        "Must provide operation name if query contains multiple operations",
+                    )))
+                }
+            }
+        } else {
+            self
+        };
+
+        match document.operations.into_iter().nth(0) {
+            Some(operation) => Ok((operation, document.fragment_definitions)),
+            None => Err(Error::new(String::from("Must provide an operation"))),
+        }
+    }
 }
 
 struct ParserState<'a> {
@@ -779,11 +1148,39 @@ struct ParserState<'a> {
 #[derive(Debug, Clone)]
 pub struct Variable<'a> {
     pub name: &'a str,
-    pub r#type: &'a str,
+    pub r#type: TypeRef<'a>,
     pub default_value: Option<ParameterValue<'a>>,
+    pub directives: Vec<Directive<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TypeRef<'a> {
+    Named(&'a str),
+    List(Box<TypeRef<'a>>),
+    NonNull(Box<TypeRef<'a>>),
 }
 
-#[derive(Debug)]
+impl<'a> TypeRef<'a> {
+    /// Returns the name of the type, without list and non null wrappers
+    pub fn get_name(&self) -> &'a str {
+        match self {
+            TypeRef::Named(name) => name,
+            TypeRef::List(t) | TypeRef::NonNull(t) => t.get_name(),
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for TypeRef<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TypeRef::Named(name) => write!(f, "{}", name),
+            TypeRef::List(t) => write!(f, "[{}]", t),
+            TypeRef::NonNull(t) => write!(f, "{}!", t),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Document<'a> {
     pub operations: Vec<Operation<'a>>,
     pub fragment_definitions: Vec<FragmentDefinition<'a>>,
@@ -795,17 +1192,25 @@ pub enum Field<'a> {
         alias: Option<&'a str>,
         name: &'a str,
         parameters: Vec<Parameter<'a>>,
+        directives: Vec<Directive<'a>>,
         fields: Vec<Field<'a>>,
     },
     Fragment {
         name: &'a str,
+        directives: Vec<Directive<'a>>,
+    },
+    InlineFragment {
+        type_condition: Option<&'a str>,
+        directives: Vec<Directive<'a>>,
+        fields: Vec<Field<'a>>,
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FragmentDefinition<'a> {
     pub name: &'a str,
     pub r#type: &'a str,
+    pub directives: Vec<Directive<'a>>,
     pub fields: Vec<Field<'a>>,
 }
 
@@ -815,11 +1220,12 @@ impl<'a> PartialEq for FragmentDefinition<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Operation<'a> {
     pub operation_type: OperationType,
     pub name: Option<&'a str>,
     pub variables: Vec<Variable<'a>>,
+    pub directives: Vec<Directive<'a>>,
     pub fields: Vec<Field<'a>>,
 }
 
@@ -845,6 +1251,7 @@ impl<'a> Operation<'a> {
             name: self.name.clone(),
             operation_type: self.operation_type,
             variables: residual_variables,
+            directives: self.directives.clone(),
             fields: merged_fields,
         };
 
@@ -856,13 +1263,9 @@ fn get_variables(fields: &[Field]) -> HashSet<String> {
     let mut hash = HashSet::new();
 
     for f in fields {
-        for p in f.get_parameters() {
-            match &p.value {
-                ParameterValue::Variable(v) => {
-                    hash.insert(v.to_string());
-                }
-                _ => {}
-            }
+        let directive_parameters = f.get_directives().iter().flat_map(|d| &d.parameters);
+        for p in f.get_parameters().iter().chain(directive_parameters) {
+            add_value_variables(&p.value, &mut hash);
         }
 
         hash.extend(get_variables(f.get_subfields()));
@@ -871,13 +1274,26 @@ fn get_variables(fields: &[Field]) -> HashSet<String> {
     hash
 }
 
+fn add_value_variables(value: &ParameterValue, variables: &mut HashSet<String>) {
+    match value {
+        ParameterValue::Variable(v) => {
+            variables.insert(v.to_string());
+        }
+        ParameterValue::List(l) => l.iter().for_each(|v| add_value_variables(v, variables)),
+        ParameterValue::Object(o) => o
+            .iter()
+            .for_each(|f| add_value_variables(&f.value, variables)),
+        _ => {}
+    }
+}
+
 impl<'a> Traversable<'a> for Operation<'a> {
     fn traverse(&self, path: &[String]) -> Option<(Vec<&Field<'a>>, &Field<'a>)> {
         if path.len() == 0 {
             None
         } else {
-            self.fields
-                .iter()
+            flatten_inline_fragments(&self.fields)
+                .into_iter()
                 .filter(|f| path[0] == f.get_alias())
                 .map(|f| f.traverse(&path[1..]))
                 .filter(|o| o.is_some())
@@ -891,11 +1307,18 @@ impl<'a> Traversable<'a> for Field<'a> {
     fn traverse(&self, path: &[String]) -> Option<(Vec<&Field<'a>>, &Field<'a>)> {
         if path.len() == 0 {
             Some((vec![], self))
+        } else if list_index(&path[0]).is_some() {
+            // The elements of a list have the selection of the list field
+            self.traverse(&path[1..])
         } else {
             match self {
                 Field::Field {
                     fields: subfields, ..
-                } => match subfields.iter().filter(|s| path[0] == s.get_alias()).nth(0) {
+                } => match flatten_inline_fragments(subfields)
+                    .into_iter()
+                    .filter(|s| path[0] == s.get_alias())
+                    .nth(0)
+                {
                     Some(f) => match f.traverse(&path[1..]) {
                         Some((mut traversed, field)) => {
                             traversed.insert(0, self);
@@ -911,6 +1334,21 @@ impl<'a> Traversable<'a> for Field<'a> {
     }
 }
 
+/// Returns the fields of a selection set, including
+/// the ones nested in its inline fragments
+pub fn flatten_inline_fragments<'a, 'b>(fields: &'b [Field<'a>]) -> Vec<&'b Field<'a>> {
+    let mut result = Vec::with_capacity(fields.len());
+
+    for field in fields {
+        match field {
+            Field::InlineFragment { fields, .. } => result.extend(flatten_inline_fragments(fields)),
+            f => result.push(f),
+        }
+    }
+
+    result
+}
+
 fn merge_subfields(mut fields: Vec<Field>) -> Vec<Field> {
     let mut new_subfields = Vec::new();
 
@@ -943,6 +1381,7 @@ impl<'a> Field<'a> {
             alias: alias,
             name: name,
             parameters: parameters,
+            directives: vec![],
             fields: fields,
         }
     }
@@ -952,6 +1391,18 @@ impl<'a> Field<'a> {
             (Field::Field { name, .. }, &Field::Field { name: name2, .. }) => {
                 name == name2 && self.has_same_parameters(field)
             }
+            (
+                Field::InlineFragment {
+                    type_condition,
+                    directives,
+                    ..
+                },
+                &Field::InlineFragment {
+                    type_condition: type_condition2,
+                    directives: directives2,
+                    ..
+                },
+            ) => type_condition == type_condition2 && directives == directives2,
             _ => false,
         }
     }
@@ -984,15 +1435,23 @@ impl<'a> Field<'a> {
 
                     *fields = merge_subfields(subfields);
                 }
+                (
+                    Field::InlineFragment { ref mut fields, .. },
+                    Field::InlineFragment {
+                        fields: fields2, ..
+                    },
+                ) => {
+                    let mut subfields = Vec::new();
+                    subfields.extend(fields.clone());
+                    subfields.extend(fields2.clone());
+
+                    *fields = merge_subfields(subfields);
+                }
                 _ => {}
             }
         }
     }
 
-    pub fn new_fragment(name: &'a str) -> Field<'a> {
-        Field::Fragment { name: name }
-    }
-
     pub fn has_parameters(&self) -> bool {
         match self {
             Field::Field { parameters, .. } => parameters.len() > 0,
@@ -1056,9 +1515,18 @@ impl<'a> Field<'a> {
         }
     }
 
+    pub fn get_directives(&self) -> &[Directive<'a>] {
+        match self {
+            Field::Field { directives, .. } => &directives,
+            Field::Fragment { directives, .. } => &directives,
+            Field::InlineFragment { directives, .. } => &directives,
+        }
+    }
+
     pub fn get_subfields(&self) -> &[Field<'a>] {
         match self {
             Field::Field { fields, .. } => &fields,
+            Field::InlineFragment { fields, .. } => &fields,
             _ => EMPTY_FIELD_LIST,
         }
     }
@@ -1073,15 +1541,61 @@ pub struct Parameter<'a> {
     pub value: ParameterValue<'a>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Directive<'a> {
+    pub name: &'a str,
+    pub parameters: Vec<Parameter<'a>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ParameterValue<'a> {
-    Nil,
-    Scalar(&'a str),
+    Null,
+    Int(&'a str),
+    Float(&'a str),
+    /// The string token, including quotes. See `string_value`
+    String(&'a str),
+    Boolean(bool),
+    Enum(&'a str),
     Object(Vec<ParameterField<'a>>),
     List(Vec<ParameterValue<'a>>),
     Variable(&'a str),
 }
 
+impl<'a> ParameterValue<'a> {
+    /// Converts the value to its JSON representation. Enums become
+    /// strings, missing variables become null
+    pub fn to_json(&self, variables: &Map<String, Value>) -> Value {
+        match self {
+            ParameterValue::Null => Value::Null,
+            ParameterValue::Boolean(b) => Value::Bool(*b),
+            ParameterValue::Int(s) | ParameterValue::Float(s) => match serde_json::from_str(s) {
+                Ok(n) => Value::Number(n),
+                Err(_) => Value::String(s.to_string()),
+            },
+            ParameterValue::String(s) => Value::String(string_value(s).unwrap_or_default()),
+            ParameterValue::Enum(e) => Value::String(e.to_string()),
+            ParameterValue::Variable(v) => variables.get(*v).cloned().unwrap_or(Value::Null),
+            ParameterValue::List(l) => {
+                Value::Array(l.iter().map(|v| v.to_json(variables)).collect())
+            }
+            ParameterValue::Object(o) => Value::Object(
+                o.iter()
+                    .map(|f| (f.name.to_string(), f.value.to_json(variables)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'a> fmt::Display for ParameterValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = String::new();
+        serialize_parameter_value(self, &mut s);
+
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum OperationType {
     Query,
@@ -1091,26 +1605,142 @@ pub enum OperationType {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ParameterField<'a> {
-    name: &'a str,
-    value: ParameterValue<'a>,
+    pub name: &'a str,
+    pub value: ParameterValue<'a>,
 }
 
-#[derive(Debug)]
-pub struct Error {
-    error: String,
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnexpectedToken {
+        token: String,
+        location: Location,
+    },
+    UnexpectedEof {
+        location: Location,
+    },
+    UnmatchedBracket {
+        bracket: String,
+        location: Location,
+    },
+    InvalidValue {
+        value: String,
+        location: Location,
+    },
+    Syntax {
+        message: String,
+        location: Location,
+    },
+    UnknownFragment {
+        name: String,
+    },
+    RecursiveFragment {
+        name: String,
+    },
+    UnknownOperation {
+        name: String,
+    },
+    Validation(String),
+    /// The upstream server couldn't be reached or sent an invalid response
+    Upstream(String),
+    /// Error of the request, with a code clients can check in the `extensions` of the error
+    Request {
+        message: String,
+        code: &'static str,
+    },
+    Other(String),
 }
 
 impl Error {
     pub fn new(error: String) -> Error {
-        Error { error: error }
+        Error::Other(error)
+    }
+
+    fn unexpected_token(token: &str, location: Location) -> Error {
+        Error::UnexpectedToken {
+            token: token.to_string(),
+            location: location,
+        }
+    }
+
+    fn unexpected_eof(location: Location) -> Error {
+        Error::UnexpectedEof { location: location }
+    }
+
+    fn unmatched_bracket(bracket: &str, location: Location) -> Error {
+        Error::UnmatchedBracket {
+            bracket: bracket.to_string(),
+            location: location,
+        }
+    }
+
+    fn invalid_value(value: &str, location: Location) -> Error {
+        Error::InvalidValue {
+            value: value.to_string(),
+            location: location,
+        }
+    }
+
+    fn syntax(message: &str, location: Location) -> Error {
+        Error::Syntax {
+            message: message.to_string(),
+            location: location,
+        }
+    }
+
+    pub fn locations(&self) -> Vec<Location> {
+        match self {
+            Error::UnexpectedToken { location, .. }
+            | Error::UnexpectedEof { location }
+            | Error::UnmatchedBracket { location, .. }
+            | Error::InvalidValue { location, .. }
+            | Error::Syntax { location, .. } => vec![*location],
+            _ => vec![],
+        }
+    }
+
+    /// Returns the error in the format of the GraphQL specs:
+    /// `{"message": "...", "locations": [{"line": 1, "column": 2}]}`
+    pub fn to_json(&self) -> Value {
+        let locations = self.locations();
+
+        if let Error::Request { message, code } = self {
+            json!({ "message": message, "extensions": { "code": code } })
+        } else if locations.len() == 0 {
+            json!({ "message": self.to_string() })
+        } else {
+            json!({ "message": self.to_string(), "locations": locations })
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnexpectedToken { token, .. } => {
+                write!(f, "Syntax Error: Unexpected \"{}\"", token)
+            }
+            Error::UnexpectedEof { .. } => write!(f, "Syntax Error: Unexpected end of document"),
+            Error::UnmatchedBracket { bracket, .. } => {
+                write!(f, "Syntax Error: Unmatched \"{}\"", bracket)
+            }
+            Error::InvalidValue { value, .. } => write!(f, "Syntax Error: Invalid value {}", value),
+            Error::Syntax { message, .. } => write!(f, "Syntax Error: {}", message),
+            Error::UnknownFragment { name } => write!(f, "Unknown fragment \"{}\"", name),
+            Error::RecursiveFragment { name } => {
+                write!(f, "Cannot spread fragment \"{}\" within itself", name)
+            }
+            Error::UnknownOperation { name } => write!(f, "Unknown operation named \"{}\"", name),
+            Error::Validation(message)
+            | Error::Upstream(message)
+            | Error::Request { message, .. }
+            | Error::Other(message) => write!(f, "{}", message),
+        }
     }
 }
 
 impl From<serde_json::error::Error> for Error {
     fn from(_err: serde_json::error::Error) -> Error {
-        Error {
-            error: String::from("deserialization error"),
-        }
+        Error::Other(String::from("deserialization error"))
     }
 }
 
@@ -1245,7 +1875,7 @@ mod tests {
             "p1",
             parsed_query.operations[0].fields[0].get_parameters()[0].name
         );
-        matches!(parsed_query.operations[0].fields[0].get_parameters()[0].value, ParameterValue::Scalar(p1) if p1 == "10");
+        matches!(parsed_query.operations[0].fields[0].get_parameters()[0].value, ParameterValue::Int(p1) if p1 == "10");
 
         assert_eq!("field1", parsed_query.operations[0].fields[1].get_name());
         assert_eq!("alias2", parsed_query.operations[0].fields[1].get_alias());
@@ -1258,7 +1888,7 @@ mod tests {
             "sub1",
             parsed_query.operations[0].fields[0].get_subfields()[0].get_name()
         );
-        matches!(parsed_query.operations[0].fields[0].get_subfields()[0].get_parameters()[0].value, ParameterValue::Scalar(p1) if p1 == "\"asd\"");
+        matches!(parsed_query.operations[0].fields[0].get_subfields()[0].get_parameters()[0].value, ParameterValue::String(p1) if p1 == "\"asd\"");
 
         assert_eq!(
             "subalias1",
@@ -1284,7 +1914,7 @@ mod tests {
         assert_eq!(1, parsed_query.fragment_definitions.len());
 
         assert_eq!("users", parsed_query.operations[0].fields[0].get_name());
-        matches!(parsed_query.operations[0].fields[0].get_subfields()[0], Field::Fragment { name } if name == "userFragment");
+        matches!(parsed_query.operations[0].fields[0].get_subfields()[0], Field::Fragment { name, .. } if name == "userFragment");
 
         assert_eq!(
             "surname",
@@ -1295,7 +1925,7 @@ mod tests {
             parsed_query.operations[0].fields[0].get_subfields()[2].get_name()
         );
 
-        matches!(parsed_query.operations[0].fields[0].get_subfields()[2].get_subfields()[0], Field::Fragment { name } if name == "userFragment");
+        matches!(parsed_query.operations[0].fields[0].get_subfields()[2].get_subfields()[0], Field::Fragment { name, .. } if name == "userFragment");
         assert_eq!(
             "surname",
             parsed_query.operations[0].fields[0].get_subfields()[2].get_subfields()[1].get_name()
@@ -1307,7 +1937,7 @@ mod tests {
         let query = "{field1(p:\"as              d              \")}";
         let parsed_query = parse_query(query).unwrap();
 
-        matches!(parsed_query.operations[0].fields[0].get_parameters()[0].value, ParameterValue::Scalar(p1) if p1 == "as              d              ");
+        matches!(parsed_query.operations[0].fields[0].get_parameters()[0].value, ParameterValue::String(p1) if p1 == "\"as              d              \"");
     }
 
     #[test]
@@ -1382,6 +2012,306 @@ mod tests {
         assert_eq!(query, serialized_query);
     }
 
+    #[test]
+    fn parser_can_parse_directives() {
+        let query = "query Q($flag: Boolean = true) @op { field1 @include(if: $flag) { sub @skip(if: false) @other } ...frag @include(if: $flag) } fragment frag on User @fragdir { id }";
+        let parsed_query = parse_query(query).unwrap();
+        let operation = &parsed_query.operations[0];
+
+        assert_eq!("op", operation.directives[0].name);
+        assert_eq!(0, operation.variables[0].directives.len());
+
+        let field1 = &operation.fields[0];
+        assert_eq!(1, field1.get_directives().len());
+        assert_eq!("include", field1.get_directives()[0].name);
+        assert_eq!("if", field1.get_directives()[0].parameters[0].name);
+        assert_eq!(
+            ParameterValue::Variable("flag"),
+            field1.get_directives()[0].parameters[0].value
+        );
+
+        let sub = &field1.get_subfields()[0];
+        assert_eq!("sub", sub.get_name());
+        assert_eq!(2, sub.get_directives().len());
+        assert_eq!("skip", sub.get_directives()[0].name);
+        assert_eq!("other", sub.get_directives()[1].name);
+        assert_eq!(0, sub.get_directives()[1].parameters.len());
+
+        assert!(
+            matches!(&operation.fields[1], Field::Fragment { name, directives } if *name == "frag" && directives[0].name == "include")
+        );
+        assert_eq!(
+            "fragdir",
+            parsed_query.fragment_definitions[0].directives[0].name
+        );
+    }
+
+    #[test]
+    fn parsed_query_with_directives_can_be_serialized() {
+        let query = "query($flag:Boolean=true)@op{field1@include(if:$flag){sub@skip(if:false)@other} ...frag@include(if:$flag)}fragment frag on User@fragdir{id}";
+        let parsed_query = parse_query(query).unwrap();
+        let serialized_query = serialize_document(&parsed_query);
+
+        assert_eq!(query, serialized_query);
+    }
+
+    #[test]
+    fn parser_can_parse_inline_fragments() {
+        let query = "{ search { __typename ... on User { name } ... @include(if: true) { id } ... { other } } }";
+        let parsed_query = parse_query(query).unwrap();
+        let subfields = parsed_query.operations[0].fields[0].get_subfields();
+
+        assert_eq!(4, subfields.len());
+        assert_eq!("__typename", subfields[0].get_name());
+        assert!(
+            matches!(&subfields[1], Field::InlineFragment { type_condition: Some("User"), directives, fields } if directives.len() == 0 && fields[0].get_name() == "name")
+        );
+        assert!(
+            matches!(&subfields[2], Field::InlineFragment { type_condition: None, directives, fields } if directives[0].name == "include" && fields[0].get_name() == "id")
+        );
+        assert!(
+            matches!(&subfields[3], Field::InlineFragment { type_condition: None, directives, fields } if directives.len() == 0 && fields[0].get_name() == "other")
+        );
+    }
+
+    #[test]
+    fn parsed_query_with_inline_fragments_can_be_serialized() {
+        let query = "{search{__typename ... on User{name} ...@include(if:true){id}}}";
+        let parsed_query = parse_query(query).unwrap();
+        let serialized_query = serialize_document(&parsed_query);
+
+        assert_eq!(query, serialized_query);
+    }
+
+    #[test]
+    fn expanded_operation_preserves_type_conditions() {
+        let query = "{ search { ... on User { ...frag } ... { other } ...frag @other } } fragment frag on User { id }";
+        let parsed_query = parse_query(query).unwrap();
+        let expanded_operation = expand_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "{search{... on User{id} other ...@other{id}}}",
+            serialize_operation(&expanded_operation)
+        );
+    }
+
+    #[test]
+    fn inline_fragments_with_same_type_condition_are_merged() {
+        let query = "{ search { ... on User { id } ... on Company { id } } search { ... on User { name } } }";
+        let parsed_query = parse_query(query).unwrap();
+        let operation = parsed_query.operations[0].deduplicate_fields().unwrap();
+
+        let subfields = operation.fields[0].get_subfields();
+        assert_eq!(2, subfields.len());
+
+        let user_fragment = subfields
+            .iter()
+            .filter(|f| {
+                matches!(
+                    f,
+                    Field::InlineFragment {
+                        type_condition: Some("User"),
+                        ..
+                    }
+                )
+            })
+            .nth(0)
+            .unwrap();
+        assert_eq!(2, user_fragment.get_subfields().len());
+    }
+
+    #[test]
+    fn parser_can_parse_values() {
+        let query = "{ field(a: -1, b: 3.14, c: 1e10, d: null, e: ENUM_VALUE, f: \"\\u00e9\\n\", g: [], h: { x: [1, $v] }) }";
+        let parsed_query = parse_query(query).unwrap();
+        let parameters = parsed_query.operations[0].fields[0].get_parameters();
+
+        assert_eq!(ParameterValue::Int("-1"), parameters[0].value);
+        assert_eq!(ParameterValue::Float("3.14"), parameters[1].value);
+        assert_eq!(ParameterValue::Float("1e10"), parameters[2].value);
+        assert_eq!(ParameterValue::Null, parameters[3].value);
+        assert_eq!(ParameterValue::Enum("ENUM_VALUE"), parameters[4].value);
+        assert!(
+            matches!(parameters[5].value, ParameterValue::String(s) if string_value(s).unwrap() == "\u{e9}\n")
+        );
+        assert_eq!(ParameterValue::List(vec![]), parameters[6].value);
+        assert_eq!(
+            ParameterValue::Object(vec![ParameterField {
+                name: "x",
+                value: ParameterValue::List(vec![
+                    ParameterValue::Int("1"),
+                    ParameterValue::Variable("v")
+                ])
+            }]),
+            parameters[7].value
+        );
+    }
+
+    #[test]
+    fn parser_rejects_invalid_values() {
+        assert!(parse_query("{ field(a: 01) }").is_err());
+        assert!(parse_query("{ field(a: 1.) }").is_err());
+        assert!(parse_query("{ field(a: 1e) }").is_err());
+        assert!(parse_query("{ field(a: \"\\x\") }").is_err());
+        assert!(parse_query("{ f(a: \"\\uD800\\u0041\") }").is_err());
+        assert!(parse_query("{ f(a: \"\\u+041\") }").is_err());
+        assert!(parse_query("{ f(a: \"\\uD83D\\uDE00\") }").is_ok());
+        assert!(parse_query("query($v: Int = $other) { field(a: $v) }").is_err());
+    }
+
+    #[test]
+    fn parser_rejects_non_ascii_names() {
+        assert!(parse_query("{ café }").is_err());
+        assert!(parse_query("{ éclair }").is_err());
+        assert!(parse_query("{ field(ñ: 1) }").is_err());
+        assert!(parse_query("{ _field2 }").is_ok());
+    }
+
+    #[test]
+    fn parser_can_parse_block_strings() {
+        let query = "{ field(a: \"\"\"\n    Hello,\n      World!\n\n    \\\"\"\" \"\"\") }";
+        let parsed_query = parse_query(query).unwrap();
+
+        match parsed_query.operations[0].fields[0].get_parameters()[0].value {
+            ParameterValue::String(s) => {
+                assert_eq!("Hello,\n  World!\n\n\"\"\" ", string_value(s).unwrap())
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parser_can_parse_variable_types() {
+        let query =
+            "query($ids: [ID!]!, $matrix: [[Int]], $name: String = \"x\") { field(ids: $ids) }";
+        let parsed_query = parse_query(query).unwrap();
+        let variables = &parsed_query.operations[0].variables;
+
+        assert_eq!(
+            TypeRef::NonNull(Box::new(TypeRef::List(Box::new(TypeRef::NonNull(
+                Box::new(TypeRef::Named("ID"))
+            ))))),
+            variables[0].r#type
+        );
+        assert_eq!("[[Int]]", variables[1].r#type.to_string());
+        assert_eq!("Int", variables[1].r#type.get_name());
+        assert_eq!(
+            Some(ParameterValue::String("\"x\"")),
+            variables[2].default_value
+        );
+    }
+
+    #[test]
+    fn parsed_query_with_values_can_be_serialized() {
+        let query = "query($ids:[ID!]!=[\"1\"] $f:Float=-1.5e3){field(a:null b:ENUM c:{x:[1 2] y:true} ids:$ids)}";
+        let parsed_query = parse_query(query).unwrap();
+        let serialized_query = serialize_document(&parsed_query);
+
+        assert_eq!(query, serialized_query);
+    }
+
+    #[test]
+    fn parser_errors_include_location() {
+        let error = parse_query("{\n  field(a: 1 b) }").unwrap_err();
+        assert_eq!(
+            Error::UnexpectedToken {
+                token: String::from(")"),
+                location: Location {
+                    line: 2,
+                    column: 15,
+                    offset: 16
+                }
+            },
+            error
+        );
+        assert_eq!(
+            json!({"message": "Syntax Error: Unexpected \")\"", "locations": [{"line": 2, "column": 15}]}),
+            error.to_json()
+        );
+
+        assert!(matches!(
+            parse_query("{ field { sub }"),
+            Err(Error::UnexpectedEof {
+                location: Location {
+                    line: 1,
+                    column: 16,
+                    ..
+                }
+            })
+        ));
+        assert!(matches!(
+            parse_query("{ field(a: [1 :] }"),
+            Err(Error::UnexpectedToken {
+                location: Location {
+                    line: 1,
+                    column: 15,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_query("{ field(a: 01) }"),
+            Err(Error::InvalidValue {
+                location: Location {
+                    line: 1,
+                    column: 12,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_query("{ field(a: [1)) }"),
+            Err(Error::UnmatchedBracket {
+                location: Location {
+                    line: 1,
+                    column: 14,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn expanding_recursive_fragments_returns_error() {
+        let query = "{ ...frag } fragment frag on Query { field { ...frag } }";
+        let parsed_query = parse_query(query).unwrap();
+        let result = expand_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+        );
+
+        assert_eq!(
+            Err(Error::RecursiveFragment {
+                name: String::from("frag")
+            }),
+            result.map(|_| ())
+        );
+    }
+
+    #[test]
+    fn expanding_unknown_fragments_returns_error() {
+        let query = "{ field { ...missing } } fragment frag on Query { id }";
+        let parsed_query = parse_query(query).unwrap();
+        let result = expand_operation(
+            parsed_query.operations.into_iter().nth(0).unwrap(),
+            parsed_query.fragment_definitions,
+        );
+
+        assert_eq!(
+            Err(Error::UnknownFragment {
+                name: String::from("missing")
+            }),
+            result.map(|_| ())
+        );
+    }
+
     #[test]
     fn fields_can_be_merged() {
         let mut field1 = Field::new_field(
@@ -1406,6 +2336,7 @@ mod tests {
                 name,
                 parameters,
                 fields,
+                ..
             } => (alias, name, parameters, fields),
             _ => panic!("invalid field"),
         };
@@ -1482,6 +2413,7 @@ mod tests {
                 name,
                 parameters,
                 fields,
+                ..
             } => (alias, name, parameters, fields),
             _ => panic!("invalid field"),
         };
@@ -1556,7 +2488,7 @@ mod tests {
                     "subfield",
                     vec![Parameter {
                         name: "p1",
-                        value: ParameterValue::Scalar("12"),
+                        value: ParameterValue::Int("12"),
                     }],
                     vec![
                         Field::new_field(None, "subsubfield", vec![], vec![]),
@@ -1582,7 +2514,7 @@ mod tests {
                     "subfield",
                     vec![Parameter {
                         name: "p1",
-                        value: ParameterValue::Scalar("22"),
+                        value: ParameterValue::Int("22"),
                     }],
                     vec![
                         Field::new_field(None, "subsubfield3", vec![], vec![]),
@@ -1606,6 +2538,7 @@ mod tests {
                 name,
                 parameters,
                 fields,
+                ..
             } => (alias, name, parameters, fields),
             _ => panic!("invalid field"),
         };
@@ -1619,7 +2552,7 @@ mod tests {
             .iter()
             .filter(|s| {
                 s.get_name() == "subfield"
-                    && if let ParameterValue::Scalar(v) =
+                    && if let ParameterValue::Int(v) =
                         &s.get_parameters().iter().nth(0).unwrap().value
                     {
                         v == &"12"
@@ -1633,7 +2566,7 @@ mod tests {
             .iter()
             .filter(|s| {
                 s.get_name() == "subfield"
-                    && if let ParameterValue::Scalar(v) =
+                    && if let ParameterValue::Int(v) =
                         &s.get_parameters().iter().nth(0).unwrap().value
                     {
                         v == &"22"
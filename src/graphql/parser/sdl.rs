@@ -0,0 +1,520 @@
+use super::tokenizer::Tokenizer;
+use super::{
+    is_valid_name, parse_directives, parse_type, parse_value, Directive, Error, OperationType,
+    ParserState,
+};
+use crate::graphql::schema::{
+    DirectiveDefinition, FieldDefinition, InputValueDefinition, Schema, SchemaDirective,
+    TypeDefinition, TypeKind,
+};
+use serde_json::{Map, Value};
+
+type RootOperationTypes = Vec<(OperationType, String)>;
+
+/// Parses a schema written in SDL. Descriptions are ignored.
+pub fn parse_schema(sdl: &str) -> Result<Schema, Error> {
+    let mut type_definitions = Vec::new();
+    let mut type_extensions = Vec::new();
+    let mut directive_definitions = Vec::new();
+    let mut root_operation_types = Vec::new();
+    let mut parser_state = ParserState {
+        hierarchy: Vec::<&str>::new(),
+    };
+    let mut tokens = Tokenizer::new(sdl);
+
+    let mut next_token = tokens.next();
+    loop {
+        let (is_extension, token) = match skip_description(next_token, &mut tokens) {
+            Some("extend") => (true, tokens.next()),
+            t => (false, t),
+        };
+
+        next_token = match token {
+            Some("schema") => {
+                let (operation_types, next_token) =
+                    parse_schema_definition(&mut tokens, &mut parser_state)?;
+                root_operation_types.extend(operation_types);
+
+                next_token
+            }
+            Some("directive") if !is_extension => {
+                let (directive_definition, next_token) =
+                    parse_directive_definition(&mut tokens, &mut parser_state)?;
+                directive_definitions.push(directive_definition);
+
+                next_token
+            }
+            Some(kind @ ("scalar" | "type" | "interface" | "union" | "enum" | "input")) => {
+                let (type_definition, next_token) =
+                    parse_type_definition(kind, &mut tokens, &mut parser_state)?;
+
+                if is_extension {
+                    type_extensions.push(type_definition);
+                } else {
+                    type_definitions.push(type_definition);
+                }
+
+                next_token
+            }
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None if !is_extension => break,
+            None => return Err(Error::unexpected_eof(tokens.location())),
+        };
+    }
+
+    Schema::new(
+        type_definitions,
+        type_extensions,
+        directive_definitions,
+        root_operation_types,
+    )
+}
+
+/// Parses a constant value, such as the default values returned by an introspection query
+pub fn parse_const_value(value: &str) -> Result<Value, Error> {
+    let mut parser_state = ParserState {
+        hierarchy: Vec::<&str>::new(),
+    };
+    let mut tokens = Tokenizer::new(value);
+
+    let result = parse_value(tokens.next(), &mut tokens, &mut parser_state, false)?;
+
+    match tokens.next() {
+        Some(s) => Err(Error::unexpected_token(s, tokens.location())),
+        None => Ok(result.to_json(&Map::new())),
+    }
+}
+
+/// Descriptions are strings preceding definitions, fields, arguments and enum values
+fn skip_description<'a>(token: Option<&'a str>, tokens: &mut Tokenizer<'a>) -> Option<&'a str> {
+    match token {
+        Some(s) if s.starts_with('"') => tokens.next(),
+        t => t,
+    }
+}
+
+fn parse_name<'a>(token: Option<&'a str>, tokens: &Tokenizer<'a>) -> Result<&'a str, Error> {
+    match token {
+        Some(name) if is_valid_name(name) => Ok(name),
+        Some(s) => Err(Error::unexpected_token(s, tokens.location())),
+        None => Err(Error::unexpected_eof(tokens.location())),
+    }
+}
+
+fn expect_token<'a>(
+    expected: &str,
+    token: Option<&'a str>,
+    tokens: &Tokenizer<'a>,
+) -> Result<(), Error> {
+    match token {
+        Some(s) if s == expected => Ok(()),
+        Some(s) => Err(Error::unexpected_token(s, tokens.location())),
+        None => Err(Error::unexpected_eof(tokens.location())),
+    }
+}
+
+fn parse_schema_definition<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<(RootOperationTypes, Option<&'a str>), Error> {
+    let (_, next_token) = parse_directives(tokens.next(), tokens, parser_state)?;
+    let mut operation_types = Vec::new();
+
+    // Schema extensions may only add directives
+    if next_token != Some("{") {
+        return Ok((operation_types, next_token));
+    }
+
+    loop {
+        let operation_type = match tokens.next() {
+            Some("}") => return Ok((operation_types, tokens.next())),
+            Some("query") => OperationType::Query,
+            Some("mutation") => OperationType::Mutation,
+            Some("subscription") => OperationType::Subscription,
+            Some(s) => return Err(Error::unexpected_token(s, tokens.location())),
+            None => return Err(Error::unexpected_eof(tokens.location())),
+        };
+
+        expect_token(":", tokens.next(), tokens)?;
+        let type_name = parse_name(tokens.next(), tokens)?;
+
+        operation_types.push((operation_type, type_name.to_string()));
+    }
+}
+
+fn parse_directive_definition<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<(DirectiveDefinition, Option<&'a str>), Error> {
+    expect_token("@", tokens.next(), tokens)?;
+    let name = parse_name(tokens.next(), tokens)?;
+
+    let mut next_token = tokens.next();
+    let arguments = match next_token {
+        Some("(") => {
+            let arguments = parse_input_value_definitions(")", tokens, parser_state)?;
+            next_token = tokens.next();
+
+            arguments
+        }
+        _ => vec![],
+    };
+
+    let repeatable = next_token == Some("repeatable");
+    if repeatable {
+        next_token = tokens.next();
+    }
+
+    expect_token("on", next_token, tokens)?;
+
+    let mut locations = Vec::new();
+    next_token = match tokens.next() {
+        Some("|") => tokens.next(),
+        t => t,
+    };
+
+    loop {
+        locations.push(parse_name(next_token, tokens)?.to_string());
+
+        match tokens.next() {
+            Some("|") => next_token = tokens.next(),
+            t => {
+                let directive_definition = DirectiveDefinition {
+                    name: name.to_string(),
+                    arguments: arguments,
+                    repeatable: repeatable,
+                    locations: locations,
+                };
+
+                return Ok((directive_definition, t));
+            }
+        }
+    }
+}
+
+/// Parses the definition (or the extension) of a type. The token
+/// following the definition is returned along with it.
+fn parse_type_definition<'a>(
+    kind: &str,
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<(TypeDefinition, Option<&'a str>), Error> {
+    let type_kind = match kind {
+        "scalar" => TypeKind::Scalar,
+        "type" => TypeKind::Object,
+        "interface" => TypeKind::Interface,
+        "union" => TypeKind::Union,
+        "enum" => TypeKind::Enum,
+        _ => TypeKind::InputObject,
+    };
+
+    let name = parse_name(tokens.next(), tokens)?;
+    let mut type_definition = TypeDefinition::new(name.to_string(), type_kind);
+    let mut next_token = tokens.next();
+
+    if (type_kind == TypeKind::Object || type_kind == TypeKind::Interface)
+        && next_token == Some("implements")
+    {
+        next_token = match tokens.next() {
+            Some("&") => tokens.next(),
+            t => t,
+        };
+
+        loop {
+            let interface = parse_name(next_token, tokens)?;
+            type_definition.interfaces.push(interface.to_string());
+
+            next_token = tokens.next();
+            match next_token {
+                Some("&") => next_token = tokens.next(),
+                _ => break,
+            }
+        }
+    }
+
+    let (directives, mut next_token) = parse_directives(next_token, tokens, parser_state)?;
+    type_definition.directives = to_schema_directives(directives);
+
+    match (type_kind, next_token) {
+        (TypeKind::Object, Some("{")) | (TypeKind::Interface, Some("{")) => {
+            type_definition.fields = parse_field_definitions(tokens, parser_state)?;
+            next_token = tokens.next();
+        }
+        (TypeKind::InputObject, Some("{")) => {
+            type_definition.input_fields =
+                parse_input_value_definitions("}", tokens, parser_state)?;
+            next_token = tokens.next();
+        }
+        (TypeKind::Enum, Some("{")) => {
+            type_definition.enum_values = parse_enum_values(tokens, parser_state)?;
+            next_token = tokens.next();
+        }
+        (TypeKind::Union, Some("=")) => {
+            next_token = match tokens.next() {
+                Some("|") => tokens.next(),
+                t => t,
+            };
+
+            loop {
+                let member = parse_name(next_token, tokens)?;
+                type_definition.members.push(member.to_string());
+
+                next_token = tokens.next();
+                match next_token {
+                    Some("|") => next_token = tokens.next(),
+                    _ => break,
+                }
+            }
+        }
+        _ => {}
+    };
+
+    Ok((type_definition, next_token))
+}
+
+fn parse_field_definitions<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<Vec<FieldDefinition>, Error> {
+    let mut fields = Vec::new();
+    let mut next_token = tokens.next();
+
+    loop {
+        let name = match skip_description(next_token, tokens) {
+            Some("}") => return Ok(fields),
+            t => parse_name(t, tokens)?,
+        };
+
+        let arguments = match tokens.next() {
+            Some("(") => {
+                let arguments = parse_input_value_definitions(")", tokens, parser_state)?;
+                expect_token(":", tokens.next(), tokens)?;
+
+                arguments
+            }
+            t => {
+                expect_token(":", t, tokens)?;
+                vec![]
+            }
+        };
+
+        let (field_type, token_after_type) = parse_type(tokens.next(), tokens, parser_state)?;
+        let (directives, token_after_directives) =
+            parse_directives(token_after_type, tokens, parser_state)?;
+
+        fields.push(FieldDefinition {
+            name: name.to_string(),
+            arguments: arguments,
+            r#type: (&field_type).into(),
+            directives: to_schema_directives(directives),
+        });
+
+        next_token = token_after_directives;
+    }
+}
+
+/// Parses arguments definitions (closed by `)`) or input fields (closed by `}`)
+fn parse_input_value_definitions<'a>(
+    closing_token: &str,
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<Vec<InputValueDefinition>, Error> {
+    let mut input_values = Vec::new();
+    let mut next_token = tokens.next();
+
+    loop {
+        let name = match skip_description(next_token, tokens) {
+            Some(s) if s == closing_token => return Ok(input_values),
+            t => parse_name(t, tokens)?,
+        };
+
+        expect_token(":", tokens.next(), tokens)?;
+        let (value_type, token_after_type) = parse_type(tokens.next(), tokens, parser_state)?;
+
+        let (default_value, token_after_default) = match token_after_type {
+            Some("=") => {
+                let value = parse_value(tokens.next(), tokens, parser_state, false)?;
+                (Some(value.to_json(&Map::new())), tokens.next())
+            }
+            t => (None, t),
+        };
+
+        // Directives on arguments and input fields are not used by the proxy
+        let (_, token_after_directives) =
+            parse_directives(token_after_default, tokens, parser_state)?;
+
+        input_values.push(InputValueDefinition {
+            name: name.to_string(),
+            r#type: (&value_type).into(),
+            default_value: default_value,
+        });
+
+        next_token = token_after_directives;
+    }
+}
+
+fn parse_enum_values<'a>(
+    tokens: &mut Tokenizer<'a>,
+    parser_state: &mut ParserState<'a>,
+) -> Result<Vec<String>, Error> {
+    let mut values = Vec::new();
+    let mut next_token = tokens.next();
+
+    loop {
+        let value = match skip_description(next_token, tokens) {
+            Some("}") => return Ok(values),
+            Some(s @ ("true" | "false" | "null")) => {
+                return Err(Error::unexpected_token(s, tokens.location()))
+            }
+            t => parse_name(t, tokens)?,
+        };
+
+        let (_, token_after_directives) = parse_directives(tokens.next(), tokens, parser_state)?;
+        values.push(value.to_string());
+
+        next_token = token_after_directives;
+    }
+}
+
+fn to_schema_directives<'a>(directives: Vec<Directive<'a>>) -> Vec<SchemaDirective> {
+    directives
+        .into_iter()
+        .map(|d| SchemaDirective {
+            name: d.name.to_string(),
+            arguments: d
+                .parameters
+                .iter()
+                .map(|p| (p.name.to_string(), p.value.to_json(&Map::new())))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::schema::SchemaType;
+    use serde_json::json;
+
+    static SDL: &'static str = r#"
+        """
+        The schema
+        """
+        schema { query: RootQuery mutation: RootMutation }
+
+        directive @cacheControl(maxAge: Int, scope: CacheControlScope, inheritMaxAge: Boolean) repeatable on FIELD_DEFINITION | OBJECT | INTERFACE | UNION
+
+        enum CacheControlScope { PUBLIC PRIVATE @deprecated }
+
+        scalar Date @specifiedBy(url: "https://example.com")
+
+        "The root query"
+        type RootQuery {
+            "Returns a user"
+            user(id: ID!, "The role" role: Role = ADMIN): User @cacheControl(maxAge: 30)
+            search(filter: Filter = { limit: 10 }): [SearchResult!]!
+        }
+
+        type RootMutation { ping: Boolean }
+
+        interface Node { id: ID! }
+        interface Named implements Node { id: ID! name: String }
+
+        type User implements & Node & Named @cacheControl(maxAge: 60, scope: PRIVATE) {
+            id: ID!
+            name: String
+        }
+
+        type Company implements Node { id: ID! }
+
+        union SearchResult = | User | Company
+
+        enum Role { ADMIN USER }
+
+        input Filter { limit: Int = 20, name: String @deprecated }
+
+        extend type Company @key(fields: "id") { name: String }
+        extend union SearchResult = Other
+        type Other { id: ID }
+        extend enum Role { GUEST }
+        extend scalar Date @other
+    "#;
+
+    #[test]
+    fn parser_can_parse_sdl() {
+        let schema = parse_schema(SDL).unwrap();
+
+        assert_eq!("RootQuery", schema.query_type);
+        assert_eq!(Some(String::from("RootMutation")), schema.mutation_type);
+
+        let user_field = schema.get_field("RootQuery", "user").unwrap();
+        assert_eq!(SchemaType::Named(String::from("User")), user_field.r#type);
+        assert_eq!(2, user_field.arguments.len());
+        assert_eq!("ID!", user_field.arguments[0].r#type.to_string());
+        assert_eq!(Some(json!("ADMIN")), user_field.arguments[1].default_value);
+        assert_eq!("cacheControl", user_field.directives[0].name);
+        assert_eq!(
+            Some(&json!(30)),
+            user_field.directives[0].arguments.get("maxAge")
+        );
+
+        let search_field = schema.get_field("RootQuery", "search").unwrap();
+        assert_eq!("[SearchResult!]!", search_field.r#type.to_string());
+        assert_eq!(
+            Some(json!({"limit": 10})),
+            search_field.arguments[0].default_value
+        );
+
+        let user = schema.get_type("User").unwrap();
+        assert_eq!(vec!["Node", "Named"], user.interfaces);
+        assert_eq!(json!("PRIVATE"), user.directives[0].arguments["scope"]);
+        assert_eq!(vec!["Node"], schema.get_type("Named").unwrap().interfaces);
+
+        let cache_control = &schema.directives["cacheControl"];
+        assert!(cache_control.repeatable);
+        assert_eq!(4, cache_control.locations.len());
+        assert_eq!(3, cache_control.arguments.len());
+
+        assert_eq!(TypeKind::Scalar, schema.get_type("Date").unwrap().kind);
+        assert_eq!(2, schema.get_type("Filter").unwrap().input_fields.len());
+    }
+
+    #[test]
+    fn parser_applies_sdl_extensions() {
+        let schema = parse_schema(SDL).unwrap();
+
+        assert!(schema.get_field("Company", "name").is_some());
+        assert_eq!(
+            "key",
+            schema.get_type("Company").unwrap().directives[0].name
+        );
+        assert_eq!(
+            vec!["User", "Company", "Other"],
+            schema.get_type("SearchResult").unwrap().members
+        );
+        assert_eq!(
+            vec!["ADMIN", "USER", "GUEST"],
+            schema.get_type("Role").unwrap().enum_values
+        );
+        assert_eq!(2, schema.get_type("Date").unwrap().directives.len());
+    }
+
+    #[test]
+    fn parser_rejects_invalid_sdl() {
+        assert!(parse_schema("type Query { id: }").is_err());
+        assert!(parse_schema("type Query { id: ID } extend type Missing { id: ID }").is_err());
+        assert!(parse_schema("type Query { id: ID } type Query { other: ID }").is_err());
+        assert!(parse_schema("type Query { id: ID } enum E { true }").is_err());
+        assert!(parse_schema("type Query { id: ID } extend").is_err());
+    }
+
+    #[test]
+    fn parser_can_parse_const_values() {
+        assert_eq!(
+            json!({"a": [1, "x", null]}),
+            parse_const_value("{a: [1, \"x\", null]}").unwrap()
+        );
+        assert!(parse_const_value("$var").is_err());
+        assert!(parse_const_value("1 2").is_err());
+    }
+}
@@ -1,104 +1,215 @@
+use serde::Serialize;
+
 pub struct Tokenizer<'a> {
-    escaping: bool,
-    in_quotes: bool,
-    completed: bool,
     slice: &'a str,
+    /// Location of the beginning of `slice`
+    position: Location,
+    last_location: Location,
+}
+
+/// Position of a token in the document. Line and column are 1-based,
+/// the column is counted in characters; the offset is in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    #[serde(skip)]
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub value: &'a str,
+    pub location: Location,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(string: &'a str) -> Tokenizer<'a> {
-        let slice = skip_insignificant_characters(string);
+        let start = Location {
+            line: 1,
+            column: 1,
+            offset: 0,
+        };
 
         Tokenizer {
-            escaping: false,
-            in_quotes: false,
-            completed: false,
-            slice: slice,
+            slice: string,
+            position: start,
+            last_location: start,
         }
     }
-}
 
-//static PUNCTUATORS: &'static [&str] = &[ &"!", &"$", &"(", &")", &"...", &":", &"=", &"@", &"[", &"]", &"{", &"|", &"}" ];
+    /// Returns the location of the last token returned by the tokenizer,
+    /// or the end of the document once all the tokens have been consumed
+    pub fn location(&self) -> Location {
+        self.last_location
+    }
+
+    pub fn next_token(&mut self) -> Option<Token<'a>> {
+        let remainder = skip_insignificant_characters(self.slice);
+        self.advance(self.slice.len() - remainder.len());
+
+        let first_char = match self.slice.chars().nth(0) {
+            Some(c) => c,
+            None => {
+                self.last_location = self.position;
+                return None;
+            }
+        };
+
+        let token_length = match first_char {
+            '.' => self.slice.chars().take(3).take_while(|c| *c == '.').count(),
+            c if PUNCTUATORS.contains(&c) => 1,
+            '"' => string_length(self.slice),
+            c if c == '-' || c.is_ascii_digit() => number_length(self.slice),
+            c if is_name_character(c) => self
+                .slice
+                .char_indices()
+                .find(|(_, c)| !is_name_character(*c))
+                .map(|(ix, _)| ix)
+                .unwrap_or(self.slice.len()),
+            c => c.len_utf8(),
+        };
+
+        let token = Token {
+            value: &self.slice[..token_length],
+            location: self.position,
+        };
+
+        self.advance(token_length);
+        self.last_location = token.location;
+
+        Some(token)
+    }
+
+    /// Moves the beginning of the slice forward by `length` bytes,
+    /// keeping track of lines and columns
+    fn advance(&mut self, length: usize) {
+        let (consumed, remainder) = self.slice.split_at(length);
+        let mut chars = consumed.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                // \r\n is a single line terminator, the line is incremented on \n
+                '\r' if chars.peek() == Some(&'\n') => {}
+                '\n' | '\r' => {
+                    self.position.line += 1;
+                    self.position.column = 1;
+                }
+                _ => self.position.column += 1,
+            }
+        }
+
+        self.position.offset += length;
+        self.slice = remainder;
+    }
+}
 
-static TOKEN_BREAKER: &'static [char] = &['{', '}', '(', ')', '[', ']', ':', '=', '$', '.'];
+static PUNCTUATORS: &'static [char] = &['!', '$', '(', ')', ':', '=', '@', '[', ']', '{', '|', '}'];
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = &'a str;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.completed {
-            return None;
-        }
+        self.next_token().map(|t| t.value)
+    }
+}
 
-        let mut iterator = self.slice.char_indices();
-        let mut start = 0usize;
-        loop {
-            match iterator.next() {
-                Some((pos, c))
-                    if !self.in_quotes
-                        && (TOKEN_BREAKER.contains(&c) || is_insignificant_character(c)) =>
-                {
-                    let processing_insignificant_character = is_insignificant_character(c);
-
-                    if pos > start {
-                        let result = &self.slice[start..pos];
-                        self.slice = &self.slice[pos..];
-                        return Some(result);
-                    } else if c == '.' {
-                        match iterator.next() {
-                            Some((_, '.')) => match iterator.next() {
-                                Some((_, '.')) => {
-                                    self.slice = &self.slice[start + 3..];
-                                    return Some("...");
-                                }
-                                _ => {
-                                    self.slice = &self.slice[start + 2..];
-                                    return Some("..");
-                                }
-                            },
-                            _ => {
-                                self.slice = &self.slice[start + 1..];
-                                return Some(".");
-                            }
-                        }
-                    } else if !processing_insignificant_character && pos == start {
-                        let result = &self.slice[start..pos + 1];
-                        self.slice = &self.slice[pos + 1..];
-                        return Some(result);
-                    } else {
-                        start = pos + 1;
-                        continue;
-                    }
-                }
-                Some((_, '\\')) => {
-                    self.escaping = true;
-                }
-                Some((_, '"')) if !self.escaping => {
-                    self.escaping = false;
-                    self.in_quotes = !self.in_quotes;
-                }
-                Some(_) => {
-                    self.escaping = false;
-                }
-                None => {
-                    self.completed = true;
-                    return None;
-                }
+/// Returns the length of the string starting at the beginning of the slice,
+/// quotes included. Both block strings and regular strings are supported.
+/// An unterminated string extends to the end of the line (or of the
+/// block); the parser will reject it.
+fn string_length(slice: &str) -> usize {
+    if slice.starts_with("\"\"\"") {
+        let mut ix = 3;
+        while ix < slice.len() {
+            let remainder = &slice[ix..];
+            if remainder.starts_with("\\\"\"\"") {
+                ix += 4;
+            } else if remainder.starts_with("\"\"\"") {
+                return ix + 3;
+            } else {
+                ix += remainder.chars().nth(0).map(|c| c.len_utf8()).unwrap_or(1);
             }
         }
+
+        return slice.len();
+    }
+
+    let mut escaping = false;
+    for (ix, c) in slice.char_indices().skip(1) {
+        match c {
+            '\n' | '\r' => return ix,
+            '"' if !escaping => return ix + 1,
+            '\\' => escaping = !escaping,
+            _ => escaping = false,
+        }
+    }
+
+    slice.len()
+}
+
+/// Returns the length of the number at the beginning of the slice:
+/// -?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?
+/// Name characters and dots immediately following the number are
+/// included in the token, so that the parser can reject it.
+fn number_length(slice: &str) -> usize {
+    let bytes = slice.as_bytes();
+    let count_digits = |from: usize| {
+        bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+
+    let mut ix = if bytes[0] == b'-' { 1 } else { 0 };
+    ix += count_digits(ix);
+
+    if ix + 1 < bytes.len() && bytes[ix] == b'.' && bytes[ix + 1].is_ascii_digit() {
+        ix += 1 + count_digits(ix + 1);
+    }
+
+    if ix < bytes.len() && (bytes[ix] == b'e' || bytes[ix] == b'E') {
+        let sign = match bytes.get(ix + 1) {
+            Some(b'+') | Some(b'-') => 1,
+            _ => 0,
+        };
+
+        if bytes.get(ix + 1 + sign).is_some_and(|b| b.is_ascii_digit()) {
+            ix += 1 + sign + count_digits(ix + 1 + sign);
+        }
     }
+
+    ix + slice[ix..]
+        .chars()
+        .take_while(|c| is_name_character(*c) || *c == '.')
+        .map(|c| c.len_utf8())
+        .sum::<usize>()
+}
+
+fn is_name_character(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
+/// Whitespace, line terminators, commas and the Unicode BOM are ignored
 fn is_insignificant_character(c: char) -> bool {
-    c.is_whitespace() || c == ','
+    c.is_whitespace() || c == ',' || c == '\u{FEFF}'
 }
 
-fn skip_insignificant_characters<'a>(string: &'a str) -> &'a str {
+/// Skips ignored characters and `#` comments, which extend to the end of the line.
+/// A `#` inside a string is never reached here, as strings are consumed as a single token.
+fn skip_insignificant_characters(string: &str) -> &str {
     let mut chars = string.char_indices();
     loop {
         match chars.next() {
             Some((_, c)) if is_insignificant_character(c) => {}
+            Some((_, '#')) => loop {
+                match chars.next() {
+                    Some((_, '\n')) | Some((_, '\r')) => break,
+                    Some(_) => {}
+                    None => return "",
+                }
+            },
             Some((ix, _)) => return &string[ix..],
-            None => return &"",
+            None => return "",
         }
     }
 }
@@ -167,4 +278,129 @@ mod tests {
         assert_eq!("}", tokens[14]);
         assert_eq!("}", tokens[15]);
     }
+
+    #[test]
+    fn tokenizer_processes_numbers() {
+        let tokenizer = Tokenizer::new("(p1: -1, p2: 3.14, p3: 1e10, p4: -1.5E-3, p5: 0.5abc)");
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "(", "p1", ":", "-1", "p2", ":", "3.14", "p3", ":", "1e10", "p4", ":", "-1.5E-3",
+                "p5", ":", "0.5abc", ")"
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn tokenizer_processes_strings() {
+        let tokenizer = Tokenizer::new(
+            r#"(p1: "a \" b", p2: "\\", p3: """block "quoted" \""" string""", p4: "\u00e9")"#,
+        );
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "(",
+                "p1",
+                ":",
+                r#""a \" b""#,
+                "p2",
+                ":",
+                r#""\\""#,
+                "p3",
+                ":",
+                r#""""block "quoted" \""" string""""#,
+                "p4",
+                ":",
+                r#""\u00e9""#,
+                ")"
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn tokenizer_processes_list_types() {
+        let tokenizer = Tokenizer::new("($ids: [ID!]!)");
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(
+            vec!["(", "$", "ids", ":", "[", "ID", "!", "]", "!", ")"],
+            tokens
+        );
+    }
+
+    #[test]
+    fn tokenizer_skips_comments() {
+        let tokenizer = Tokenizer::new("# a comment\n{ f1 # another { comment }\r\n f2 }# end");
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(vec!["{", "f1", "f2", "}"], tokens);
+    }
+
+    #[test]
+    fn tokenizer_does_not_treat_hash_in_strings_as_comment() {
+        let tokenizer = Tokenizer::new("{ f1(a: \"# not a comment\", b: \"\"\"#\n#\"\"\") }");
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "{",
+                "f1",
+                "(",
+                "a",
+                ":",
+                "\"# not a comment\"",
+                "b",
+                ":",
+                "\"\"\"#\n#\"\"\"",
+                ")",
+                "}"
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn tokenizer_skips_byte_order_mark() {
+        let tokenizer = Tokenizer::new("\u{FEFF}{ f1 }");
+        let tokens = tokenizer.collect::<Vec<_>>();
+
+        assert_eq!(vec!["{", "f1", "}"], tokens);
+    }
+
+    #[test]
+    fn tokenizer_tracks_token_locations() {
+        let mut tokenizer = Tokenizer::new("{\r\n  f1(a: \"\"\"x\n\"\"\")\n# comment\n  é f2 }");
+        let mut locations = vec![];
+        while let Some(token) = tokenizer.next_token() {
+            locations.push((token.value, token.location.line, token.location.column));
+        }
+
+        assert_eq!(
+            vec![
+                ("{", 1, 1),
+                ("f1", 2, 3),
+                ("(", 2, 5),
+                ("a", 2, 6),
+                (":", 2, 7),
+                ("\"\"\"x\n\"\"\"", 2, 9),
+                (")", 3, 4),
+                ("é", 5, 3),
+                ("f2", 5, 5),
+                ("}", 5, 8),
+            ],
+            locations
+        );
+        assert_eq!(
+            Location {
+                line: 5,
+                column: 9,
+                offset: 40
+            },
+            tokenizer.location()
+        );
+    }
 }
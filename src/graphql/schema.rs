@@ -0,0 +1,467 @@
+mod introspection;
+
+use crate::graphql::parser::sdl::parse_schema;
+use crate::graphql::parser::{Error, OperationType, TypeRef};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+static BUILT_IN_SCALARS: &'static [&'static str] = &["Int", "Float", "String", "Boolean", "ID"];
+
+/// The schema of the upstream server, loaded from SDL or from the result
+/// of an introspection query. Unlike the operations, the schema is owned,
+/// as it lives for the whole life of the proxy.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub query_type: String,
+    pub mutation_type: Option<String>,
+    pub subscription_type: Option<String>,
+    pub types: HashMap<String, TypeDefinition>,
+    pub directives: HashMap<String, DirectiveDefinition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    Scalar,
+    Object,
+    Interface,
+    Union,
+    Enum,
+    InputObject,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeDefinition {
+    pub name: String,
+    pub kind: TypeKind,
+    /// Fields of objects and interfaces
+    pub fields: Vec<FieldDefinition>,
+    /// Interfaces implemented by objects and interfaces
+    pub interfaces: Vec<String>,
+    /// Members of unions
+    pub members: Vec<String>,
+    pub enum_values: Vec<String>,
+    pub input_fields: Vec<InputValueDefinition>,
+    pub directives: Vec<SchemaDirective>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDefinition {
+    pub name: String,
+    pub arguments: Vec<InputValueDefinition>,
+    pub r#type: SchemaType,
+    pub directives: Vec<SchemaDirective>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InputValueDefinition {
+    pub name: String,
+    pub r#type: SchemaType,
+    pub default_value: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectiveDefinition {
+    pub name: String,
+    pub arguments: Vec<InputValueDefinition>,
+    pub repeatable: bool,
+    pub locations: Vec<String>,
+}
+
+/// A directive applied to a type or a field in the schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaDirective {
+    pub name: String,
+    pub arguments: Map<String, Value>,
+}
+
+/// Owned version of `TypeRef`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SchemaType {
+    Named(String),
+    List(Box<SchemaType>),
+    NonNull(Box<SchemaType>),
+}
+
+impl SchemaType {
+    /// Returns the name of the type, without list and non null wrappers
+    pub fn get_name(&self) -> &str {
+        match self {
+            SchemaType::Named(name) => name,
+            SchemaType::List(t) | SchemaType::NonNull(t) => t.get_name(),
+        }
+    }
+
+    pub fn is_non_null(&self) -> bool {
+        matches!(self, SchemaType::NonNull(_))
+    }
+}
+
+impl<'a> From<&TypeRef<'a>> for SchemaType {
+    fn from(type_ref: &TypeRef<'a>) -> SchemaType {
+        match type_ref {
+            TypeRef::Named(name) => SchemaType::Named(name.to_string()),
+            TypeRef::List(t) => SchemaType::List(Box::new(t.as_ref().into())),
+            TypeRef::NonNull(t) => SchemaType::NonNull(Box::new(t.as_ref().into())),
+        }
+    }
+}
+
+impl fmt::Display for SchemaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaType::Named(name) => write!(f, "{}", name),
+            SchemaType::List(t) => write!(f, "[{}]", t),
+            SchemaType::NonNull(t) => write!(f, "{}!", t),
+        }
+    }
+}
+
+impl TypeDefinition {
+    pub fn new(name: String, kind: TypeKind) -> TypeDefinition {
+        TypeDefinition {
+            name: name,
+            kind: kind,
+            fields: vec![],
+            interfaces: vec![],
+            members: vec![],
+            enum_values: vec![],
+            input_fields: vec![],
+            directives: vec![],
+        }
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&FieldDefinition> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.kind == TypeKind::Scalar || self.kind == TypeKind::Enum
+    }
+
+    pub fn is_composite(&self) -> bool {
+        self.kind == TypeKind::Object
+            || self.kind == TypeKind::Interface
+            || self.kind == TypeKind::Union
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.kind == TypeKind::Scalar
+            || self.kind == TypeKind::Enum
+            || self.kind == TypeKind::InputObject
+    }
+
+    /// Adds the fields, values, members and directives of an extension to the type
+    fn extend(&mut self, extension: TypeDefinition) -> Result<(), Error> {
+        if extension.kind != self.kind {
+            return Err(Error::new(format!(
+                "Cannot extend non-{:?} type \"{}\"",
+                extension.kind, self.name
+            )));
+        }
+
+        self.fields.extend(extension.fields);
+        self.interfaces.extend(extension.interfaces);
+        self.members.extend(extension.members);
+        self.enum_values.extend(extension.enum_values);
+        self.input_fields.extend(extension.input_fields);
+        self.directives.extend(extension.directives);
+
+        Ok(())
+    }
+}
+
+impl Schema {
+    /// Creates a schema from its definitions. Extensions are merged into the
+    /// types they extend; built-in scalars and directives are added if they
+    /// are not defined. When the root operation types are not defined, the
+    /// types named `Query`, `Mutation` and `Subscription` are used.
+    pub fn new(
+        type_definitions: Vec<TypeDefinition>,
+        type_extensions: Vec<TypeDefinition>,
+        directive_definitions: Vec<DirectiveDefinition>,
+        root_operation_types: Vec<(OperationType, String)>,
+    ) -> Result<Schema, Error> {
+        let mut types = HashMap::new();
+        for type_definition in type_definitions {
+            if types.contains_key(&type_definition.name) {
+                return Err(Error::new(format!(
+                    "There can be only one type named \"{}\"",
+                    type_definition.name
+                )));
+            }
+
+            types.insert(type_definition.name.clone(), type_definition);
+        }
+
+        for scalar in BUILT_IN_SCALARS {
+            types
+                .entry(scalar.to_string())
+                .or_insert_with(|| TypeDefinition::new(scalar.to_string(), TypeKind::Scalar));
+        }
+
+        for extension in type_extensions {
+            match types.get_mut(&extension.name) {
+                Some(t) => t.extend(extension)?,
+                None => {
+                    return Err(Error::new(format!(
+                        "Cannot extend type \"{}\" because it is not defined",
+                        extension.name
+                    )))
+                }
+            }
+        }
+
+        let mut directives = built_in_directives();
+        for directive in directive_definitions {
+            directives.insert(directive.name.clone(), directive);
+        }
+
+        let root_type =
+            |operation_type: OperationType, default_name: &str| match root_operation_types
+                .iter()
+                .find(|(o, _)| *o == operation_type)
+            {
+                Some((_, name)) => Some(name.clone()),
+                None if root_operation_types.len() == 0 && types.contains_key(default_name) => {
+                    Some(default_name.to_string())
+                }
+                None => None,
+            };
+
+        let schema = Schema {
+            query_type: match root_type(OperationType::Query, "Query") {
+                Some(q) => q,
+                None => return Err(Error::new(String::from("Query root type must be provided"))),
+            },
+            mutation_type: root_type(OperationType::Mutation, "Mutation"),
+            subscription_type: root_type(OperationType::Subscription, "Subscription"),
+            types: types,
+            directives: directives,
+        };
+
+        for root_type in [
+            Some(&schema.query_type),
+            schema.mutation_type.as_ref(),
+            schema.subscription_type.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            match schema.get_type(root_type) {
+                Some(t) if t.kind == TypeKind::Object => {}
+                _ => {
+                    return Err(Error::new(format!(
+                        "Root type \"{}\" must be a defined object type",
+                        root_type
+                    )))
+                }
+            }
+        }
+
+        Ok(schema)
+    }
+
+    pub fn from_sdl(sdl: &str) -> Result<Schema, Error> {
+        parse_schema(sdl)
+    }
+
+    /// Creates the schema from the result of an introspection query.
+    /// Both the whole response (`{"data": {"__schema": ...}}`) and
+    /// the `__schema` object are accepted
+    pub fn from_introspection(introspection: &Value) -> Result<Schema, Error> {
+        introspection::schema_from_introspection(introspection)
+    }
+
+    /// Loads the schema from a file: JSON files are expected to contain the
+    /// result of an introspection query, any other file is parsed as SDL
+    pub fn load(path: &Path) -> Result<Schema, Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(Error::new(format!(
+                    "Unable to read schema file {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Schema::from_introspection(&serde_json::from_str(&content)?),
+            _ => Schema::from_sdl(&content),
+        }
+    }
+
+    pub fn get_type(&self, name: &str) -> Option<&TypeDefinition> {
+        self.types.get(name)
+    }
+
+    pub fn get_root_type(&self, operation_type: OperationType) -> Option<&TypeDefinition> {
+        let name = match operation_type {
+            OperationType::Query => Some(&self.query_type),
+            OperationType::Mutation => self.mutation_type.as_ref(),
+            OperationType::Subscription => self.subscription_type.as_ref(),
+        };
+
+        name.and_then(|n| self.get_type(n))
+    }
+
+    pub fn get_field(&self, type_name: &str, field_name: &str) -> Option<&FieldDefinition> {
+        self.get_type(type_name)
+            .and_then(|t| t.get_field(field_name))
+    }
+
+    /// Returns the object types which can be returned for the given type:
+    /// the type itself for objects, the implementations for interfaces,
+    /// the members for unions
+    pub fn get_possible_types<'a>(&'a self, type_name: &str) -> Vec<&'a str> {
+        match self.get_type(type_name) {
+            Some(t) if t.kind == TypeKind::Object => vec![t.name.as_str()],
+            Some(t) if t.kind == TypeKind::Interface => self
+                .types
+                .values()
+                .filter(|o| o.kind == TypeKind::Object && o.interfaces.contains(&t.name))
+                .map(|o| o.name.as_str())
+                .collect(),
+            Some(t) if t.kind == TypeKind::Union => t.members.iter().map(|m| m.as_str()).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+fn built_in_directives() -> HashMap<String, DirectiveDefinition> {
+    let if_argument = InputValueDefinition {
+        name: String::from("if"),
+        r#type: SchemaType::NonNull(Box::new(SchemaType::Named(String::from("Boolean")))),
+        default_value: None,
+    };
+    let executable_locations = vec![
+        String::from("FIELD"),
+        String::from("FRAGMENT_SPREAD"),
+        String::from("INLINE_FRAGMENT"),
+    ];
+
+    let directives = vec![
+        DirectiveDefinition {
+            name: String::from("skip"),
+            arguments: vec![if_argument.clone()],
+            repeatable: false,
+            locations: executable_locations.clone(),
+        },
+        DirectiveDefinition {
+            name: String::from("include"),
+            arguments: vec![if_argument],
+            repeatable: false,
+            locations: executable_locations,
+        },
+        DirectiveDefinition {
+            name: String::from("deprecated"),
+            arguments: vec![InputValueDefinition {
+                name: String::from("reason"),
+                r#type: SchemaType::Named(String::from("String")),
+                default_value: Some(Value::String(String::from("No longer supported"))),
+            }],
+            repeatable: false,
+            locations: vec![
+                String::from("FIELD_DEFINITION"),
+                String::from("ARGUMENT_DEFINITION"),
+                String::from("INPUT_FIELD_DEFINITION"),
+                String::from("ENUM_VALUE"),
+            ],
+        },
+        DirectiveDefinition {
+            name: String::from("specifiedBy"),
+            arguments: vec![InputValueDefinition {
+                name: String::from("url"),
+                r#type: SchemaType::NonNull(Box::new(SchemaType::Named(String::from("String")))),
+                default_value: None,
+            }],
+            repeatable: false,
+            locations: vec![String::from("SCALAR")],
+        },
+    ];
+
+    directives
+        .into_iter()
+        .map(|d| (d.name.clone(), d))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn schema_adds_built_in_definitions() {
+        let schema = Schema::from_sdl("type Query { id: ID }").unwrap();
+
+        assert_eq!("Query", schema.query_type);
+        assert_eq!(None, schema.mutation_type);
+        assert_eq!(TypeKind::Scalar, schema.get_type("Boolean").unwrap().kind);
+        assert!(schema.directives.contains_key("include"));
+    }
+
+    #[test]
+    fn schema_returns_possible_types() {
+        let sdl = "type Query { node: Node } interface Node { id: ID! } type User implements Node { id: ID! } type Company implements Node { id: ID! } union Result = User | Company";
+        let schema = Schema::from_sdl(sdl).unwrap();
+
+        let mut possible_types = schema.get_possible_types("Node");
+        possible_types.sort();
+        assert_eq!(vec!["Company", "User"], possible_types);
+        assert_eq!(vec!["User", "Company"], schema.get_possible_types("Result"));
+        assert_eq!(vec!["User"], schema.get_possible_types("User"));
+    }
+
+    #[test]
+    fn schema_requires_query_type() {
+        assert!(Schema::from_sdl("type Mutation { id: ID }").is_err());
+        assert!(Schema::from_sdl("schema { query: Missing } type Query { id: ID }").is_err());
+    }
+
+    #[test]
+    fn schema_can_be_loaded_from_introspection() {
+        let introspection = json!({"data": {"__schema": {
+            "queryType": {"name": "Query"},
+            "mutationType": null,
+            "subscriptionType": null,
+            "types": [
+                {"kind": "OBJECT", "name": "Query", "fields": [
+                    {"name": "user", "args": [
+                        {"name": "id", "type": {"kind": "NON_NULL", "name": null, "ofType": {"kind": "SCALAR", "name": "ID", "ofType": null}}, "defaultValue": null},
+                        {"name": "role", "type": {"kind": "ENUM", "name": "Role", "ofType": null}, "defaultValue": "ADMIN"}
+                    ], "type": {"kind": "OBJECT", "name": "User", "ofType": null}}
+                ], "inputFields": null, "interfaces": [], "enumValues": null, "possibleTypes": null},
+                {"kind": "OBJECT", "name": "User", "fields": [
+                    {"name": "tags", "args": [], "type": {"kind": "LIST", "name": null, "ofType": {"kind": "SCALAR", "name": "String", "ofType": null}}}
+                ], "inputFields": null, "interfaces": [], "enumValues": null, "possibleTypes": null},
+                {"kind": "ENUM", "name": "Role", "fields": null, "inputFields": null, "interfaces": null, "enumValues": [{"name": "ADMIN"}, {"name": "USER"}], "possibleTypes": null},
+                {"kind": "SCALAR", "name": "ID", "fields": null, "inputFields": null, "interfaces": null, "enumValues": null, "possibleTypes": null},
+                {"kind": "OBJECT", "name": "__Type", "fields": [], "inputFields": null, "interfaces": [], "enumValues": null, "possibleTypes": null}
+            ],
+            "directives": [
+                {"name": "include", "locations": ["FIELD"], "args": []}
+            ]
+        }}});
+
+        let schema = Schema::from_introspection(&introspection).unwrap();
+
+        let user_field = schema.get_field("Query", "user").unwrap();
+        assert_eq!("User", user_field.r#type.to_string());
+        assert_eq!("ID!", user_field.arguments[0].r#type.to_string());
+        assert_eq!(Some(json!("ADMIN")), user_field.arguments[1].default_value);
+        assert_eq!(
+            "[String]",
+            schema.get_field("User", "tags").unwrap().r#type.to_string()
+        );
+        assert_eq!(
+            vec!["ADMIN", "USER"],
+            schema.get_type("Role").unwrap().enum_values
+        );
+        assert!(schema.get_type("__Type").is_none());
+    }
+}
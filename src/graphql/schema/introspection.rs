@@ -0,0 +1,192 @@
+use super::{
+    DirectiveDefinition, FieldDefinition, InputValueDefinition, Schema, SchemaType, TypeDefinition,
+    TypeKind,
+};
+use crate::graphql::parser::sdl::parse_const_value;
+use crate::graphql::parser::{Error, OperationType};
+use serde::Deserialize;
+use serde_json::{from_value, Value};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectionSchema {
+    query_type: NamedType,
+    mutation_type: Option<NamedType>,
+    subscription_type: Option<NamedType>,
+    types: Vec<IntrospectionType>,
+    #[serde(default)]
+    directives: Vec<IntrospectionDirective>,
+}
+
+#[derive(Deserialize)]
+struct NamedType {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectionType {
+    kind: String,
+    name: String,
+    fields: Option<Vec<IntrospectionField>>,
+    input_fields: Option<Vec<IntrospectionInputValue>>,
+    interfaces: Option<Vec<NamedType>>,
+    possible_types: Option<Vec<NamedType>>,
+    enum_values: Option<Vec<NamedType>>,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionField {
+    name: String,
+    #[serde(default)]
+    args: Vec<IntrospectionInputValue>,
+    r#type: IntrospectionTypeRef,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectionInputValue {
+    name: String,
+    r#type: IntrospectionTypeRef,
+    default_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectionTypeRef {
+    kind: String,
+    name: Option<String>,
+    of_type: Option<Box<IntrospectionTypeRef>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectionDirective {
+    name: String,
+    locations: Vec<String>,
+    #[serde(default)]
+    args: Vec<IntrospectionInputValue>,
+    #[serde(default)]
+    is_repeatable: bool,
+}
+
+pub fn schema_from_introspection(introspection: &Value) -> Result<Schema, Error> {
+    let schema_value = match introspection.pointer("/data/__schema") {
+        Some(s) => s,
+        None => introspection.get("__schema").unwrap_or(introspection),
+    };
+
+    let schema: IntrospectionSchema = match from_value(schema_value.clone()) {
+        Ok(s) => s,
+        Err(e) => return Err(Error::new(format!("Invalid introspection result: {}", e))),
+    };
+
+    let mut types = Vec::with_capacity(schema.types.len());
+    // Introspection types are not part of the schema, they are handled by the server
+    for introspection_type in schema
+        .types
+        .into_iter()
+        .filter(|t| !t.name.starts_with("__"))
+    {
+        types.push(to_type_definition(introspection_type)?);
+    }
+
+    let mut directives = Vec::with_capacity(schema.directives.len());
+    for directive in schema.directives {
+        directives.push(DirectiveDefinition {
+            name: directive.name,
+            arguments: to_input_value_definitions(directive.args)?,
+            repeatable: directive.is_repeatable,
+            locations: directive.locations,
+        });
+    }
+
+    let mut root_operation_types = vec![(OperationType::Query, schema.query_type.name)];
+    if let Some(mutation_type) = schema.mutation_type {
+        root_operation_types.push((OperationType::Mutation, mutation_type.name));
+    }
+    if let Some(subscription_type) = schema.subscription_type {
+        root_operation_types.push((OperationType::Subscription, subscription_type.name));
+    }
+
+    Schema::new(types, vec![], directives, root_operation_types)
+}
+
+fn to_type_definition(introspection_type: IntrospectionType) -> Result<TypeDefinition, Error> {
+    let kind = match introspection_type.kind.as_str() {
+        "SCALAR" => TypeKind::Scalar,
+        "OBJECT" => TypeKind::Object,
+        "INTERFACE" => TypeKind::Interface,
+        "UNION" => TypeKind::Union,
+        "ENUM" => TypeKind::Enum,
+        "INPUT_OBJECT" => TypeKind::InputObject,
+        k => return Err(Error::new(format!("Invalid type kind {}", k))),
+    };
+
+    let mut type_definition = TypeDefinition::new(introspection_type.name, kind);
+
+    for field in introspection_type.fields.unwrap_or_default() {
+        type_definition.fields.push(FieldDefinition {
+            name: field.name,
+            arguments: to_input_value_definitions(field.args)?,
+            r#type: to_schema_type(field.r#type)?,
+            directives: vec![],
+        });
+    }
+
+    type_definition.input_fields =
+        to_input_value_definitions(introspection_type.input_fields.unwrap_or_default())?;
+    type_definition.interfaces = to_names(introspection_type.interfaces);
+    type_definition.enum_values = to_names(introspection_type.enum_values);
+
+    // Implementations of interfaces are computed from the objects, only union members are needed
+    if kind == TypeKind::Union {
+        type_definition.members = to_names(introspection_type.possible_types);
+    }
+
+    Ok(type_definition)
+}
+
+fn to_input_value_definitions(
+    input_values: Vec<IntrospectionInputValue>,
+) -> Result<Vec<InputValueDefinition>, Error> {
+    let mut result = Vec::with_capacity(input_values.len());
+
+    for input_value in input_values {
+        // Default values are returned as GraphQL literals
+        let default_value = match input_value.default_value {
+            Some(v) => Some(parse_const_value(&v)?),
+            None => None,
+        };
+
+        result.push(InputValueDefinition {
+            name: input_value.name,
+            r#type: to_schema_type(input_value.r#type)?,
+            default_value: default_value,
+        });
+    }
+
+    Ok(result)
+}
+
+fn to_schema_type(type_ref: IntrospectionTypeRef) -> Result<SchemaType, Error> {
+    match (type_ref.kind.as_str(), type_ref.name, type_ref.of_type) {
+        ("NON_NULL", _, Some(of_type)) => {
+            Ok(SchemaType::NonNull(Box::new(to_schema_type(*of_type)?)))
+        }
+        ("LIST", _, Some(of_type)) => Ok(SchemaType::List(Box::new(to_schema_type(*of_type)?))),
+        (_, Some(name), _) => Ok(SchemaType::Named(name)),
+        (kind, None, _) => Err(Error::new(format!(
+            "Invalid type reference of kind {}",
+            kind
+        ))),
+    }
+}
+
+fn to_names(named_types: Option<Vec<NamedType>>) -> Vec<String> {
+    named_types
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| t.name)
+        .collect()
+}
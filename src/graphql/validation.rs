@@ -0,0 +1,1105 @@
+use crate::graphql::parser::{
+    parse_query, Directive, Document, Error, Field, FragmentDefinition, Operation, OperationType,
+    Parameter, ParameterField, ParameterValue, Variable,
+};
+use crate::graphql::schema::{InputValueDefinition, Schema, SchemaType, TypeDefinition, TypeKind};
+use itertools::Itertools;
+use std::collections::HashSet;
+
+/// Directives which can't be repeated at the same location.
+/// The other directives are unknown without a schema, and are allowed to be repeated
+static NON_REPEATABLE_DIRECTIVES: &'static [&'static str] = &["skip", "include", "cacheControl"];
+
+/// Parses the query and validates it, against the schema when there is one
+pub fn parse_and_validate<'a>(
+    query: &'a str,
+    schema: Option<&Schema>,
+) -> Result<Document<'a>, Vec<Error>> {
+    let document = parse_query(query).map_err(|e| vec![e])?;
+    validate(&document)?;
+    if let Some(schema) = schema {
+        validate_with_schema(&document, schema)?;
+    }
+
+    Ok(document)
+}
+
+/// Validates an executable document against the rules of the specs (section 5)
+/// which don't require a schema.
+/// All the errors are returned, not only the first one.
+pub fn validate<'a>(document: &Document<'a>) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
+
+    validate_operation_names(document, &mut errors);
+    validate_fragment_names(document, &mut errors);
+    validate_fragment_spreads(document, &mut errors);
+
+    for operation in document.operations.iter() {
+        validate_variables(operation, document, &mut errors);
+
+        if operation.operation_type == OperationType::Subscription {
+            validate_subscription_root_field(operation, document, &mut errors);
+        }
+
+        validate_directives(&operation.directives, &mut errors);
+        validate_selection_set(&operation.fields, &mut errors);
+    }
+
+    for fragment in document.fragment_definitions.iter() {
+        validate_directives(&fragment.directives, &mut errors);
+        validate_selection_set(&fragment.fields, &mut errors);
+    }
+
+    if errors.len() == 0 {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn validate_operation_names<'a>(document: &Document<'a>, errors: &mut Vec<Error>) {
+    let mut names = HashSet::new();
+
+    for operation in document.operations.iter() {
+        match operation.name {
+            Some(name) if !names.insert(name) => errors.push(Error::Validation(format!(
+                "There can be only one operation named \"{}\".",
+                name
+            ))),
+            None if document.operations.len() > 1 => errors.push(Error::Validation(String::from(
+                "This anonymous operation must be the only defined operation.",
+            ))),
+            _ => {}
+        }
+    }
+}
+
+fn validate_fragment_names<'a>(document: &Document<'a>, errors: &mut Vec<Error>) {
+    let mut names = HashSet::new();
+
+    for fragment in document.fragment_definitions.iter() {
+        if !names.insert(fragment.name) {
+            errors.push(Error::Validation(format!(
+                "There can be only one fragment named \"{}\".",
+                fragment.name
+            )));
+        }
+    }
+}
+
+/// Spreads must refer to defined fragments, must not form cycles,
+/// and every fragment must be used by at least one operation
+fn validate_fragment_spreads<'a>(document: &Document<'a>, errors: &mut Vec<Error>) {
+    let mut undefined_fragments = HashSet::new();
+    let selection_sets = document
+        .operations
+        .iter()
+        .map(|o| &o.fields)
+        .chain(document.fragment_definitions.iter().map(|f| &f.fields));
+
+    for fields in selection_sets {
+        for name in get_fragment_spreads(fields) {
+            if find_fragment(document, name).is_none() && undefined_fragments.insert(name) {
+                errors.push(Error::UnknownFragment {
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+
+    for fragment in document.fragment_definitions.iter() {
+        if get_reachable_fragments(&fragment.fields, document).contains(fragment.name) {
+            errors.push(Error::RecursiveFragment {
+                name: fragment.name.to_string(),
+            });
+        }
+    }
+
+    let used_fragments = document
+        .operations
+        .iter()
+        .flat_map(|o| get_reachable_fragments(&o.fields, document))
+        .collect::<HashSet<_>>();
+
+    for fragment in document.fragment_definitions.iter() {
+        if !used_fragments.contains(fragment.name) {
+            errors.push(Error::Validation(format!(
+                "Fragment \"{}\" is never used.",
+                fragment.name
+            )));
+        }
+    }
+}
+
+/// Variables must be unique, defined, and used by the operation
+/// (or by the fragments it spreads)
+fn validate_variables<'a>(
+    operation: &Operation<'a>,
+    document: &Document<'a>,
+    errors: &mut Vec<Error>,
+) {
+    let mut defined_variables = HashSet::new();
+    for variable in operation.variables.iter() {
+        if !defined_variables.insert(variable.name) {
+            errors.push(Error::Validation(format!(
+                "There can be only one variable named \"${}\".",
+                variable.name
+            )));
+        }
+
+        validate_directives(&variable.directives, errors);
+    }
+
+    let fragments = get_reachable_fragments(&operation.fields, document);
+    let mut used_variables = HashSet::new();
+    add_directives_variables(&operation.directives, &mut used_variables);
+    add_fields_variables(&operation.fields, &mut used_variables);
+    for fragment in document
+        .fragment_definitions
+        .iter()
+        .filter(|f| fragments.contains(f.name))
+    {
+        add_directives_variables(&fragment.directives, &mut used_variables);
+        add_fields_variables(&fragment.fields, &mut used_variables);
+    }
+
+    let operation_description = match operation.name {
+        Some(name) => format!(" by operation \"{}\"", name),
+        None => String::new(),
+    };
+
+    for variable in used_variables
+        .iter()
+        .filter(|v| !defined_variables.contains(*v))
+        .sorted()
+    {
+        errors.push(Error::Validation(format!(
+            "Variable \"${}\" is not defined{}.",
+            variable, operation_description
+        )));
+    }
+
+    let operation_description = match operation.name {
+        Some(name) => format!(" in operation \"{}\"", name),
+        None => String::new(),
+    };
+
+    for variable in operation.variables.iter() {
+        if !used_variables.contains(variable.name) {
+            errors.push(Error::Validation(format!(
+                "Variable \"${}\" is never used{}.",
+                variable.name, operation_description
+            )));
+        }
+    }
+}
+
+/// A subscription must select exactly one root field
+fn validate_subscription_root_field<'a>(
+    operation: &Operation<'a>,
+    document: &Document<'a>,
+    errors: &mut Vec<Error>,
+) {
+    let mut response_keys = HashSet::new();
+    collect_root_response_keys(
+        &operation.fields,
+        document,
+        &mut HashSet::new(),
+        &mut response_keys,
+    );
+
+    if response_keys.len() != 1 {
+        let message = match operation.name {
+            Some(name) => format!(
+                "Subscription \"{}\" must select only one top level field.",
+                name
+            ),
+            None => String::from("Anonymous Subscription must select only one top level field."),
+        };
+
+        errors.push(Error::Validation(message));
+    }
+}
+
+fn collect_root_response_keys<'a, 'b>(
+    fields: &'b [Field<'a>],
+    document: &'b Document<'a>,
+    visited_fragments: &mut HashSet<&'a str>,
+    response_keys: &mut HashSet<&'a str>,
+) {
+    for field in fields {
+        match field {
+            Field::Field { alias, name, .. } => {
+                response_keys.insert(alias.unwrap_or(name));
+            }
+            Field::InlineFragment { fields, .. } => {
+                collect_root_response_keys(fields, document, visited_fragments, response_keys)
+            }
+            Field::Fragment { name, .. } => {
+                if !visited_fragments.insert(name) {
+                    continue;
+                }
+
+                if let Some(fragment) = find_fragment(document, name) {
+                    collect_root_response_keys(
+                        &fragment.fields,
+                        document,
+                        visited_fragments,
+                        response_keys,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Arguments, input object fields and directives must be unique
+fn validate_selection_set<'a>(fields: &[Field<'a>], errors: &mut Vec<Error>) {
+    for field in fields {
+        validate_directives(field.get_directives(), errors);
+
+        match field {
+            Field::Field {
+                parameters, fields, ..
+            } => {
+                validate_parameters(parameters, errors);
+                validate_selection_set(fields, errors);
+            }
+            Field::InlineFragment { fields, .. } => validate_selection_set(fields, errors),
+            Field::Fragment { .. } => {}
+        }
+    }
+}
+
+fn validate_directives<'a>(directives: &[Directive<'a>], errors: &mut Vec<Error>) {
+    let mut names = HashSet::new();
+
+    for directive in directives {
+        if !names.insert(directive.name) && NON_REPEATABLE_DIRECTIVES.contains(&directive.name) {
+            errors.push(Error::Validation(format!(
+                "The directive \"@{}\" can only be used once at this location.",
+                directive.name
+            )));
+        }
+
+        validate_parameters(&directive.parameters, errors);
+    }
+}
+
+fn validate_parameters<'a>(parameters: &[Parameter<'a>], errors: &mut Vec<Error>) {
+    let mut names = HashSet::new();
+
+    for parameter in parameters {
+        if !names.insert(parameter.name) {
+            errors.push(Error::Validation(format!(
+                "There can be only one argument named \"{}\".",
+                parameter.name
+            )));
+        }
+
+        validate_value(&parameter.value, errors);
+    }
+}
+
+fn validate_value<'a>(value: &ParameterValue<'a>, errors: &mut Vec<Error>) {
+    match value {
+        ParameterValue::Object(object_fields) => {
+            let mut names = HashSet::new();
+
+            for object_field in object_fields {
+                if !names.insert(object_field.name) {
+                    errors.push(Error::Validation(format!(
+                        "There can be only one input field named \"{}\".",
+                        object_field.name
+                    )));
+                }
+
+                validate_value(&object_field.value, errors);
+            }
+        }
+        ParameterValue::List(values) => values.iter().for_each(|v| validate_value(v, errors)),
+        _ => {}
+    }
+}
+
+/// Validates an executable document against the schema: fields, arguments and
+/// directives must be defined, values and variables must have the expected types,
+/// fragment type conditions must be possible, and leaf fields can't have selections.
+/// The rules which don't need a schema are checked by `validate`.
+pub fn validate_with_schema<'a>(
+    document: &Document<'a>,
+    schema: &Schema,
+) -> Result<(), Vec<Error>> {
+    let mut errors = Vec::<Error>::new();
+
+    for operation in document.operations.iter() {
+        let mut context = SchemaValidationContext {
+            schema: schema,
+            document: document,
+            variables: &operation.variables,
+            visited_fragments: HashSet::new(),
+            errors: vec![],
+        };
+
+        context.validate_operation(operation);
+
+        // Fragments shared by different operations are validated more than once
+        for error in context.errors {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+    }
+
+    if errors.len() == 0 {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct SchemaValidationContext<'s, 'd, 'a> {
+    schema: &'s Schema,
+    document: &'d Document<'a>,
+    /// Variables of the operation being validated
+    variables: &'d [Variable<'a>],
+    visited_fragments: HashSet<&'a str>,
+    errors: Vec<Error>,
+}
+
+impl<'s, 'd, 'a> SchemaValidationContext<'s, 'd, 'a> {
+    fn error(&mut self, message: String) {
+        self.errors.push(Error::Validation(message));
+    }
+
+    fn validate_operation(&mut self, operation: &Operation<'a>) {
+        let (root_type, location) = match operation.operation_type {
+            OperationType::Query => (self.schema.get_root_type(operation.operation_type), "QUERY"),
+            OperationType::Mutation => (
+                self.schema.get_root_type(operation.operation_type),
+                "MUTATION",
+            ),
+            OperationType::Subscription => (
+                self.schema.get_root_type(operation.operation_type),
+                "SUBSCRIPTION",
+            ),
+        };
+
+        let root_type = match root_type {
+            Some(t) => t,
+            None => {
+                return self.error(format!(
+                    "Schema is not configured to execute {} operation.",
+                    location.to_lowercase()
+                ))
+            }
+        };
+
+        for variable in operation.variables.iter() {
+            self.validate_directives(&variable.directives, "VARIABLE_DEFINITION");
+
+            match self.schema.get_type(variable.r#type.get_name()) {
+                Some(t) if t.is_input() => {
+                    if let Some(default_value) = &variable.default_value {
+                        self.validate_value(default_value, &(&variable.r#type).into(), false);
+                    }
+                }
+                Some(_) => self.error(format!(
+                    "Variable \"${}\" cannot be non-input type \"{}\".",
+                    variable.name, variable.r#type
+                )),
+                None => self.error(format!("Unknown type \"{}\".", variable.r#type.get_name())),
+            }
+        }
+
+        self.validate_directives(&operation.directives, location);
+        self.validate_selection_set(&operation.fields, root_type);
+    }
+
+    fn validate_selection_set(&mut self, fields: &[Field<'a>], parent_type: &'s TypeDefinition) {
+        for field in fields {
+            match field {
+                Field::Field {
+                    name,
+                    parameters,
+                    directives,
+                    fields: subfields,
+                    ..
+                } => {
+                    self.validate_directives(directives, "FIELD");
+                    self.validate_field(name, parameters, subfields, parent_type);
+                }
+                Field::InlineFragment {
+                    type_condition,
+                    directives,
+                    fields: subfields,
+                } => {
+                    self.validate_directives(directives, "INLINE_FRAGMENT");
+
+                    let fragment_type = match type_condition {
+                        Some(t) => self.validate_type_condition(t, parent_type),
+                        None => Some(parent_type),
+                    };
+
+                    if let Some(fragment_type) = fragment_type {
+                        self.validate_selection_set(subfields, fragment_type);
+                    }
+                }
+                Field::Fragment { name, directives } => {
+                    self.validate_directives(directives, "FRAGMENT_SPREAD");
+
+                    // Unknown fragments are reported by `validate`
+                    let fragment = match find_fragment(self.document, name) {
+                        Some(f) => f,
+                        None => continue,
+                    };
+
+                    let fragment_type =
+                        match self.validate_type_condition(fragment.r#type, parent_type) {
+                            Some(t) => t,
+                            None => continue,
+                        };
+
+                    if self.visited_fragments.insert(fragment.name) {
+                        self.validate_directives(&fragment.directives, "FRAGMENT_DEFINITION");
+                        self.validate_selection_set(&fragment.fields, fragment_type);
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_field(
+        &mut self,
+        name: &str,
+        parameters: &[Parameter<'a>],
+        subfields: &[Field<'a>],
+        parent_type: &'s TypeDefinition,
+    ) {
+        if name == "__typename" {
+            if subfields.len() > 0 {
+                self.error(format!(
+                    "Field \"{}\" must not have a selection since type \"String!\" has no subfields.",
+                    name
+                ));
+            }
+
+            return;
+        }
+
+        // Introspection queries are not validated
+        if (name == "__schema" || name == "__type") && parent_type.name == self.schema.query_type {
+            return;
+        }
+
+        let field_definition = match parent_type.get_field(name) {
+            Some(f) => f,
+            None => {
+                return self.error(format!(
+                    "Cannot query field \"{}\" on type \"{}\".",
+                    name, parent_type.name
+                ))
+            }
+        };
+
+        self.validate_arguments(
+            parameters,
+            &field_definition.arguments,
+            &format!("field \"{}.{}\"", parent_type.name, name),
+        );
+
+        let field_type = match self.schema.get_type(field_definition.r#type.get_name()) {
+            Some(t) => t,
+            None => return,
+        };
+
+        if field_type.is_leaf() && subfields.len() > 0 {
+            self.error(format!(
+                "Field \"{}\" must not have a selection since type \"{}\" has no subfields.",
+                name, field_definition.r#type
+            ));
+        } else if !field_type.is_leaf() && subfields.len() == 0 {
+            self.error(format!(
+                "Field \"{}\" of type \"{}\" must have a selection of subfields.",
+                name, field_definition.r#type
+            ));
+        } else if !field_type.is_leaf() {
+            self.validate_selection_set(subfields, field_type);
+        }
+    }
+
+    /// Returns the type of the fragment, if it's valid and can be spread in the parent type
+    fn validate_type_condition(
+        &mut self,
+        type_condition: &str,
+        parent_type: &'s TypeDefinition,
+    ) -> Option<&'s TypeDefinition> {
+        let fragment_type = match self.schema.get_type(type_condition) {
+            Some(t) if t.is_composite() => t,
+            Some(_) => {
+                self.error(format!(
+                    "Fragment cannot condition on non composite type \"{}\".",
+                    type_condition
+                ));
+                return None;
+            }
+            None => {
+                self.error(format!("Unknown type \"{}\".", type_condition));
+                return None;
+            }
+        };
+
+        let parent_possible_types = self.schema.get_possible_types(&parent_type.name);
+        let is_possible = self
+            .schema
+            .get_possible_types(&fragment_type.name)
+            .iter()
+            .any(|t| parent_possible_types.contains(t));
+
+        if is_possible {
+            Some(fragment_type)
+        } else {
+            self.error(format!(
+                "Fragment cannot be spread here as objects of type \"{}\" can never be of type \"{}\".",
+                parent_type.name, fragment_type.name
+            ));
+            None
+        }
+    }
+
+    fn validate_directives(&mut self, directives: &[Directive<'a>], location: &str) {
+        let mut names = HashSet::new();
+        for directive in directives {
+            // @cacheControl on the fields of an operation is resolved by the proxy
+            if directive.name == "cacheControl" && location == "FIELD" {
+                continue;
+            }
+
+            let schema = self.schema;
+            let directive_definition = match schema.directives.get(directive.name) {
+                Some(d) => d,
+                None => {
+                    self.error(format!("Unknown directive \"@{}\".", directive.name));
+                    continue;
+                }
+            };
+
+            if !directive_definition.locations.iter().any(|l| l == location) {
+                self.error(format!(
+                    "Directive \"@{}\" may not be used on {}.",
+                    directive.name, location
+                ));
+            }
+
+            // The built-in directives are checked without the schema
+            if !names.insert(directive.name)
+                && !directive_definition.repeatable
+                && !NON_REPEATABLE_DIRECTIVES.contains(&directive.name)
+            {
+                self.error(format!(
+                    "The directive \"@{}\" can only be used once at this location.",
+                    directive.name
+                ));
+            }
+
+            self.validate_arguments(
+                &directive.parameters,
+                &directive_definition.arguments,
+                &format!("directive \"@{}\"", directive.name),
+            );
+        }
+    }
+
+    fn validate_arguments(
+        &mut self,
+        parameters: &[Parameter<'a>],
+        definitions: &'s [InputValueDefinition],
+        owner: &str,
+    ) {
+        for parameter in parameters {
+            match definitions.iter().find(|d| d.name == parameter.name) {
+                Some(d) => {
+                    self.validate_value(&parameter.value, &d.r#type, d.default_value.is_some())
+                }
+                None => self.error(format!(
+                    "Unknown argument \"{}\" on {}.",
+                    parameter.name, owner
+                )),
+            }
+        }
+
+        for definition in definitions.iter() {
+            if is_required(definition) && !parameters.iter().any(|p| p.name == definition.name) {
+                self.error(format!(
+                    "Argument \"{}\" of type \"{}\" is required on {}, but it was not provided.",
+                    definition.name, definition.r#type, owner
+                ));
+            }
+        }
+    }
+
+    /// Checks that the value can be coerced to the expected type.
+    /// `has_default` tells whether the argument or input field the value
+    /// is assigned to has a default value
+    fn validate_value(
+        &mut self,
+        value: &ParameterValue<'a>,
+        expected_type: &SchemaType,
+        has_default: bool,
+    ) {
+        match (value, expected_type) {
+            (ParameterValue::Variable(name), _) => {
+                self.validate_variable_usage(name, expected_type, has_default)
+            }
+            (ParameterValue::Null, SchemaType::NonNull(_)) => self.error(format!(
+                "Expected value of type \"{}\", found null.",
+                expected_type
+            )),
+            (ParameterValue::Null, _) => {}
+            (v, SchemaType::NonNull(inner_type)) => self.validate_value(v, inner_type, false),
+            (ParameterValue::List(values), SchemaType::List(inner_type)) => {
+                for v in values {
+                    self.validate_value(v, inner_type, false);
+                }
+            }
+            // A single value is coerced to a list
+            (v, SchemaType::List(inner_type)) => self.validate_value(v, inner_type, false),
+            (v, SchemaType::Named(type_name)) => self.validate_named_type_value(v, type_name),
+        }
+    }
+
+    fn validate_named_type_value(&mut self, value: &ParameterValue<'a>, type_name: &str) {
+        let type_definition = match self.schema.get_type(type_name) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let is_valid = match (type_definition.kind, value) {
+            (TypeKind::Scalar, v) => match (type_name, v) {
+                ("Int", ParameterValue::Int(i)) => i.parse::<i32>().is_ok(),
+                ("Float", ParameterValue::Int(_) | ParameterValue::Float(_)) => true,
+                ("String", ParameterValue::String(_)) => true,
+                ("Boolean", ParameterValue::Boolean(_)) => true,
+                ("ID", ParameterValue::String(_) | ParameterValue::Int(_)) => true,
+                ("Int" | "Float" | "String" | "Boolean" | "ID", _) => false,
+                // Custom scalars can accept any literal
+                _ => true,
+            },
+            (TypeKind::Enum, ParameterValue::Enum(e)) => {
+                type_definition.enum_values.iter().any(|v| v == e)
+            }
+            (TypeKind::InputObject, ParameterValue::Object(fields)) => {
+                self.validate_input_object(fields, type_definition);
+                true
+            }
+            _ => false,
+        };
+
+        if !is_valid {
+            self.error(format!(
+                "Expected value of type \"{}\", found {}.",
+                type_name, value
+            ));
+        }
+    }
+
+    fn validate_input_object(
+        &mut self,
+        fields: &[ParameterField<'a>],
+        type_definition: &'s TypeDefinition,
+    ) {
+        for field in fields {
+            match type_definition
+                .input_fields
+                .iter()
+                .find(|d| d.name == field.name)
+            {
+                Some(d) => self.validate_value(&field.value, &d.r#type, d.default_value.is_some()),
+                None => self.error(format!(
+                    "Field \"{}\" is not defined by type \"{}\".",
+                    field.name, type_definition.name
+                )),
+            }
+        }
+
+        for definition in type_definition.input_fields.iter() {
+            if is_required(definition) && !fields.iter().any(|f| f.name == definition.name) {
+                self.error(format!(
+                    "Field \"{}.{}\" of required type \"{}\" was not provided.",
+                    type_definition.name, definition.name, definition.r#type
+                ));
+            }
+        }
+    }
+
+    fn validate_variable_usage(
+        &mut self,
+        name: &str,
+        location_type: &SchemaType,
+        location_has_default: bool,
+    ) {
+        // Undefined variables are reported by `validate`
+        let variable = match self.variables.iter().find(|v| v.name == name) {
+            Some(v) => v,
+            None => return,
+        };
+
+        let variable_type = SchemaType::from(&variable.r#type);
+        let variable_has_default = match variable.default_value {
+            Some(ParameterValue::Null) | None => false,
+            Some(_) => true,
+        };
+
+        // A nullable variable can be used for a non null argument, if either has a default value
+        let is_allowed = match location_type {
+            SchemaType::NonNull(inner_type)
+                if !variable_type.is_non_null()
+                    && (variable_has_default || location_has_default) =>
+            {
+                is_type_compatible(&variable_type, inner_type)
+            }
+            _ => is_type_compatible(&variable_type, location_type),
+        };
+
+        if !is_allowed {
+            self.error(format!(
+                "Variable \"${}\" of type \"{}\" used in position expecting type \"{}\".",
+                name, variable_type, location_type
+            ));
+        }
+    }
+}
+
+fn is_required(definition: &InputValueDefinition) -> bool {
+    definition.r#type.is_non_null() && definition.default_value.is_none()
+}
+
+fn is_type_compatible(variable_type: &SchemaType, location_type: &SchemaType) -> bool {
+    match (variable_type, location_type) {
+        (SchemaType::NonNull(v), SchemaType::NonNull(l)) => is_type_compatible(v, l),
+        (_, SchemaType::NonNull(_)) => false,
+        (SchemaType::NonNull(v), l) => is_type_compatible(v, l),
+        (SchemaType::List(v), SchemaType::List(l)) => is_type_compatible(v, l),
+        (SchemaType::List(_), _) | (_, SchemaType::List(_)) => false,
+        (SchemaType::Named(v), SchemaType::Named(l)) => v == l,
+    }
+}
+
+fn find_fragment<'a, 'b>(
+    document: &'b Document<'a>,
+    name: &str,
+) -> Option<&'b FragmentDefinition<'a>> {
+    document
+        .fragment_definitions
+        .iter()
+        .find(|f| f.name == name)
+}
+
+/// Returns the names of the fragments spread in the selection set, at any depth
+fn get_fragment_spreads<'a>(fields: &[Field<'a>]) -> Vec<&'a str> {
+    let mut result = Vec::new();
+
+    for field in fields {
+        match field {
+            Field::Fragment { name, .. } => result.push(*name),
+            Field::Field { fields, .. } | Field::InlineFragment { fields, .. } => {
+                result.extend(get_fragment_spreads(fields))
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the names of the fragments spread in the selection set,
+/// directly or through other fragments
+fn get_reachable_fragments<'a>(fields: &[Field<'a>], document: &Document<'a>) -> HashSet<&'a str> {
+    let mut result = HashSet::new();
+    let mut to_visit = get_fragment_spreads(fields);
+
+    while let Some(name) = to_visit.pop() {
+        if !result.insert(name) {
+            continue;
+        }
+
+        if let Some(fragment) = find_fragment(document, name) {
+            to_visit.extend(get_fragment_spreads(&fragment.fields));
+        }
+    }
+
+    result
+}
+
+fn add_fields_variables<'a>(fields: &[Field<'a>], variables: &mut HashSet<&'a str>) {
+    for field in fields {
+        add_directives_variables(field.get_directives(), variables);
+
+        match field {
+            Field::Field {
+                parameters, fields, ..
+            } => {
+                parameters
+                    .iter()
+                    .for_each(|p| add_value_variables(&p.value, variables));
+                add_fields_variables(fields, variables);
+            }
+            Field::InlineFragment { fields, .. } => add_fields_variables(fields, variables),
+            Field::Fragment { .. } => {}
+        }
+    }
+}
+
+fn add_directives_variables<'a>(directives: &[Directive<'a>], variables: &mut HashSet<&'a str>) {
+    for directive in directives {
+        directive
+            .parameters
+            .iter()
+            .for_each(|p| add_value_variables(&p.value, variables));
+    }
+}
+
+fn add_value_variables<'a>(value: &ParameterValue<'a>, variables: &mut HashSet<&'a str>) {
+    match value {
+        ParameterValue::Variable(v) => {
+            variables.insert(v);
+        }
+        ParameterValue::List(l) => l.iter().for_each(|v| add_value_variables(v, variables)),
+        ParameterValue::Object(o) => o
+            .iter()
+            .for_each(|f| add_value_variables(&f.value, variables)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::parser::parse_query;
+
+    fn validation_errors(query: &str) -> Vec<String> {
+        match validate(&parse_query(query).unwrap()) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_document_has_no_errors() {
+        let query = "query Q($id: ID, $flag: Boolean = true) { user(id: $id) { ...userFields ... on User @include(if: $flag) { name } } } fragment userFields on User { id ...other } fragment other on User { email }";
+
+        assert_eq!(Vec::<String>::new(), validation_errors(query));
+    }
+
+    #[test]
+    fn operation_names_must_be_unique() {
+        assert_eq!(
+            vec!["There can be only one operation named \"Q\"."],
+            validation_errors("query Q { a } query Q { b }")
+        );
+    }
+
+    #[test]
+    fn anonymous_operation_must_be_alone() {
+        assert_eq!(
+            vec!["This anonymous operation must be the only defined operation."],
+            validation_errors("query { a } query Q { b }")
+        );
+    }
+
+    #[test]
+    fn fragments_must_be_defined_unique_and_used() {
+        assert_eq!(
+            vec![
+                "Unknown fragment \"missing\"",
+                "There can be only one fragment named \"frag\".",
+                "Fragment \"unused\" is never used."
+            ]
+            .into_iter()
+            .collect::<HashSet<_>>(),
+            validation_errors("{ ...missing ...frag } fragment frag on Query { a } fragment frag on Query { b } fragment unused on Query { c }")
+                .iter()
+                .map(|e| e.as_str())
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn fragments_must_not_form_cycles() {
+        assert_eq!(
+            vec![
+                "Cannot spread fragment \"a\" within itself",
+                "Cannot spread fragment \"b\" within itself"
+            ],
+            validation_errors(
+                "{ ...a } fragment a on Query { x { ...b } } fragment b on Query { ...a }"
+            )
+        );
+    }
+
+    #[test]
+    fn variables_must_be_unique_defined_and_used() {
+        assert_eq!(
+            vec![
+                "There can be only one variable named \"$a\".",
+                "Variable \"$c\" is not defined by operation \"Q\".",
+                "Variable \"$b\" is never used in operation \"Q\"."
+            ],
+            validation_errors("query Q($a: Int, $a: Int, $b: Int) { f(x: $a) ...frag } fragment frag on Query { g(y: [{ z: $c }]) }")
+        );
+    }
+
+    #[test]
+    fn arguments_input_fields_and_directives_must_be_unique() {
+        assert_eq!(
+            vec![
+                "The directive \"@include\" can only be used once at this location.",
+                "There can be only one argument named \"x\".",
+                "There can be only one input field named \"z\"."
+            ],
+            validation_errors("{ f(x: 1, x: 2, y: { z: 1, z: 2 }) @include(if: true) @include(if: false) @custom @custom }")
+        );
+    }
+
+    #[test]
+    fn subscription_must_select_one_root_field() {
+        assert_eq!(
+            Vec::<String>::new(),
+            validation_errors("subscription S { a { b c } }")
+        );
+        assert_eq!(
+            vec!["Subscription \"S\" must select only one top level field."],
+            validation_errors("subscription S { a ...frag } fragment frag on Subscription { b }")
+        );
+    }
+
+    static SDL: &'static str = r#"
+        type Query {
+            user(id: ID!, role: Role = USER): User
+            search(filter: Filter, ids: [ID!]): [SearchResult]
+            node: Node
+        }
+        type Mutation { ping(count: Int!): Boolean }
+        interface Node { id: ID! }
+        type User implements Node { id: ID! name: String friends(first: Int = 10): [User] }
+        type Company implements Node { id: ID! name: String }
+        union SearchResult = User | Company
+        enum Role { ADMIN USER }
+        input Filter { name: String! limit: Int }
+        directive @cached on FIELD_DEFINITION
+        directive @once on FIELD
+        directive @tag(name: String) repeatable on FIELD
+    "#;
+
+    fn schema_validation_errors(query: &str) -> Vec<String> {
+        let schema = Schema::from_sdl(SDL).unwrap();
+
+        match validate_with_schema(&parse_query(query).unwrap(), &schema) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_document_has_no_schema_errors() {
+        let query = "query($id: ID!, $limit: Int = 5, $flag: Boolean!) { user(id: $id) { __typename id friends(first: $limit) { name } ...userFields } search(filter: { name: \"x\" limit: 3 }, ids: \"1\") { ... on User { id } ... on Company { name } } node { id ... on Company @include(if: $flag) { name @cacheControl(maxAge: 10) } } } fragment userFields on Node { id }";
+
+        assert_eq!(Vec::<String>::new(), schema_validation_errors(query));
+    }
+
+    #[test]
+    fn fields_must_be_defined() {
+        assert_eq!(
+            vec![
+                "Cannot query field \"missing\" on type \"Query\".",
+                "Cannot query field \"id\" on type \"SearchResult\"."
+            ],
+            schema_validation_errors("{ missing search { __typename id } }")
+        );
+    }
+
+    #[test]
+    fn leaf_fields_must_not_have_selections() {
+        assert_eq!(
+            vec![
+                "Field \"id\" must not have a selection since type \"ID!\" has no subfields.",
+                "Field \"friends\" of type \"[User]\" must have a selection of subfields."
+            ],
+            schema_validation_errors("{ user(id: 1) { id { x } friends } }")
+        );
+    }
+
+    #[test]
+    fn arguments_must_have_valid_types() {
+        assert_eq!(
+            vec![
+                "Expected value of type \"Role\", found OTHER.",
+                "Unknown argument \"other\" on field \"Query.user\".",
+                "Argument \"id\" of type \"ID!\" is required on field \"Query.user\", but it was not provided.",
+                "Field \"other\" is not defined by type \"Filter\".",
+                "Expected value of type \"Int\", found \"3\".",
+                "Field \"Filter.name\" of required type \"String!\" was not provided.",
+                "Expected value of type \"ID!\", found null."
+            ],
+            schema_validation_errors("{ user(role: OTHER, other: 1) { id } search(filter: { other: 1, limit: \"3\" }, ids: [null]) { __typename } }")
+        );
+    }
+
+    #[test]
+    fn variables_must_be_input_types_used_in_compatible_positions() {
+        assert_eq!(
+            vec![
+                "Variable \"$user\" cannot be non-input type \"User\".",
+                "Variable \"$id\" of type \"ID\" used in position expecting type \"ID!\".",
+                "Variable \"$ids\" of type \"[ID]\" used in position expecting type \"[ID!]\".",
+                "Variable \"$user\" of type \"User\" used in position expecting type \"ID!\"."
+            ],
+            schema_validation_errors("query($user: User, $id: ID, $ids: [ID], $first: Int) { a: user(id: $id) { friends(first: $first) { id } } b: user(id: \"1\") { id u: __typename } search(ids: $ids) { __typename } c: user(id: $user) { id } }")
+        );
+    }
+
+    #[test]
+    fn fragments_must_be_possible() {
+        assert_eq!(
+            vec![
+                "Unknown type \"Missing\".",
+                "Fragment cannot condition on non composite type \"Role\".",
+                "Fragment cannot be spread here as objects of type \"User\" can never be of type \"Company\"."
+            ],
+            schema_validation_errors("{ user(id: 1) { ... on Missing { id } ... on Role { id } ...company } } fragment company on Company { id }")
+        );
+    }
+
+    #[test]
+    fn directives_must_be_defined_in_valid_locations() {
+        assert_eq!(
+            vec![
+                "Unknown directive \"@unknown\".",
+                "Directive \"@cached\" may not be used on FIELD.",
+                "Argument \"if\" of type \"Boolean!\" is required on directive \"@include\", but it was not provided."
+            ],
+            schema_validation_errors("{ user(id: 1) @unknown { id @cached name @include } }")
+        );
+    }
+
+    #[test]
+    fn non_repeatable_directives_must_be_unique() {
+        assert_eq!(
+            vec!["The directive \"@once\" can only be used once at this location."],
+            schema_validation_errors(
+                "{ user(id: 1) @once @once @tag(name: \"a\") @tag(name: \"b\") { id @once } }"
+            )
+        );
+    }
+
+    #[test]
+    fn operation_types_must_be_defined() {
+        assert_eq!(
+            vec!["Schema is not configured to execute subscription operation."],
+            schema_validation_errors("subscription { user(id: 1) { id } }")
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            schema_validation_errors("mutation { ping(count: 1) }")
+        );
+    }
+}
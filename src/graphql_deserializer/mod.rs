@@ -1,17 +1,188 @@
 use crate::graphql::json::{extract, merge_json, remove_field};
 use serde;
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::cmp::Ordering;
 
+/// The response of the upstream server, along with
+/// the headers which are relevant to the cache
+#[derive(Debug)]
+pub struct UpstreamResponse {
+    pub body: Value,
+    pub cache_control: Option<String>,
+}
+
+impl From<Value> for UpstreamResponse {
+    fn from(body: Value) -> UpstreamResponse {
+        UpstreamResponse {
+            body: body,
+            cache_control: None,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GraphQLResponse {
     pub data: Value,
+    /// Errors of the upstream server. A response with errors is not cached
+    #[serde(default)]
+    pub errors: Vec<Value>,
     pub extensions: Option<GraphQLExtensions>,
+    /// Set by a `Cache-Control: no-store` header, nothing in the response can be cached
+    #[serde(skip)]
+    no_store: bool,
 }
 
 impl GraphQLResponse {
+    /// Applies the `Cache-Control` header of the upstream response. The max age
+    /// (`s-maxage` takes precedence over `max-age`, `no-cache` sets it to 0) and
+    /// the scope are added as a hint for the root of the response, inherited by
+    /// the fields without a hint. `no-store` disables caching for the whole response.
+    pub fn apply_cache_control_header(&mut self, header: &str) {
+        let mut max_age = None;
+        let mut shared_max_age = None;
+        let mut no_cache = false;
+        let mut scope = None;
+
+        for directive in header.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+
+            match (name.to_ascii_lowercase().as_str(), value) {
+                ("no-store", _) => self.no_store = true,
+                ("no-cache", _) => no_cache = true,
+                ("max-age", Some(v)) => max_age = parse_seconds(v),
+                ("s-maxage", Some(v)) => shared_max_age = parse_seconds(v),
+                ("private", _) => scope = Some(CacheScope::PRIVATE),
+                ("public", _) if scope.is_none() => scope = Some(CacheScope::PUBLIC),
+                _ => {}
+            }
+        }
+
+        let max_age = match no_cache {
+            true => Some(0),
+            false => shared_max_age.or(max_age),
+        };
+
+        if max_age.is_none() && scope.is_none() {
+            return;
+        }
+
+        let extensions = self.extensions.get_or_insert_with(|| GraphQLExtensions {
+            cache_control: CacheControl {
+                version: 1,
+                hints: vec![],
+            },
+            cache_tags: vec![],
+        });
+
+        let cache_hints = &mut extensions.cache_control.hints;
+        cache_hints.retain(|h| h.path.len() > 0);
+        cache_hints.push(CacheHintDto {
+            path: vec![],
+            max_age: max_age,
+            scope: scope,
+        });
+    }
+
+    /// Returns the tags the upstream server sent for the whole response
+    pub fn cache_tags(&self) -> Vec<String> {
+        match &self.extensions {
+            Some(e) => e.cache_tags.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Adds the given hints to the response. Hints sent by the upstream server
+    /// with the same path are replaced.
+    pub fn override_cache_hints(&mut self, hints: Vec<CacheHintDto>) {
+        if hints.len() == 0 {
+            return;
+        }
+
+        let extensions = self.extensions.get_or_insert_with(|| GraphQLExtensions {
+            cache_control: CacheControl {
+                version: 1,
+                hints: vec![],
+            },
+            cache_tags: vec![],
+        });
+
+        let cache_hints = &mut extensions.cache_control.hints;
+        cache_hints.retain(|h| !hints.iter().any(|o| o.path == h.path));
+        cache_hints.extend(hints);
+    }
+
+    /// Restricts the hints of the response with the given ones: the shortest max age
+    /// and the private scope win. The hints of the client can't make a field more
+    /// cacheable than the upstream server, the schema and the rules allow.
+    pub fn restrict_cache_hints(&mut self, hints: Vec<CacheHintDto>) {
+        for hint in hints {
+            let (scope, max_age) = self.effective_cache_hint(&hint.path);
+            let restricted_hint = CacheHintDto {
+                max_age: Some(hint.max_age.map_or(max_age, |m| m.min(max_age))),
+                scope: match hint.scope {
+                    Some(CacheScope::PRIVATE) => Some(CacheScope::PRIVATE),
+                    _ => Some(scope),
+                },
+                path: hint.path,
+            };
+
+            self.override_cache_hints(vec![restricted_hint]);
+        }
+    }
+
+    /// Returns the scope and max age applied to a path, inherited from the closest
+    /// hint. Fields without any hint are not cached
+    fn effective_cache_hint(&self, path: &[String]) -> (CacheScope, u16) {
+        let mut scope = CacheScope::PUBLIC;
+        let mut max_age = 0;
+
+        let hints = match &self.extensions {
+            Some(e) => &e.cache_control.hints,
+            None => return (scope, max_age),
+        };
+
+        for i in 0..=path.len() {
+            for hint in hints.iter().filter(|h| h.path == path[..i]) {
+                scope = hint.scope.unwrap_or(scope);
+                max_age = hint.max_age.unwrap_or(max_age);
+            }
+        }
+
+        (scope, max_age)
+    }
+
+    /// Adds the given hints to the response, unless the upstream
+    /// server already sent a hint with the same path.
+    pub fn add_default_cache_hints(&mut self, hints: Vec<CacheHintDto>) {
+        if hints.len() == 0 {
+            return;
+        }
+
+        let extensions = self.extensions.get_or_insert_with(|| GraphQLExtensions {
+            cache_control: CacheControl {
+                version: 1,
+                hints: vec![],
+            },
+            cache_tags: vec![],
+        });
+
+        let cache_hints = &mut extensions.cache_control.hints;
+        let new_hints = hints
+            .into_iter()
+            .filter(|h| !cache_hints.iter().any(|o| o.path == h.path))
+            .collect::<Vec<_>>();
+        cache_hints.extend(new_hints);
+    }
+
     pub fn compress_cache_hints(self) -> (Value, Vec<(Value, CacheHint)>) {
+        if self.no_store {
+            return (self.data, vec![]);
+        }
+
         let mut cache = match self.extensions {
             Some(c) => c.cache_control,
             None => return (self.data, Vec::new()),
@@ -57,10 +228,47 @@ impl GraphQLResponse {
         stack.retain(|(value, _)| object_has_value(value));
         compressed_hints.extend(stack);
 
+        // The hint of the root of the response applies to each of the root fields
+        let compressed_hints = compressed_hints
+            .into_iter()
+            .flat_map(split_root_hint)
+            .filter(|(value, _)| object_has_value(value))
+            .collect();
+
         return (self.data, compressed_hints);
     }
 }
 
+fn split_root_hint((value, hint): (Value, CacheHint)) -> Vec<(Value, CacheHint)> {
+    if hint.path.len() > 0 {
+        return vec![(value, hint)];
+    }
+
+    match value {
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(field, field_value)| {
+                (
+                    json!({ &field: field_value }),
+                    CacheHint {
+                        path: vec![field],
+                        max_age: hint.max_age,
+                        scope: hint.scope,
+                    },
+                )
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn parse_seconds(value: &str) -> Option<u16> {
+    value
+        .parse::<u64>()
+        .ok()
+        .map(|v| v.min(u16::MAX as u64) as u16)
+}
+
 fn object_has_value(json_value: &Value) -> bool {
     match json_value {
         Value::Object(map) => map
@@ -68,6 +276,11 @@ fn object_has_value(json_value: &Value) -> bool {
             .filter(|v| object_has_value(v))
             .nth(0)
             .is_some(),
+        // The elements nothing was extracted from are empty objects or nulls
+        Value::Array(elements) => {
+            elements.iter().all(Value::is_null)
+                || elements.iter().any(|e| !e.is_null() && object_has_value(e))
+        }
         _ => true,
     }
 }
@@ -82,10 +295,14 @@ pub struct CacheHint {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GraphQLExtensions {
+    #[serde(default)]
     pub cache_control: CacheControl,
+    /// Tags of all the values of the response, to purge them together
+    #[serde(default)]
+    pub cache_tags: Vec<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 pub struct CacheControl {
     pub version: u8,
     pub hints: Vec<CacheHintDto>,
@@ -94,11 +311,35 @@ pub struct CacheControl {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CacheHintDto {
+    /// The indices of list elements are kept as strings of digits
+    #[serde(deserialize_with = "deserialize_path")]
     pub path: Vec<String>,
     pub max_age: Option<u16>,
     pub scope: Option<CacheScope>,
 }
 
+fn deserialize_path<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Segment {
+        Field(String),
+        Index(u64),
+    }
+
+    let segments = Vec::<Segment>::deserialize(deserializer)?;
+
+    Ok(segments
+        .into_iter()
+        .map(|s| match s {
+            Segment::Field(f) => f,
+            Segment::Index(i) => i.to_string(),
+        })
+        .collect())
+}
+
 #[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum CacheScope {
     PUBLIC,
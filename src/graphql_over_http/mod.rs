@@ -0,0 +1,422 @@
+use crate::graphql::parser::Error;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use warp::http::header::{HeaderValue, CONTENT_TYPE, VARY};
+use warp::http::StatusCode;
+use warp::Reply;
+
+pub const GRAPHQL_RESPONSE_JSON: &str = "application/graphql-response+json";
+pub const APPLICATION_JSON: &str = "application/json";
+
+/// Media type of the response, negotiated with the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseFormat {
+    GraphQLResponseJson,
+    Json,
+}
+
+impl ResponseFormat {
+    /// Picks the supported media type with the highest quality. Without an `Accept`
+    /// header, `application/json` is used, as legacy clients don't send one.
+    /// Returns `None` if none of the media types can be served.
+    pub fn negotiate(accept: Option<&str>) -> Option<ResponseFormat> {
+        let accept = match accept {
+            Some(a) if a.trim().len() > 0 => a,
+            _ => return Some(ResponseFormat::Json),
+        };
+
+        let mut best: Option<(ResponseFormat, f32)> = None;
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .filter_map(|q| q.trim().parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+
+            let format = match media_type.as_str() {
+                GRAPHQL_RESPONSE_JSON | "application/*" | "*/*" => {
+                    ResponseFormat::GraphQLResponseJson
+                }
+                APPLICATION_JSON => ResponseFormat::Json,
+                _ => continue,
+            };
+
+            // On equal quality, application/graphql-response+json is preferred
+            best = match best {
+                _ if quality <= 0.0 => best,
+                Some((_, q)) if q > quality => best,
+                Some((ResponseFormat::GraphQLResponseJson, q)) if q == quality => best,
+                _ => Some((format, quality)),
+            };
+        }
+
+        best.map(|(format, _)| format)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ResponseFormat::GraphQLResponseJson => {
+                "application/graphql-response+json; charset=utf-8"
+            }
+            ResponseFormat::Json => "application/json; charset=utf-8",
+        }
+    }
+
+    /// Status of the response to a well-formed request which couldn't be executed
+    /// (syntax or validation errors). `application/json` responses always use 200.
+    pub fn request_error_status(&self) -> StatusCode {
+        match self {
+            ResponseFormat::GraphQLResponseJson => StatusCode::BAD_REQUEST,
+            ResponseFormat::Json => StatusCode::OK,
+        }
+    }
+}
+
+/// The parameters of a GraphQL request, from the URL of a GET or the body of a POST
+#[derive(Debug, PartialEq)]
+pub struct GraphQLRequest {
+    pub query: Option<String>,
+    pub variables: Map<String, Value>,
+    pub operation_name: Option<String>,
+    pub extensions: Map<String, Value>,
+    /// Id of a trusted document, sent as `documentId` (or `doc_id` by Relay)
+    pub document_id: Option<String>,
+}
+
+/// A POST body holds either a single request or a batch: a JSON array of requests
+#[derive(Debug, PartialEq)]
+pub enum RequestBody {
+    Single(GraphQLRequest),
+    Batch(Vec<GraphQLRequest>),
+}
+
+/// A request which doesn't follow the GraphQL-over-HTTP specs
+#[derive(Debug, PartialEq)]
+pub struct RequestError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl RequestError {
+    fn bad_request(message: &str) -> RequestError {
+        RequestError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl GraphQLRequest {
+    /// Reads the `query`, `operationName`, `variables`, `extensions` and `documentId`
+    /// URL parameters. `variables` and `extensions` are JSON encoded.
+    pub fn from_query_params(
+        mut params: HashMap<String, String>,
+    ) -> Result<GraphQLRequest, RequestError> {
+        let variables = match params.remove("variables") {
+            Some(v) => parse_json_object(&v, "variables")?,
+            None => Map::new(),
+        };
+        let extensions = match params.remove("extensions") {
+            Some(e) => parse_json_object(&e, "extensions")?,
+            None => Map::new(),
+        };
+
+        let document_id = params
+            .remove("documentId")
+            .or_else(|| params.remove("doc_id"));
+
+        Ok(GraphQLRequest {
+            query: params.remove("query"),
+            variables: variables,
+            operation_name: params.remove("operationName").filter(|n| n.len() > 0),
+            extensions: extensions,
+            document_id: document_id,
+        })
+    }
+
+    /// Reads a JSON encoded request body, a request object or an array of requests.
+    /// A body without a Content-Type is read as JSON, like the clients predating the specs send it
+    pub fn from_body(content_type: Option<&str>, body: &[u8]) -> Result<RequestBody, RequestError> {
+        match content_type.map(|c| {
+            c.split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase()
+        }) {
+            Some(c) if c == APPLICATION_JSON => {}
+            None => {}
+            _ => {
+                return Err(RequestError {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    message: String::from("Content-Type must be application/json"),
+                })
+            }
+        }
+
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Object(map)) => Ok(RequestBody::Single(GraphQLRequest::from_json(map)?)),
+            Ok(Value::Array(requests)) if requests.len() > 0 => {
+                let mut batch = Vec::with_capacity(requests.len());
+                for request in requests {
+                    match request {
+                        Value::Object(map) => batch.push(GraphQLRequest::from_json(map)?),
+                        _ => {
+                            return Err(RequestError::bad_request(
+                                "The requests of a batch must be JSON objects",
+                            ))
+                        }
+                    }
+                }
+
+                Ok(RequestBody::Batch(batch))
+            }
+            Ok(Value::Array(_)) => Err(RequestError::bad_request("The batch is empty")),
+            Ok(_) => Err(RequestError::bad_request(
+                "The body must be a JSON object or array",
+            )),
+            Err(_) => Err(RequestError::bad_request("The body is not valid JSON")),
+        }
+    }
+
+    fn from_json(mut body: Map<String, Value>) -> Result<GraphQLRequest, RequestError> {
+        let query = match body.remove("query") {
+            Some(Value::String(q)) => Some(q),
+            Some(Value::Null) | None => None,
+            Some(_) => return Err(RequestError::bad_request("The query must be a string")),
+        };
+        let variables = match body.remove("variables") {
+            Some(Value::Object(v)) => v,
+            Some(Value::Null) | None => Map::new(),
+            Some(_) => return Err(RequestError::bad_request("The variables must be an object")),
+        };
+        let operation_name = match body.remove("operationName") {
+            Some(Value::String(n)) => Some(n),
+            Some(Value::Null) | None => None,
+            Some(_) => {
+                return Err(RequestError::bad_request(
+                    "The operationName must be a string",
+                ))
+            }
+        };
+        let extensions = match body.remove("extensions") {
+            Some(Value::Object(e)) => e,
+            Some(Value::Null) | None => Map::new(),
+            Some(_) => {
+                return Err(RequestError::bad_request(
+                    "The extensions must be an object",
+                ))
+            }
+        };
+        let document_id = match body.remove("documentId").or_else(|| body.remove("doc_id")) {
+            Some(Value::String(i)) => Some(i),
+            Some(Value::Null) | None => None,
+            Some(_) => return Err(RequestError::bad_request("The documentId must be a string")),
+        };
+
+        Ok(GraphQLRequest {
+            query: query,
+            variables: variables,
+            operation_name: operation_name,
+            extensions: extensions,
+            document_id: document_id,
+        })
+    }
+}
+
+fn parse_json_object(value: &str, name: &str) -> Result<Map<String, Value>, RequestError> {
+    match serde_json::from_str::<Value>(value) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(Value::Null) => Ok(Map::new()),
+        _ => Err(RequestError::bad_request(&format!(
+            "The {} must be a JSON encoded object",
+            name
+        ))),
+    }
+}
+
+/// Serializes the body with the content type of the format
+pub fn response(format: ResponseFormat, status: StatusCode, body: &Value) -> warp::reply::Response {
+    let mut response = warp::reply::with_status(body.to_string(), status).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(VARY, HeaderValue::from_static("Accept"));
+
+    response
+}
+
+/// Serializes the errors as a GraphQL response: `{"errors": [{"message": "...", "locations": [...]}]}`
+pub fn errors_body(errors: &[Error]) -> Value {
+    let errors = errors.iter().map(|e| e.to_json()).collect::<Vec<_>>();
+
+    json!({ "errors": errors })
+}
+
+pub fn error_response(
+    format: ResponseFormat,
+    status: StatusCode,
+    errors: Vec<Error>,
+) -> warp::reply::Response {
+    response(format, status, &errors_body(&errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GraphQLRequest, RequestBody, RequestError, ResponseFormat};
+    use serde_json::{json, Map};
+    use std::collections::HashMap;
+    use warp::http::StatusCode;
+
+    #[test]
+    fn response_format_is_negotiated() {
+        let negotiate = |accept| ResponseFormat::negotiate(accept);
+
+        assert_eq!(negotiate(None), Some(ResponseFormat::Json));
+        assert_eq!(negotiate(Some("")), Some(ResponseFormat::Json));
+        assert_eq!(
+            negotiate(Some("application/json")),
+            Some(ResponseFormat::Json)
+        );
+        assert_eq!(
+            negotiate(Some("application/graphql-response+json")),
+            Some(ResponseFormat::GraphQLResponseJson)
+        );
+        assert_eq!(
+            negotiate(Some("application/json, application/graphql-response+json")),
+            Some(ResponseFormat::GraphQLResponseJson)
+        );
+        assert_eq!(
+            negotiate(Some(
+                "application/graphql-response+json;q=0.5, application/json;charset=utf-8"
+            )),
+            Some(ResponseFormat::Json)
+        );
+        assert_eq!(
+            negotiate(Some("text/html, */*;q=0.8")),
+            Some(ResponseFormat::GraphQLResponseJson)
+        );
+        assert_eq!(negotiate(Some("text/html")), None);
+        assert_eq!(negotiate(Some("application/json;q=0")), None);
+    }
+
+    #[test]
+    fn requests_are_read_from_query_params() {
+        let mut params = HashMap::new();
+        params.insert(String::from("query"), String::from("{ field1 }"));
+        params.insert(String::from("variables"), String::from("{\"id\": 1}"));
+        params.insert(String::from("operationName"), String::from(""));
+
+        let request = GraphQLRequest::from_query_params(params).unwrap();
+        assert_eq!(request.query, Some(String::from("{ field1 }")));
+        assert_eq!(
+            request.variables,
+            json!({"id": 1}).as_object().unwrap().clone()
+        );
+        assert_eq!(request.operation_name, None);
+        assert_eq!(request.extensions, Map::new());
+        assert_eq!(request.document_id, None);
+
+        let mut params = HashMap::new();
+        params.insert(String::from("doc_id"), String::from("abc"));
+        let request = GraphQLRequest::from_query_params(params).unwrap();
+        assert_eq!(request.document_id, Some(String::from("abc")));
+
+        let mut params = HashMap::new();
+        params.insert(String::from("variables"), String::from("[1]"));
+        assert_eq!(
+            GraphQLRequest::from_query_params(params)
+                .err()
+                .unwrap()
+                .status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn requests_are_read_from_body() {
+        let body = json!({"query": "{ field1 }", "operationName": null, "variables": {"id": 1}});
+        let request = match GraphQLRequest::from_body(
+            Some("application/json; charset=utf-8"),
+            body.to_string().as_bytes(),
+        ) {
+            Ok(RequestBody::Single(r)) => r,
+            r => panic!("Unexpected request {:?}", r),
+        };
+        assert_eq!(request.query, Some(String::from("{ field1 }")));
+        assert_eq!(request.operation_name, None);
+        assert_eq!(
+            request.variables,
+            json!({"id": 1}).as_object().unwrap().clone()
+        );
+
+        assert_eq!(
+            GraphQLRequest::from_body(Some("text/plain"), body.to_string().as_bytes()),
+            Err(RequestError {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                message: String::from("Content-Type must be application/json")
+            })
+        );
+        assert!(matches!(
+            GraphQLRequest::from_body(None, body.to_string().as_bytes()),
+            Ok(RequestBody::Single(_))
+        ));
+        assert_eq!(
+            GraphQLRequest::from_body(Some("application/json"), b"{")
+                .err()
+                .unwrap()
+                .status,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            GraphQLRequest::from_body(
+                Some("application/json"),
+                json!({"query": "{ field1 }", "variables": 1})
+                    .to_string()
+                    .as_bytes()
+            )
+            .err()
+            .unwrap()
+            .message,
+            "The variables must be an object"
+        );
+
+        let body = json!({"documentId": "abc"});
+        match GraphQLRequest::from_body(Some("application/json"), body.to_string().as_bytes()) {
+            Ok(RequestBody::Single(r)) => assert_eq!(r.document_id, Some(String::from("abc"))),
+            r => panic!("Unexpected request {:?}", r),
+        }
+    }
+
+    #[test]
+    fn batches_are_read_from_body() {
+        let body = json!([{"query": "{ field1 }"}, {"query": "{ field2 }", "operationName": "Op"}]);
+        match GraphQLRequest::from_body(Some("application/json"), body.to_string().as_bytes()) {
+            Ok(RequestBody::Batch(requests)) => {
+                assert_eq!(requests.len(), 2);
+                assert_eq!(requests[1].query, Some(String::from("{ field2 }")));
+                assert_eq!(requests[1].operation_name, Some(String::from("Op")));
+            }
+            r => panic!("Unexpected request {:?}", r),
+        }
+
+        assert_eq!(
+            GraphQLRequest::from_body(Some("application/json"), b"[]")
+                .err()
+                .unwrap()
+                .message,
+            "The batch is empty"
+        );
+        assert_eq!(
+            GraphQLRequest::from_body(Some("application/json"), b"[{\"query\": \"{ f }\"}, 1]")
+                .err()
+                .unwrap()
+                .status,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -1,23 +1,51 @@
+mod admin;
 mod auth;
 mod graphql;
 mod graphql_deserializer;
+mod graphql_over_http;
+mod persisted_queries;
+mod server;
+mod upstream;
 
+use admin::AdminConfig;
 use auth::{authorize_header, get_oidc_config, AuthConfiguration, AuthHeader, AuthorizationType};
 use clap::Parser;
-use graphql::cache::Cache;
-use graphql::parser::serialize_operation;
+use futures::future::{self, join_all, FutureExt};
+use graphql::cache::{create_backend, Backend, CacheBackend};
+use graphql::cache_handler::CacheContext;
+use graphql::cache_policy::CacheRule;
+use graphql::cache_policy::ResponseCachePolicy;
+use graphql::invalidation::InvalidationRule;
+use graphql::normalization::NormalizationConfig;
+use graphql::operation_cache::OperationCache;
+use graphql::parser::{Error, OperationType};
+use graphql::schema::Schema;
+use graphql_over_http::{
+    error_response, errors_body, GraphQLRequest, RequestBody, RequestError, ResponseFormat,
+    APPLICATION_JSON, GRAPHQL_RESPONSE_JSON,
+};
+use itertools::Itertools;
+use persisted_queries::{PersistedQueries, PersistedQueriesConfig, TrustedDocuments};
 use serde::Deserialize;
 use serde_json;
-use serde_json::Map;
 use serde_json::Value;
+use server::{
+    default_graphql_path, default_listeners, path_filter, shutdown_on_signal, InFlight,
+    ListenerConfig, Shutdown,
+};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fs;
 use std::net::SocketAddr;
-use std::process::exit;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
-use warp::Filter;
+use std::time::Duration;
+use upstream::{Batching, Upstream, UpstreamBatch, UpstreamConfig};
+use warp::http::header::{HeaderMap, HeaderValue, ACCEPT, AGE, ALLOW, CACHE_CONTROL};
+use warp::http::{Method, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::{Filter, Reply};
 
 #[derive(Parser)]
 struct CliArguments {
@@ -26,9 +54,76 @@ struct CliArguments {
 
 #[derive(Debug, Deserialize)]
 struct Config {
+    /// Storage of the cache, `redis` by default
+    #[serde(default)]
+    backend: Backend,
+    /// Only used by the `redis` backend
+    #[serde(default)]
     redis_connection_string: String,
     oidc_configuration_endpoint: String,
     oidc_token_header: String,
+    /// SDL or introspection result (.json) of the upstream schema.
+    /// When provided, operations are validated against the schema
+    schema_file: Option<String>,
+    /// Cache policies applied to the fields the upstream server sends no hint for
+    #[serde(default)]
+    cache_rules: Vec<CacheRule>,
+    /// Cache entries removed by the mutations
+    #[serde(default)]
+    invalidation_rules: Vec<InvalidationRule>,
+    /// Stores the entities once, keyed by `__typename` and id. Disabled when missing.
+    /// Needs `schema_file` to add the key fields the queries don't select
+    normalization: Option<NormalizationConfig>,
+    /// Adds the `Cache-Control` and `Age` headers to the responses
+    #[serde(default = "default_true")]
+    cache_control_headers: bool,
+    /// Upstream GraphQL server the operations are forwarded to
+    upstream: UpstreamConfig,
+    /// Addresses the proxy listens on, optionally with TLS
+    #[serde(default = "default_listeners")]
+    listeners: Vec<ListenerConfig>,
+    /// Path of the GraphQL endpoint
+    #[serde(default = "default_graphql_path")]
+    graphql_path: String,
+    /// Listener of the admin API. The API is disabled when missing
+    admin: Option<AdminConfig>,
+    /// Automatic Persisted Queries, enabled by default
+    #[serde(default)]
+    persisted_queries: PersistedQueriesConfig,
+    /// Number of parsed operations kept in memory, 0 disables the cache
+    #[serde(default = "default_operation_cache_size")]
+    operation_cache_size: usize,
+    /// Time given to the requests in progress to complete on shutdown
+    #[serde(default = "default_shutdown_timeout_ms")]
+    shutdown_timeout_ms: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_operation_cache_size() -> usize {
+    1000
+}
+
+/// Less than the 30 seconds Kubernetes waits before killing the pod
+fn default_shutdown_timeout_ms() -> u64 {
+    25000
+}
+
+/// State shared by all the requests
+struct Proxy {
+    upstream: Upstream,
+    cache: Arc<dyn CacheBackend>,
+    persisted_queries: PersistedQueries,
+    /// Parsed operations of the recent queries
+    operations: OperationCache,
+    /// Allowlist of the documents which can be executed
+    trusted_documents: Option<TrustedDocuments>,
+    /// Schema and rules of the cache
+    cache_context: CacheContext,
+    cache_control_headers: bool,
+    in_flight: InFlight,
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 16)]
@@ -41,12 +136,58 @@ async fn main() {
     let file_content = fs::read_to_string(config_path).expect("Unable to read file");
     let config: Config = serde_json::from_str(&file_content).expect("Unable to parse");
 
-    #[cfg(not(test))]
-    let cache = Cache::new(&config.redis_connection_string)
+    let schema = config
+        .schema_file
+        .as_ref()
+        .map(|schema_file| Schema::load(Path::new(schema_file)).expect("Unable to load schema"));
+    let upstream = Upstream::new(&config.upstream).expect("Unable to configure the upstream");
+    let trusted_documents = config
+        .persisted_queries
+        .manifest_file
+        .as_ref()
+        .map(|manifest_file| {
+            TrustedDocuments::load(Path::new(manifest_file), schema.as_ref())
+                .expect("Unable to load the trusted documents")
+        });
+    if let Some(trusted_documents) = &trusted_documents {
+        println!("Loaded {} trusted documents", trusted_documents.count());
+    }
+
+    let cache = create_backend(config.backend, &config.redis_connection_string)
         .await
         .expect("Error initializing cache");
-    #[cfg(test)]
-    let cache = Cache::new();
+
+    let shutdown = Shutdown::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+    let admin_server = match &config.admin {
+        Some(admin) => server::bind(
+            std::slice::from_ref(&admin.listener),
+            admin::routes(&admin.token, cache.clone(), shutdown.clone()),
+            &shutdown,
+        )
+        .await
+        .expect("Unable to start the admin server"),
+        None => future::ready(()).boxed(),
+    };
+
+    let proxy = Arc::new(Proxy {
+        upstream: upstream,
+        persisted_queries: PersistedQueries::new(&config.persisted_queries, cache.clone()),
+        trusted_documents: trusted_documents,
+        operations: OperationCache::new(config.operation_cache_size),
+        cache: cache,
+        cache_context: CacheContext {
+            schema: schema,
+            cache_rules: config.cache_rules,
+            invalidation_rules: config.invalidation_rules,
+            normalization: config.normalization,
+        },
+        cache_control_headers: config.cache_control_headers,
+        in_flight: InFlight::default(),
+    });
+    let in_flight = proxy.in_flight.clone();
+    let cache = proxy.cache.clone();
 
     // We must leak the Box in order to get a `&'static str` borrow
     // `warp::header` requires the header name to be passed as a `&'static str`
@@ -65,147 +206,352 @@ async fn main() {
             }
         };
 
-    let end = warp::path("end").map(|| {
-        exit(0);
-        ""
-    });
-    let endpoint = warp::path("hello")
+    // A GET without parameters is answered with a "Must provide query string" error
+    let get_request = warp::get()
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .or(warp::any().map(HashMap::new))
+                .unify(),
+        )
+        .map(|params| GraphQLRequest::from_query_params(params).map(RequestBody::Single));
+    let post_request = warp::post()
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::bytes())
+        .map(|content_type: Option<String>, body: Bytes| {
+            GraphQLRequest::from_body(content_type.as_deref(), &body)
+        });
+
+    let endpoint = path_filter(&config.graphql_path)
         .and(warp::addr::remote())
-        .and(warp::body::json())
+        .and(warp::method())
+        .and(get_request.or(post_request).unify())
+        .and(warp::header::headers_cloned())
         .and(authorize_header(Arc::new(auth_configuration)))
-        .and_then(move |c, d, auth_token| handle_request(c, d, auth_token, cache.clone()));
+        .and_then(move |addr, method, request, headers, auth_token| {
+            spawn_request(addr, method, request, headers, auth_token, proxy.clone())
+        });
 
-    let routes = endpoint.or(end);
-    warp::serve(routes).run(([0, 0, 0, 0], 3033)).await;
-}
+    let routes = endpoint.map(Reply::into_response).boxed();
+    let server = server::bind(&config.listeners, routes, &shutdown)
+        .await
+        .expect("Unable to start the server");
 
-async fn forward_graphql_request<'a>(
-    operation: graphql::parser::Operation<'a>,
-    variables: Map<String, Value>,
-    auth_header: Option<String>,
-) -> (
-    Result<Value, graphql::parser::Error>,
-    graphql::parser::Operation<'a>,
-    Map<String, Value>,
-) {
-    let sss = serialize_operation(&operation);
-    println!("Request: {}", sss);
-    let mut map = HashMap::new();
-    map.insert("query", Value::String(sss));
-    map.insert("variables", Value::Object(variables));
-
-    let client = reqwest::Client::new();
-    let request = client.post("http://192.168.1.50:4000/").json(&map);
-
-    let request_builder = if let Some(header) = auth_header {
-        request.header("Authorization", header)
-    } else {
-        request
+    // The servers complete once shutdown is triggered and the connections are closed.
+    // The requests are handled in their own tasks, which may still be running
+    let drain = async {
+        future::join(server, admin_server).await;
+        in_flight.wait_idle().await;
+    };
+    let timeout = async {
+        shutdown.clone().wait().await;
+        tokio::time::sleep(Duration::from_millis(config.shutdown_timeout_ms)).await;
     };
 
-    let res = request_builder.send().await;
+    tokio::select! {
+        _ = drain => println!("All requests completed"),
+        _ = timeout => println!(
+            "Shutdown timeout expired, {} requests aborted",
+            in_flight.count()
+        ),
+    }
 
-    let the_v = match map.remove("variables") {
-        Some(Value::Object(v)) => v,
-        _ => Map::new(),
-    };
+    if let Err(e) = cache.close().await {
+        println!("Error while closing the cache: {:?}", e);
+    }
+    println!("Server stopped");
+}
 
-    let resp = match res {
-        Ok(r) => r.json::<Value>().await,
-        Err(e) => {
-            return (
-                Err(graphql::parser::Error::new(format!(
-                    "Request error: {:?}",
-                    e
-                ))),
-                operation,
-                the_v,
-            )
-        }
-    };
+/// Handles the request in its own task, so that the operation and its cache writes
+/// are completed even if the client disconnects while the proxy is shutting down
+async fn spawn_request(
+    addr_opt: Option<SocketAddr>,
+    method: Method,
+    request: Result<RequestBody, RequestError>,
+    headers: HeaderMap,
+    auth_header: Option<AuthHeader>,
+    proxy: Arc<Proxy>,
+) -> Result<warp::reply::Response, Infallible> {
+    let in_flight = proxy.in_flight.start();
+    let task = tokio::spawn(async move {
+        let _in_flight = in_flight;
+        handle_request(addr_opt, method, request, headers, auth_header, proxy).await
+    });
 
-    match resp {
-        Ok(r) => (Ok(r), operation, the_v),
-        Err(e) => (
-            Err(graphql::parser::Error::new(format!(
-                "Deserialization error: {:?}",
-                e
-            ))),
-            operation,
-            the_v,
-        ),
+    match task.await {
+        Ok(response) => response,
+        Err(e) => Ok(error_response(
+            ResponseFormat::Json,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            vec![Error::new(format!("Internal error: {}", e))],
+        )),
+    }
+}
+
+/// What the operations of a request are executed with
+struct RequestContext<'r> {
+    method: Method,
+    format: ResponseFormat,
+    auth_token: Option<String>,
+    auth_header: Option<String>,
+    headers: &'r HeaderMap,
+    proxy: &'r Proxy,
+}
+
+/// The result of an operation. There's no cache policy
+/// when the operation couldn't be executed
+struct ExecutionResult {
+    status: StatusCode,
+    body: Value,
+    cache_policy: Option<ResponseCachePolicy>,
+}
+
+impl ExecutionResult {
+    fn errors(status: StatusCode, errors: Vec<Error>) -> ExecutionResult {
+        ExecutionResult {
+            status: status,
+            body: errors_body(&errors),
+            cache_policy: None,
+        }
     }
 }
 
 async fn handle_request(
     _addr_opt: Option<SocketAddr>,
-    mut body: HashMap<String, Value>,
+    method: Method,
+    request: Result<RequestBody, RequestError>,
+    headers: HeaderMap,
     auth_header: Option<AuthHeader>,
-    cache: Cache,
-) -> Result<impl warp::Reply, Infallible> {
+    proxy: Arc<Proxy>,
+) -> Result<warp::reply::Response, Infallible> {
+    let accept = headers.get(ACCEPT).and_then(|h| h.to_str().ok());
+    let format = match ResponseFormat::negotiate(accept) {
+        Some(f) => f,
+        None => {
+            return Ok(error_response(
+                ResponseFormat::Json,
+                StatusCode::NOT_ACCEPTABLE,
+                vec![Error::new(format!(
+                    "Supported media types are {} and {}",
+                    GRAPHQL_RESPONSE_JSON, APPLICATION_JSON
+                ))],
+            ))
+        }
+    };
+
+    let request = match request {
+        Ok(r) => r,
+        Err(e) => {
+            return Ok(error_response(
+                format,
+                e.status,
+                vec![Error::new(e.message)],
+            ))
+        }
+    };
+
     let (auth_token, auth_header_value) = match auth_header {
         Some(t) => (Some(t.sub), Some(t.header)),
         _ => (None, None),
     };
 
-    let q = match body.remove("query") {
-        Some(Value::String(q)) => q,
-        _ => return Ok(format!("no")),
+    let context = RequestContext {
+        method: method,
+        format: format,
+        auth_token: auth_token,
+        auth_header: auth_header_value,
+        headers: &headers,
+        proxy: &proxy,
     };
 
-    let document = match graphql::parser::parse_query(&q) {
-        Ok(r) => r,
-        Err(_) => return Ok(format!("nein")),
+    let result = match request {
+        RequestBody::Single(request) => execute_request(request, &context, None).await,
+        RequestBody::Batch(requests) => execute_batch(requests, &context).await,
     };
 
-    let variables = match body.remove("variables") {
-        Some(Value::Object(map)) => map,
-        Some(_) => return Ok(format!("nein variables")),
-        None => serde_json::Map::<String, Value>::new(),
+    let mut response = graphql_over_http::response(format, result.status, &result.body);
+    if result.status == StatusCode::METHOD_NOT_ALLOWED {
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("POST"));
+    }
+
+    match result.cache_policy {
+        Some(cache_policy) if proxy.cache_control_headers => {
+            let headers = response.headers_mut();
+            headers.insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&cache_policy.cache_control_header()).unwrap(),
+            );
+            headers.insert(AGE, HeaderValue::from(cache_policy.age));
+        }
+        _ => {}
+    }
+
+    Ok(response)
+}
+
+/// Executes the requests of a batch concurrently. The responses are returned in
+/// the order of the requests
+async fn execute_batch(
+    requests: Vec<GraphQLRequest>,
+    context: &RequestContext<'_>,
+) -> ExecutionResult {
+    let batch = match context.proxy.upstream.batching {
+        Batching::Batched => Some(UpstreamBatch::new(
+            &context.proxy.upstream,
+            context.auth_header.clone(),
+            context.headers,
+            requests.len(),
+        )),
+        Batching::Parallel => None,
+    };
+
+    let results = join_all(requests.into_iter().map(|request| async {
+        let result = execute_request(request, context, batch.as_ref()).await;
+        if let Some(batch) = &batch {
+            batch.complete().await;
+        }
+        result
+    }))
+    .await;
+
+    // The status of the requests is used when they all have the same
+    let status = match results.iter().map(|r| r.status).dedup().exactly_one() {
+        Ok(status) => status,
+        Err(_) => StatusCode::OK,
     };
 
-    let (operation, fragment_definitions) = if document.operations.len() > 1 {
-        if let Some(Value::String(operation_name)) = body.remove("operationName") {
-            match document.filter_operation(&operation_name) {
-                Ok(d) => (
-                    d.operations.into_iter().nth(0).unwrap(),
-                    d.fragment_definitions,
-                ),
-                Err(_) => return Ok(String::from("operationName ist gulen")),
+    // The batch can only be cached if each of its requests can
+    let mut cache_policy = results[0].cache_policy;
+    for result in &results[1..] {
+        cache_policy = match (cache_policy, result.cache_policy) {
+            (Some(p1), Some(p2)) => Some(p1.restrict(p2)),
+            _ => None,
+        };
+    }
+
+    ExecutionResult {
+        status: status,
+        body: Value::Array(results.into_iter().map(|r| r.body).collect()),
+        cache_policy: cache_policy,
+    }
+}
+
+/// Executes a single request. The residual operation is sent through the batch if any,
+/// otherwise directly to the upstream server
+async fn execute_request(
+    request: GraphQLRequest,
+    context: &RequestContext<'_>,
+    batch: Option<&UpstreamBatch<'_>>,
+) -> ExecutionResult {
+    let format = context.format;
+    let proxy = context.proxy;
+
+    let parsed_operation;
+    let (operation, fragment_definitions) = match &proxy.trusted_documents {
+        // The trusted documents are parsed, validated and expanded when they are loaded
+        Some(trusted_documents) => {
+            let document = match trusted_documents.get(&request) {
+                Ok(d) => d,
+                Err(e) => return ExecutionResult::errors(StatusCode::BAD_REQUEST, vec![e.into()]),
+            };
+
+            match document.select_operation(request.operation_name.as_deref()) {
+                Ok(o) => (o.operation().clone(), vec![]),
+                Err(e) => return ExecutionResult::errors(format.request_error_status(), vec![e]),
             }
-        } else {
-            return Ok(format!("nein operationName"));
         }
-    } else {
-        (
-            document.operations.into_iter().nth(0).unwrap(),
-            document.fragment_definitions,
-        )
+        None => {
+            let q = match proxy
+                .persisted_queries
+                .resolve(request.query, &request.extensions)
+                .await
+            {
+                Ok(Some(q)) => q,
+                Ok(None) => {
+                    return ExecutionResult::errors(
+                        StatusCode::BAD_REQUEST,
+                        vec![Error::new(String::from("Must provide query string"))],
+                    )
+                }
+                Err(e) => return ExecutionResult::errors(e.status(), vec![e.into()]),
+            };
+
+            parsed_operation = match proxy.operations.get(
+                &q,
+                request.operation_name.as_deref(),
+                proxy.cache_context.schema.as_ref(),
+            ) {
+                Ok(o) => o,
+                Err(errors) => {
+                    return ExecutionResult::errors(format.request_error_status(), errors)
+                }
+            };
+
+            // The fragments are already expanded
+            (parsed_operation.operation().clone(), vec![])
+        }
     };
 
-    let result = match graphql::cache_handler::execute_operation(
+    // GET requests must be safe, only queries can be executed
+    if context.method == Method::GET && operation.operation_type != OperationType::Query {
+        return ExecutionResult::errors(
+            StatusCode::METHOD_NOT_ALLOWED,
+            vec![Error::new(String::from(
+                "Only queries can be executed with GET, use POST",
+            ))],
+        );
+    }
+
+    let result = graphql::cache_handler::execute_operation(
         operation,
         fragment_definitions,
-        variables,
-        cache,
-        auth_token,
-        |a, b| forward_graphql_request(a, b, auth_header_value),
+        request.variables,
+        proxy.cache.clone(),
+        &proxy.cache_context,
+        context.auth_token.clone(),
+        |operation, variables| async move {
+            match batch {
+                Some(batch) => batch.submit(operation, variables).await,
+                None => {
+                    proxy
+                        .upstream
+                        .forward_graphql_request(
+                            operation,
+                            variables,
+                            context.auth_header.clone(),
+                            context.headers,
+                        )
+                        .await
+                }
+            }
+        },
     )
-    .await
-    {
-        Ok(r) => format!("{}", r.to_string()),
-        Err(e) => format!("{:?}", e),
-    };
-
-    Ok(result)
+    .await;
+
+    match result {
+        // A response without data is a request error
+        Ok(r) => ExecutionResult {
+            status: match r.body.get("data") {
+                Some(_) => StatusCode::OK,
+                None => format.request_error_status(),
+            },
+            body: r.body,
+            cache_policy: Some(r.cache_policy),
+        },
+        Err(e @ Error::Upstream(_)) => ExecutionResult::errors(StatusCode::BAD_GATEWAY, vec![e]),
+        Err(e) => ExecutionResult::errors(format.request_error_status(), vec![e]),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::graphql;
     use super::graphql_deserializer::{CacheScope, GraphQLResponse};
+    use super::*;
     use futures::executor::block_on;
-    use serde_json::Value;
+    use serde_json::{json, Map, Value};
+    use warp::http::header::CONTENT_TYPE;
 
     #[test]
     fn test_parser() -> () {
@@ -361,6 +707,231 @@ mod tests {
         return Ok(());
     }
 
+    fn create_proxy() -> Arc<Proxy> {
+        // Nothing listens on the discard port, requests to the upstream server fail
+        let upstream_config =
+            serde_json::from_value(json!({"url": "http://127.0.0.1:9/"})).unwrap();
+
+        let cache: Arc<dyn CacheBackend> = Arc::new(graphql::cache::MemoryCache::new());
+
+        Arc::new(Proxy {
+            upstream: Upstream::new(&upstream_config).unwrap(),
+            trusted_documents: None,
+            operations: OperationCache::new(10),
+            persisted_queries: PersistedQueries::new(
+                &PersistedQueriesConfig::default(),
+                cache.clone(),
+            ),
+            cache: cache,
+            cache_context: CacheContext::default(),
+            cache_control_headers: true,
+            in_flight: InFlight::default(),
+        })
+    }
+
+    async fn send_request(
+        method: Method,
+        request: Result<RequestBody, RequestError>,
+        accept: Option<&'static str>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        send_proxy_request(create_proxy(), method, request, accept).await
+    }
+
+    async fn send_proxy_request(
+        proxy: Arc<Proxy>,
+        method: Method,
+        request: Result<RequestBody, RequestError>,
+        accept: Option<&'static str>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        }
+
+        let response = handle_request(None, method, request, headers, None, proxy)
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = warp::hyper::body::to_bytes(body).await.unwrap();
+
+        (
+            parts.status,
+            parts.headers,
+            serde_json::from_slice(&body).unwrap(),
+        )
+    }
+
+    fn graphql_request(query: &str) -> GraphQLRequest {
+        GraphQLRequest {
+            query: Some(query.to_string()),
+            variables: Map::new(),
+            operation_name: None,
+            extensions: Map::new(),
+            document_id: None,
+        }
+    }
+
+    fn query_request(query: &str) -> Result<RequestBody, RequestError> {
+        Ok(RequestBody::Single(graphql_request(query)))
+    }
+
+    #[tokio::test]
+    async fn request_errors_use_the_negotiated_status() {
+        let (status, headers, body) = send_request(
+            Method::POST,
+            query_request("{ field1 "),
+            Some(GRAPHQL_RESPONSE_JSON),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            headers[CONTENT_TYPE],
+            "application/graphql-response+json; charset=utf-8"
+        );
+        assert!(body["errors"][0]["message"].is_string());
+
+        let (status, headers, _) =
+            send_request(Method::POST, query_request("{ field1 "), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], "application/json; charset=utf-8");
+
+        let (status, _, _) =
+            send_request(Method::POST, query_request("{ field1 }"), Some("text/html")).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+
+        let missing_query =
+            GraphQLRequest::from_query_params(HashMap::new()).map(RequestBody::Single);
+        let (status, _, body) = send_request(Method::GET, missing_query, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({"errors": [{"message": "Must provide query string"}]})
+        );
+
+        let invalid_body = GraphQLRequest::from_body(Some("text/plain"), b"{ field1 }");
+        let (status, _, _) = send_request(Method::POST, invalid_body, None).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn mutations_are_not_allowed_with_get() {
+        let (status, headers, _) =
+            send_request(Method::GET, query_request("mutation { field1 }"), None).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(headers[ALLOW], "POST");
+    }
+
+    #[tokio::test]
+    async fn upstream_failures_are_bad_gateway() {
+        let (status, _, body) = send_request(Method::GET, query_request("{ field1 }"), None).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Request error"));
+    }
+
+    #[tokio::test]
+    async fn batches_are_answered_in_order() {
+        let batch = Ok(RequestBody::Batch(vec![
+            graphql_request("{ field1 }"),
+            graphql_request("{ field1 "),
+            graphql_request("{ field2 }"),
+        ]));
+        let (status, headers, body) = send_request(Method::POST, batch, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(CACHE_CONTROL).is_none());
+
+        let body = body.as_array().unwrap();
+        assert_eq!(body.len(), 3);
+        // The upstream server is unreachable, the batch sent for the first and last entries fails
+        assert!(body[0]["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Request error"));
+        assert!(body[1]["errors"][0]["message"].is_string());
+        assert_eq!(body[2], body[0]);
+    }
+
+    #[tokio::test]
+    async fn persisted_queries_are_registered_and_executed_with_get() {
+        let proxy = create_proxy();
+        let hash = "fdd4227ac475f777eec387dbc1a954a43910741524bef5f05d5e4cc59d56d979";
+        let extensions = json!({"persistedQuery": {"version": 1, "sha256Hash": hash}});
+        let get_request = || {
+            let params = HashMap::from([(String::from("extensions"), extensions.to_string())]);
+            GraphQLRequest::from_query_params(params).map(RequestBody::Single)
+        };
+
+        let (status, _, body) =
+            send_proxy_request(proxy.clone(), Method::GET, get_request(), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"errors": [{
+                "message": "PersistedQueryNotFound",
+                "extensions": {"code": "PERSISTED_QUERY_NOT_FOUND"}
+            }]})
+        );
+
+        let mut request = graphql_request("{ field1 }");
+        request.extensions = extensions.as_object().unwrap().clone();
+        let (status, _, _) = send_proxy_request(
+            proxy.clone(),
+            Method::POST,
+            Ok(RequestBody::Single(request)),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        // The registered query is sent to the upstream server, which is unreachable
+        let (status, _, _) = send_proxy_request(proxy, Method::GET, get_request(), None).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn only_trusted_documents_are_executed() {
+        let manifest = json!({"abc": "{ field1 }"});
+        let mut proxy = Arc::try_unwrap(create_proxy()).ok().unwrap();
+        proxy.trusted_documents = Some(TrustedDocuments::from_manifest(manifest, None).unwrap());
+        let proxy = Arc::new(proxy);
+
+        let (status, _, body) = send_proxy_request(
+            proxy.clone(),
+            Method::POST,
+            query_request("{ field1 }"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "PERSISTED_QUERY_ID_REQUIRED"
+        );
+
+        let mut request = graphql_request("{ field2 }");
+        request.document_id = Some(String::from("def"));
+        let (status, _, body) = send_proxy_request(
+            proxy.clone(),
+            Method::POST,
+            Ok(RequestBody::Single(request)),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "PERSISTED_QUERY_NOT_IN_LIST"
+        );
+
+        // The trusted document is sent to the upstream server, which is unreachable
+        let params = HashMap::from([(String::from("documentId"), String::from("abc"))]);
+        let request = GraphQLRequest::from_query_params(params).map(RequestBody::Single);
+        let (status, _, _) = send_proxy_request(proxy, Method::GET, request, None).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
     #[cfg(feature = "slow_tests")]
     mod slow_tests {
         use super::super::graphql;
@@ -472,7 +1043,7 @@ mod tests {
 
             match cache.get(&String::from("adsasd0")).await {
                 Some(vec) => {
-                    let value = &vec[0];
+                    let value = &vec[0].value;
                     assert_eq!(value.to_string(), vc.to_string())
                 }
                 _ => assert_eq!(1, 0),
@@ -0,0 +1,259 @@
+mod trusted_documents;
+
+pub use trusted_documents::TrustedDocuments;
+
+use crate::graphql::cache::CacheBackend;
+use crate::graphql::parser::Error;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use warp::http::StatusCode;
+
+#[derive(Debug, Deserialize)]
+pub struct PersistedQueriesConfig {
+    /// Accepts the hash of a query registered by a previous request
+    /// in place of the query (Automatic Persisted Queries)
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// How long a registered query is kept in the cache
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u16,
+    /// Manifest of the trusted documents (Apollo or Relay format). When set, only
+    /// the documents of the manifest can be executed, ad-hoc queries are rejected
+    pub manifest_file: Option<String>,
+}
+
+impl Default for PersistedQueriesConfig {
+    fn default() -> PersistedQueriesConfig {
+        PersistedQueriesConfig {
+            enabled: default_enabled(),
+            ttl_seconds: default_ttl_seconds(),
+            manifest_file: None,
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_ttl_seconds() -> u16 {
+    43200
+}
+
+/// Errors of the APQ protocol and of the trusted documents.
+/// The messages and codes are the ones Apollo clients check
+#[derive(Debug, PartialEq)]
+pub enum PersistedQueryError {
+    /// The hash is not registered, the client sends the query along with the hash
+    NotFound,
+    NotSupported,
+    UnsupportedVersion,
+    InvalidHash,
+    HashMismatch,
+    /// Only trusted documents can be executed, the request must refer to one
+    IdRequired,
+    NotInList,
+}
+
+impl PersistedQueryError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            // Apollo clients retry with the query on a 200
+            PersistedQueryError::NotFound | PersistedQueryError::NotSupported => StatusCode::OK,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl From<PersistedQueryError> for Error {
+    fn from(error: PersistedQueryError) -> Error {
+        let (message, code) = match error {
+            PersistedQueryError::NotFound => {
+                ("PersistedQueryNotFound", "PERSISTED_QUERY_NOT_FOUND")
+            }
+            PersistedQueryError::NotSupported => (
+                "PersistedQueryNotSupported",
+                "PERSISTED_QUERY_NOT_SUPPORTED",
+            ),
+            PersistedQueryError::UnsupportedVersion => {
+                ("Unsupported persisted query version", "BAD_USER_INPUT")
+            }
+            PersistedQueryError::InvalidHash => {
+                ("sha256Hash must be a hex encoded SHA-256", "BAD_USER_INPUT")
+            }
+            PersistedQueryError::HashMismatch => {
+                ("provided sha does not match query", "BAD_USER_INPUT")
+            }
+            PersistedQueryError::IdRequired => (
+                "Only trusted documents can be executed, send a documentId",
+                "PERSISTED_QUERY_ID_REQUIRED",
+            ),
+            PersistedQueryError::NotInList => (
+                "The document is not in the list of trusted documents",
+                "PERSISTED_QUERY_NOT_IN_LIST",
+            ),
+        };
+
+        Error::Request {
+            message: message.to_string(),
+            code: code,
+        }
+    }
+}
+
+/// Store of the queries registered with the APQ protocol, kept in the cache
+pub struct PersistedQueries {
+    enabled: bool,
+    ttl_seconds: u16,
+    cache: Arc<dyn CacheBackend>,
+}
+
+impl PersistedQueries {
+    pub fn new(config: &PersistedQueriesConfig, cache: Arc<dyn CacheBackend>) -> PersistedQueries {
+        PersistedQueries {
+            enabled: config.enabled,
+            ttl_seconds: config.ttl_seconds,
+            cache: cache,
+        }
+    }
+
+    /// Returns the query of the request. When the request has a
+    /// `persistedQuery` extension, the query is read from the store if
+    /// missing, otherwise its hash is verified and it is registered.
+    pub async fn resolve(
+        &self,
+        query: Option<String>,
+        extensions: &Map<String, Value>,
+    ) -> Result<Option<String>, PersistedQueryError> {
+        let persisted_query = match extensions.get("persistedQuery") {
+            Some(p) => p,
+            None => return Ok(query),
+        };
+
+        if !self.enabled {
+            return Err(PersistedQueryError::NotSupported);
+        }
+
+        match persisted_query.get("version") {
+            Some(v) if v == 1 => {}
+            _ => return Err(PersistedQueryError::UnsupportedVersion),
+        }
+
+        let hash = match persisted_query_hash(extensions) {
+            Some(h) if h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()) => {
+                h.to_ascii_lowercase()
+            }
+            _ => return Err(PersistedQueryError::InvalidHash),
+        };
+        let key = cache_key(&hash);
+
+        match query {
+            Some(query) => {
+                if hex::encode(Sha256::digest(query.as_bytes())) != hash {
+                    return Err(PersistedQueryError::HashMismatch);
+                }
+
+                // The query can still be executed if it can't be registered
+                if let Err(e) = self
+                    .cache
+                    .insert(key, self.ttl_seconds, Value::String(query.clone()))
+                    .await
+                {
+                    println!("Unable to register the persisted query {}: {:?}", hash, e);
+                }
+
+                Ok(Some(query))
+            }
+            None => match self.cache.get(&key).await {
+                Some(values) => match values.last().map(|v| &v.value) {
+                    Some(Value::String(query)) => Ok(Some(query.clone())),
+                    _ => Err(PersistedQueryError::NotFound),
+                },
+                None => Err(PersistedQueryError::NotFound),
+            },
+        }
+    }
+}
+
+/// Returns the `sha256Hash` of the `persistedQuery` extension
+fn persisted_query_hash(extensions: &Map<String, Value>) -> Option<&str> {
+    extensions
+        .get("persistedQuery")
+        .and_then(|p| p.get("sha256Hash"))
+        .and_then(|h| h.as_str())
+}
+
+/// Field names can't contain `:`, the keys don't collide with the ones of the fields
+fn cache_key(hash: &str) -> String {
+    format!("apq:{}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PersistedQueries, PersistedQueriesConfig, PersistedQueryError};
+    use crate::graphql::cache::MemoryCache;
+    use futures::executor::block_on;
+    use serde_json::{json, Map, Value};
+    use std::sync::Arc;
+
+    const QUERY: &str = "{ field1 }";
+    const HASH: &str = "fdd4227ac475f777eec387dbc1a954a43910741524bef5f05d5e4cc59d56d979";
+
+    fn extensions(hash: &str) -> Map<String, Value> {
+        match json!({"persistedQuery": {"version": 1, "sha256Hash": hash}}) {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn queries_are_registered_and_resolved() {
+        let store = PersistedQueries::new(
+            &PersistedQueriesConfig::default(),
+            Arc::new(MemoryCache::new()),
+        );
+
+        assert_eq!(
+            block_on(store.resolve(None, &extensions(HASH))),
+            Err(PersistedQueryError::NotFound)
+        );
+        assert_eq!(
+            block_on(store.resolve(Some(String::from("{ field2 }")), &extensions(HASH))),
+            Err(PersistedQueryError::HashMismatch)
+        );
+        assert_eq!(
+            block_on(store.resolve(Some(QUERY.to_string()), &extensions(HASH))),
+            Ok(Some(QUERY.to_string()))
+        );
+        assert_eq!(
+            block_on(store.resolve(None, &extensions(&HASH.to_ascii_uppercase()))),
+            Ok(Some(QUERY.to_string()))
+        );
+        assert_eq!(
+            block_on(store.resolve(None, &extensions("abc"))),
+            Err(PersistedQueryError::InvalidHash)
+        );
+        assert_eq!(block_on(store.resolve(None, &Map::new())), Ok(None));
+    }
+
+    #[test]
+    fn persisted_queries_can_be_disabled() {
+        let config = PersistedQueriesConfig {
+            enabled: false,
+            ttl_seconds: 60,
+            manifest_file: None,
+        };
+        let store = PersistedQueries::new(&config, Arc::new(MemoryCache::new()));
+
+        assert_eq!(
+            block_on(store.resolve(Some(QUERY.to_string()), &extensions(HASH))),
+            Err(PersistedQueryError::NotSupported)
+        );
+        assert_eq!(
+            block_on(store.resolve(Some(QUERY.to_string()), &Map::new())),
+            Ok(Some(QUERY.to_string()))
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::{persisted_query_hash, PersistedQueryError};
+use crate::graphql;
+use crate::graphql::operation_cache::ParsedOperation;
+use crate::graphql::parser::Error;
+use crate::graphql::schema::Schema;
+use crate::graphql_over_http::GraphQLRequest;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The operations which can be executed when the proxy runs in allowlist mode.
+/// The documents are parsed, validated and expanded once, when the manifest is loaded.
+pub struct TrustedDocuments {
+    documents: HashMap<String, TrustedDocument>,
+}
+
+/// The operations of a trusted document, with their fragments expanded
+pub struct TrustedDocument {
+    operations: Vec<ParsedOperation>,
+}
+
+impl TrustedDocument {
+    /// Returns the operation to execute.
+    /// The name is required when the document contains multiple operations
+    pub fn select_operation(
+        &self,
+        operation_name: Option<&str>,
+    ) -> Result<&ParsedOperation, Error> {
+        if self.operations.len() == 1 {
+            return Ok(&self.operations[0]);
+        }
+
+        match operation_name {
+            Some(operation_name) => self
+                .operations
+                .iter()
+                .find(|o| o.operation().name == Some(operation_name))
+                .ok_or_else(|| Error::UnknownOperation {
+                    name: operation_name.to_string(),
+                }),
+            None => Err(Error::new(String::from(
+                "Must provide operation name if query contains multiple operations",
+            ))),
+        }
+    }
+}
+
+impl TrustedDocuments {
+    /// Loads a manifest in the Apollo format
+    /// (`{"format": "apollo-persisted-query-manifest", "operations": [{"id", "body"}]}`)
+    /// or in the Relay format (`{"<id>": "<document>"}`)
+    pub fn load(path: &Path, schema: Option<&Schema>) -> Result<TrustedDocuments, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let manifest: Value =
+            serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        TrustedDocuments::from_manifest(manifest, schema)
+    }
+
+    pub fn from_manifest(
+        manifest: Value,
+        schema: Option<&Schema>,
+    ) -> Result<TrustedDocuments, String> {
+        let entries = match manifest {
+            Value::Object(mut map) if map.contains_key("format") => {
+                match map.remove("format") {
+                    Some(Value::String(f)) if f == "apollo-persisted-query-manifest" => {}
+                    f => return Err(format!("Unsupported manifest format {:?}", f)),
+                }
+
+                let operations = match map.remove("operations") {
+                    Some(Value::Array(o)) => o,
+                    _ => return Err(String::from("The manifest has no operations")),
+                };
+
+                operations
+                    .into_iter()
+                    .map(|operation| match (&operation["id"], &operation["body"]) {
+                        (Value::String(id), Value::String(body)) => Ok((id.clone(), body.clone())),
+                        _ => Err(format!("Invalid operation {}", operation)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            Value::Object(map) => map
+                .into_iter()
+                .map(|(id, body)| match body {
+                    Value::String(body) => Ok((id, body)),
+                    _ => Err(format!("The document {} is not a string", id)),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(String::from("The manifest must be a JSON object")),
+        };
+
+        let mut documents = HashMap::new();
+        for (id, body) in entries {
+            let invalid_document = |errors: Vec<Error>| {
+                let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                format!("Invalid document {}: {}", id, errors.join(", "))
+            };
+
+            let document =
+                graphql::validation::parse_and_validate(&body, schema).map_err(invalid_document)?;
+            if document.operations.len() == 0 {
+                return Err(invalid_document(vec![Error::new(String::from(
+                    "Must provide an operation",
+                ))]));
+            }
+
+            // Each operation owns a copy of the document
+            let operations = document
+                .operations
+                .iter()
+                .map(|o| ParsedOperation::parse(body.clone(), o.name, schema))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid_document)?;
+
+            documents.insert(
+                id.clone(),
+                TrustedDocument {
+                    operations: operations,
+                },
+            );
+        }
+
+        Ok(TrustedDocuments {
+            documents: documents,
+        })
+    }
+
+    /// Returns the document the request refers to, with `documentId` or the
+    /// APQ hash. Requests with a query and no id are rejected.
+    pub fn get(&self, request: &GraphQLRequest) -> Result<&TrustedDocument, PersistedQueryError> {
+        let id = match request.document_id.as_deref() {
+            Some(id) => id,
+            None => match persisted_query_hash(&request.extensions) {
+                Some(id) => id,
+                None => return Err(PersistedQueryError::IdRequired),
+            },
+        };
+
+        self.documents.get(id).ok_or(PersistedQueryError::NotInList)
+    }
+
+    pub fn count(&self) -> usize {
+        self.documents.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrustedDocuments;
+    use crate::graphql_over_http::GraphQLRequest;
+    use crate::persisted_queries::PersistedQueryError;
+    use serde_json::{json, Map};
+
+    fn request(
+        document_id: Option<&str>,
+        extensions: Map<String, serde_json::Value>,
+    ) -> GraphQLRequest {
+        GraphQLRequest {
+            query: Some(String::from("{ adhoc }")),
+            variables: Map::new(),
+            operation_name: None,
+            extensions: extensions,
+            document_id: document_id.map(String::from),
+        }
+    }
+
+    #[test]
+    fn apollo_manifests_are_loaded() {
+        let manifest = json!({
+            "format": "apollo-persisted-query-manifest",
+            "version": 1,
+            "operations": [
+                {"id": "abc", "name": "Op", "type": "query", "body": "query Op { field1 }"}
+            ]
+        });
+        let documents = TrustedDocuments::from_manifest(manifest, None).unwrap();
+        assert_eq!(documents.count(), 1);
+
+        let extensions = json!({"persistedQuery": {"version": 1, "sha256Hash": "abc"}});
+        let document = documents
+            .get(&request(None, extensions.as_object().unwrap().clone()))
+            .unwrap();
+        let operation = document.select_operation(Some("Other")).unwrap();
+        assert_eq!(operation.operation().name, Some("Op"));
+
+        assert_eq!(
+            documents.get(&request(None, Map::new())).err(),
+            Some(PersistedQueryError::IdRequired)
+        );
+        assert_eq!(
+            documents.get(&request(Some("def"), Map::new())).err(),
+            Some(PersistedQueryError::NotInList)
+        );
+    }
+
+    #[test]
+    fn relay_manifests_are_loaded() {
+        let manifest = json!({"abc": "{ field1 }", "def": "{ field2 { id } }"});
+        let documents = TrustedDocuments::from_manifest(manifest, None).unwrap();
+        assert_eq!(documents.count(), 2);
+        assert!(documents.get(&request(Some("def"), Map::new())).is_ok());
+
+        let manifest = json!({"abc": "{ field1 "});
+        assert!(TrustedDocuments::from_manifest(manifest, None)
+            .err()
+            .unwrap()
+            .starts_with("Invalid document abc"));
+    }
+
+    #[test]
+    fn operations_are_expanded_when_loaded() {
+        let manifest = json!({
+            "abc": "query Op1 { field1 { ...fields } } query Op2 { field2 } fragment fields on T { id }"
+        });
+        let documents = TrustedDocuments::from_manifest(manifest, None).unwrap();
+        let document = documents.get(&request(Some("abc"), Map::new())).unwrap();
+
+        let operation = document.select_operation(Some("Op1")).unwrap().operation();
+        assert_eq!(operation.name, Some("Op1"));
+        assert_eq!(operation.fields[0].get_subfields()[0].get_name(), "id");
+        assert_eq!(
+            document
+                .select_operation(Some("Op2"))
+                .unwrap()
+                .operation()
+                .name,
+            Some("Op2")
+        );
+        assert!(document.select_operation(None).is_err());
+        assert!(document.select_operation(Some("Op3")).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use futures::channel::mpsc;
+use futures::future::{join_all, BoxFuture};
+use futures::{FutureExt, StreamExt};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Notify};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use warp::filters::BoxedFilter;
+use warp::Filter;
+
+pub type Routes = BoxedFilter<(warp::reply::Response,)>;
+
+#[derive(Debug, Deserialize)]
+pub struct ListenerConfig {
+    /// Address and port to listen on, e.g. `0.0.0.0:3033`
+    pub address: SocketAddr,
+    /// Terminates TLS on this listener
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain
+    pub certificate_file: String,
+    /// PEM file with the private key (PKCS#8, RSA or EC)
+    pub key_file: String,
+    /// Connections which don't complete the handshake in time are closed
+    #[serde(default = "default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+}
+
+fn default_handshake_timeout_ms() -> u64 {
+    10000
+}
+
+pub fn default_listeners() -> Vec<ListenerConfig> {
+    vec![ListenerConfig {
+        address: SocketAddr::from(([0, 0, 0, 0], 3033)),
+        tls: None,
+    }]
+}
+
+pub fn default_graphql_path() -> String {
+    String::from("/graphql")
+}
+
+/// Signals the servers to stop accepting connections. The servers
+/// complete once the requests in progress have been answered.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (sender, receiver) = watch::channel(false);
+
+        Shutdown {
+            sender: Arc::new(sender),
+            receiver: receiver,
+        }
+    }
+
+    pub fn trigger(&self) {
+        let _ = self.sender.send(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Completes when the shutdown is triggered
+    pub async fn wait(mut self) {
+        while !*self.receiver.borrow() {
+            if self.receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Triggers `shutdown` when the process receives SIGTERM or Ctrl+C
+pub async fn shutdown_on_signal(shutdown: Shutdown) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+            _ = shutdown.clone().wait() => return,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = shutdown.clone().wait() => return,
+        }
+    }
+
+    println!("Shutdown signal received");
+    shutdown.trigger();
+}
+
+/// Counts the requests being processed, so that the shutdown can wait for them
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<(AtomicUsize, Notify)>,
+}
+
+/// Marks a request as completed when dropped
+pub struct InFlightGuard {
+    in_flight: InFlight,
+}
+
+impl InFlight {
+    pub fn start(&self) -> InFlightGuard {
+        self.inner.0.fetch_add(1, Ordering::SeqCst);
+
+        InFlightGuard {
+            in_flight: self.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    /// Completes when no request is being processed
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.inner.1.notified();
+            if self.count() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.in_flight.inner.0.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.in_flight.inner.1.notify_waiters();
+        }
+    }
+}
+
+/// Matches the requests to `path`, e.g. `/graphql` or `/api/graphql`
+pub fn path_filter(path: &str) -> BoxedFilter<()> {
+    path.split('/')
+        .filter(|segment| segment.len() > 0)
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.to_string())).boxed()
+        })
+        .and(warp::path::end())
+        .boxed()
+}
+
+/// Binds all the listeners. The returned future serves `routes` on all of them,
+/// until `shutdown` is triggered and the requests in progress are completed.
+pub async fn bind(
+    listeners: &[ListenerConfig],
+    routes: Routes,
+    shutdown: &Shutdown,
+) -> Result<BoxFuture<'static, ()>, String> {
+    if listeners.len() == 0 {
+        return Err(String::from("No listener configured"));
+    }
+
+    let mut servers = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        let server = match &listener.tls {
+            Some(tls) => bind_tls(listener.address, tls, routes.clone(), shutdown).await?,
+            None => match warp::serve(routes.clone())
+                .try_bind_with_graceful_shutdown(listener.address, shutdown.clone().wait())
+            {
+                Ok((_, server)) => server.boxed(),
+                Err(e) => return Err(format!("Unable to bind {}: {}", listener.address, e)),
+            },
+        };
+
+        println!(
+            "Listening on {}{}",
+            listener.address,
+            if listener.tls.is_some() { " (TLS)" } else { "" }
+        );
+        servers.push(server);
+    }
+
+    Ok(join_all(servers).map(|_| ()).boxed())
+}
+
+async fn bind_tls(
+    address: SocketAddr,
+    config: &TlsConfig,
+    routes: Routes,
+    shutdown: &Shutdown,
+) -> Result<BoxFuture<'static, ()>, String> {
+    let acceptor = tls_acceptor(config)?;
+    let handshake_timeout = Duration::from_millis(config.handshake_timeout_ms);
+    let listener = match TcpListener::bind(address).await {
+        Ok(l) => l,
+        Err(e) => return Err(format!("Unable to bind {}: {}", address, e)),
+    };
+
+    // The handshakes are done in their own tasks, so that a slow client
+    // doesn't prevent the other connections from being accepted
+    let (sender, receiver) = mpsc::unbounded();
+    let accept_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = accept_shutdown.clone().wait() => break,
+            };
+
+            match accepted {
+                Ok((stream, _)) => {
+                    let acceptor = acceptor.clone();
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await
+                        {
+                            Ok(Ok(stream)) => {
+                                let _ = sender.unbounded_send(stream);
+                            }
+                            Ok(Err(e)) => println!("TLS handshake error: {}", e),
+                            Err(_) => println!("TLS handshake timed out"),
+                        }
+                    });
+                }
+                Err(e) => {
+                    println!("Accept error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    });
+
+    Ok(warp::serve(routes)
+        .serve_incoming_with_graceful_shutdown(
+            receiver.map(Ok::<_, std::io::Error>),
+            shutdown.clone().wait(),
+        )
+        .boxed())
+}
+
+fn tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let certificates = load_certificates(&config.certificate_file)?;
+    let key = load_private_key(&config.key_file)?;
+
+    let mut server_config = match ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+    {
+        Ok(c) => c,
+        Err(e) => return Err(format!("Invalid TLS configuration: {}", e)),
+    };
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    let mut reader = open_pem_file(path)?;
+    match rustls_pemfile::certs(&mut reader) {
+        Ok(certificates) if certificates.len() > 0 => {
+            Ok(certificates.into_iter().map(Certificate).collect())
+        }
+        Ok(_) => Err(format!("No certificate found in {}", path)),
+        Err(e) => Err(format!("Unable to read {}: {}", path, e)),
+    }
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    let mut reader = open_pem_file(path)?;
+    let items = match rustls_pemfile::read_all(&mut reader) {
+        Ok(items) => items,
+        Err(e) => return Err(format!("Unable to read {}: {}", path, e)),
+    };
+
+    for item in items {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    Err(format!("No private key found in {}", path))
+}
+
+fn open_pem_file(path: &str) -> Result<BufReader<File>, String> {
+    match File::open(path) {
+        Ok(f) => Ok(BufReader::new(f)),
+        Err(e) => Err(format!("Unable to open {}: {}", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_listeners, path_filter, tls_acceptor, InFlight, ListenerConfig, Shutdown, TlsConfig,
+    };
+    use futures::executor::block_on;
+    use futures::FutureExt;
+    use serde_json::json;
+    use std::io::Write;
+
+    #[test]
+    fn listeners_are_parsed() {
+        let listeners: Vec<ListenerConfig> = serde_json::from_value(json!([
+            {"address": "0.0.0.0:443", "tls": {"certificate_file": "cert.pem", "key_file": "key.pem"}},
+            {"address": "127.0.0.1:3033"}
+        ]))
+        .unwrap();
+
+        assert_eq!(listeners[0].address.port(), 443);
+        assert_eq!(
+            listeners[0].tls.as_ref().unwrap().certificate_file,
+            "cert.pem"
+        );
+        assert_eq!(listeners[1].address.to_string(), "127.0.0.1:3033");
+        assert!(listeners[1].tls.is_none());
+
+        assert_eq!(default_listeners()[0].address.to_string(), "0.0.0.0:3033");
+    }
+
+    #[test]
+    fn path_filter_matches_whole_path() {
+        let filter = path_filter("/api/graphql/");
+
+        assert!(block_on(
+            warp::test::request().path("/api/graphql").matches(&filter)
+        ));
+        assert!(!block_on(
+            warp::test::request().path("/api").matches(&filter)
+        ));
+        assert!(!block_on(
+            warp::test::request()
+                .path("/api/graphql/other")
+                .matches(&filter)
+        ));
+        assert!(!block_on(
+            warp::test::request().path("/graphql").matches(&filter)
+        ));
+    }
+
+    #[test]
+    fn tls_configuration_errors_are_reported() {
+        let missing = TlsConfig {
+            certificate_file: String::from("/nonexistent/cert.pem"),
+            key_file: String::from("/nonexistent/key.pem"),
+            handshake_timeout_ms: 10000,
+        };
+        assert!(tls_acceptor(&missing).is_err());
+
+        let path = std::env::temp_dir().join("graphql_cache_empty.pem");
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(b"not a pem file").unwrap();
+        let empty = TlsConfig {
+            certificate_file: path.to_string_lossy().to_string(),
+            key_file: path.to_string_lossy().to_string(),
+            handshake_timeout_ms: 10000,
+        };
+        assert_eq!(
+            tls_acceptor(&empty).err().unwrap(),
+            format!("No certificate found in {}", path.to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn in_flight_requests_are_awaited() {
+        let in_flight = InFlight::default();
+        assert!(in_flight.wait_idle().now_or_never().is_some());
+
+        let first = in_flight.start();
+        let second = in_flight.start();
+        assert_eq!(in_flight.count(), 2);
+
+        let mut idle = Box::pin(in_flight.wait_idle());
+        assert!((&mut idle).now_or_never().is_none());
+
+        drop(first);
+        assert!((&mut idle).now_or_never().is_none());
+
+        drop(second);
+        assert_eq!(in_flight.count(), 0);
+        assert!(idle.now_or_never().is_some());
+    }
+
+    #[test]
+    fn shutdown_completes_waiters() {
+        let shutdown = Shutdown::new();
+        let mut wait = Box::pin(shutdown.clone().wait());
+        assert!((&mut wait).now_or_never().is_none());
+
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+        assert!(wait.now_or_never().is_some());
+        assert!(shutdown.clone().wait().now_or_never().is_some());
+    }
+}
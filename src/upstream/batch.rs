@@ -0,0 +1,164 @@
+use super::{request_payload, Upstream};
+use crate::graphql::parser::{Error, Operation};
+use crate::graphql_deserializer::UpstreamResponse;
+use futures::channel::oneshot;
+use reqwest::header::HeaderMap;
+use serde_json::{Map, Value};
+use std::mem;
+use std::sync::Mutex;
+
+type PendingRequest = (
+    Map<String, Value>,
+    oneshot::Sender<Result<UpstreamResponse, Error>>,
+);
+
+/// Collects the residual operations of the entries of a batch, so that they are
+/// sent to the upstream server in a single request. The request is sent once each
+/// entry has either submitted its operation or completed without needing one.
+pub struct UpstreamBatch<'u> {
+    upstream: &'u Upstream,
+    auth_header: Option<String>,
+    client_headers: &'u HeaderMap,
+    entries: usize,
+    state: Mutex<BatchState>,
+}
+
+struct BatchState {
+    pending: Vec<PendingRequest>,
+    completed: usize,
+}
+
+impl<'u> UpstreamBatch<'u> {
+    pub fn new(
+        upstream: &'u Upstream,
+        auth_header: Option<String>,
+        client_headers: &'u HeaderMap,
+        entries: usize,
+    ) -> UpstreamBatch<'u> {
+        UpstreamBatch {
+            upstream: upstream,
+            auth_header: auth_header,
+            client_headers: client_headers,
+            entries: entries,
+            state: Mutex::new(BatchState {
+                pending: vec![],
+                completed: 0,
+            }),
+        }
+    }
+
+    /// Adds the operation to the batch, and waits for its response
+    pub async fn submit<'a>(
+        &self,
+        operation: Operation<'a>,
+        variables: Map<String, Value>,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
+        let mut payload = request_payload(&operation, variables);
+        let variables = match payload.get("variables") {
+            Some(Value::Object(v)) => v.clone(),
+            _ => Map::new(),
+        };
+
+        let (sender, receiver) = oneshot::channel();
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.pending.push((mem::take(&mut payload), sender));
+            state.pending.len() + state.completed == self.entries
+        };
+
+        if ready {
+            self.flush().await;
+        }
+
+        let result = match receiver.await {
+            Ok(r) => r,
+            Err(_) => Err(Error::Upstream(String::from("The batch was not sent"))),
+        };
+
+        (result, operation, variables)
+    }
+
+    /// Marks an entry as completed. Must be called once by each entry, when it's done
+    pub async fn complete(&self) {
+        let ready = {
+            let mut state = self.state.lock().unwrap();
+            state.completed += 1;
+            state.pending.len() > 0 && state.pending.len() + state.completed == self.entries
+        };
+
+        if ready {
+            self.flush().await;
+        }
+    }
+
+    async fn flush(&self) {
+        let pending = mem::take(&mut self.state.lock().unwrap().pending);
+        let (requests, senders): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+
+        // A single operation doesn't need to be batched
+        let results = if requests.len() == 1 {
+            let request = requests.into_iter().next().unwrap();
+            vec![
+                self.upstream
+                    .send(&request, self.auth_header.clone(), self.client_headers)
+                    .await,
+            ]
+        } else {
+            self.upstream
+                .forward_graphql_batch(requests, self.auth_header.clone(), self.client_headers)
+                .await
+        };
+
+        for (sender, result) in senders.into_iter().zip(results) {
+            // The entry may have been dropped, its response is not needed anymore
+            let _ = sender.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UpstreamBatch;
+    use crate::graphql::parser::{parse_query, Error};
+    use crate::upstream::{Upstream, UpstreamConfig};
+    use futures::future::join3;
+    use reqwest::header::HeaderMap;
+    use serde_json::{json, Map};
+
+    #[tokio::test]
+    async fn batch_is_sent_once_all_entries_are_ready() {
+        // Nothing listens on the discard port, the batch fails as a whole
+        let config: UpstreamConfig =
+            serde_json::from_value(json!({"url": "http://127.0.0.1:9/"})).unwrap();
+        let upstream = Upstream::new(&config).unwrap();
+        let headers = HeaderMap::new();
+        let batch = UpstreamBatch::new(&upstream, None, &headers, 3);
+
+        let document1 = parse_query("{ field1 }").unwrap();
+        let document2 = parse_query("{ field2 }").unwrap();
+        let entry1 = async {
+            let operation = document1.operations.into_iter().next().unwrap();
+            let (result, operation, _) = batch.submit(operation, Map::new()).await;
+            batch.complete().await;
+            (result, operation.fields[0].get_name().to_string())
+        };
+        let entry2 = async {
+            let operation = document2.operations.into_iter().next().unwrap();
+            let (result, operation, _) = batch.submit(operation, Map::new()).await;
+            batch.complete().await;
+            (result, operation.fields[0].get_name().to_string())
+        };
+        // Served from the cache
+        let entry3 = batch.complete();
+
+        let ((result1, name1), (result2, name2), _) = join3(entry1, entry2, entry3).await;
+        assert_eq!(name1, "field1");
+        assert_eq!(name2, "field2");
+        assert!(matches!(result1, Err(Error::Upstream(_))));
+        assert!(matches!(result2, Err(Error::Upstream(_))));
+    }
+}
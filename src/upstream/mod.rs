@@ -0,0 +1,277 @@
+mod batch;
+
+pub use batch::UpstreamBatch;
+
+use crate::graphql::parser::{serialize_operation, Error, Operation};
+use crate::graphql_deserializer::UpstreamResponse;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CACHE_CONTROL};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+pub struct UpstreamConfig {
+    /// URL of the GraphQL endpoint of the upstream server
+    pub url: String,
+    pub connect_timeout_ms: Option<u64>,
+    /// Timeout of the whole request, including the response body
+    pub timeout_ms: Option<u64>,
+    /// Maximum number of idle connections kept in the pool
+    pub max_idle_connections: Option<usize>,
+    /// Headers added to every request
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Headers copied from the client request
+    #[serde(default)]
+    pub forwarded_headers: Vec<String>,
+    /// How the residual operations of a batch are sent
+    #[serde(default)]
+    pub batching: Batching,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Batching {
+    /// A single upstream request, with a JSON array of operations
+    #[default]
+    Batched,
+    /// An upstream request for each operation, sent in parallel
+    Parallel,
+}
+
+/// The upstream GraphQL server. A single client is shared
+/// by all the requests, so that connections are reused.
+pub struct Upstream {
+    client: reqwest::Client,
+    url: String,
+    forwarded_headers: Vec<HeaderName>,
+    pub batching: Batching,
+}
+
+impl Upstream {
+    pub fn new(config: &UpstreamConfig) -> Result<Upstream, String> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in config.headers.iter() {
+            default_headers.insert(parse_header_name(name)?, parse_header_value(value)?);
+        }
+
+        let mut builder = reqwest::Client::builder().default_headers(default_headers);
+        if let Some(connect_timeout) = config.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(connect_timeout));
+        }
+        if let Some(timeout) = config.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(timeout));
+        }
+        if let Some(max_idle_connections) = config.max_idle_connections {
+            builder = builder.pool_max_idle_per_host(max_idle_connections);
+        }
+
+        let client = match builder.build() {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Unable to create the upstream client: {}", e)),
+        };
+
+        let mut forwarded_headers = Vec::with_capacity(config.forwarded_headers.len());
+        for name in config.forwarded_headers.iter() {
+            forwarded_headers.push(parse_header_name(name)?);
+        }
+
+        Ok(Upstream {
+            client: client,
+            url: config.url.clone(),
+            forwarded_headers: forwarded_headers,
+            batching: config.batching,
+        })
+    }
+
+    /// Sends the operation to the upstream server. The authorization header and the
+    /// forwarded headers of the client request are added to the upstream request.
+    pub async fn forward_graphql_request<'a>(
+        &self,
+        operation: Operation<'a>,
+        variables: Map<String, Value>,
+        auth_header: Option<String>,
+        client_headers: &HeaderMap,
+    ) -> (
+        Result<UpstreamResponse, Error>,
+        Operation<'a>,
+        Map<String, Value>,
+    ) {
+        let mut map = request_payload(&operation, variables);
+
+        let result = self.send(&map, auth_header, client_headers).await;
+
+        let the_v = match map.remove("variables") {
+            Some(Value::Object(v)) => v,
+            _ => Map::new(),
+        };
+
+        (result, operation, the_v)
+    }
+
+    /// Sends the requests as a JSON array in a single upstream request.
+    /// The responses are returned in the order of the requests.
+    pub async fn forward_graphql_batch(
+        &self,
+        requests: Vec<Map<String, Value>>,
+        auth_header: Option<String>,
+        client_headers: &HeaderMap,
+    ) -> Vec<Result<UpstreamResponse, Error>> {
+        let count = requests.len();
+        let result = self.send(&requests, auth_header, client_headers).await;
+
+        match result {
+            Ok(UpstreamResponse {
+                body: Value::Array(bodies),
+                cache_control,
+            }) if bodies.len() == count => bodies
+                .into_iter()
+                .map(|body| {
+                    Ok(UpstreamResponse {
+                        body: body,
+                        cache_control: cache_control.clone(),
+                    })
+                })
+                .collect(),
+            Ok(_) => (0..count)
+                .map(|_| {
+                    Err(Error::Upstream(String::from(
+                        "The upstream server didn't answer the batch with an array of responses",
+                    )))
+                })
+                .collect(),
+            Err(e) => (0..count).map(|_| Err(e.clone())).collect(),
+        }
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        body: &T,
+        auth_header: Option<String>,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse, Error> {
+        let mut request = self.client.post(&self.url).json(body);
+
+        for name in self.forwarded_headers.iter() {
+            for value in client_headers.get_all(name) {
+                request = request.header(name, value);
+            }
+        }
+
+        let request_builder = if let Some(header) = auth_header {
+            request.header(AUTHORIZATION, header)
+        } else {
+            request
+        };
+
+        let res = request_builder.send().await;
+
+        let (resp, cache_control) = match res {
+            Ok(r) => {
+                let cache_control = r
+                    .headers()
+                    .get(CACHE_CONTROL)
+                    .and_then(|h| h.to_str().ok())
+                    .map(|h| h.to_string());
+
+                (r.json::<Value>().await, cache_control)
+            }
+            Err(e) => return Err(Error::Upstream(format!("Request error: {:?}", e))),
+        };
+
+        match resp {
+            Ok(r) => Ok(UpstreamResponse {
+                body: r,
+                cache_control: cache_control,
+            }),
+            Err(e) => Err(Error::Upstream(format!("Deserialization error: {:?}", e))),
+        }
+    }
+}
+
+/// The body of the upstream request: `{"query": "...", "variables": {...}}`
+pub fn request_payload(operation: &Operation, variables: Map<String, Value>) -> Map<String, Value> {
+    let sss = serialize_operation(operation);
+    println!("Request: {}", sss);
+    let mut map = Map::new();
+    map.insert(String::from("query"), Value::String(sss));
+    map.insert(String::from("variables"), Value::Object(variables));
+
+    map
+}
+
+fn parse_header_name(name: &str) -> Result<HeaderName, String> {
+    match HeaderName::try_from(name) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Invalid header name \"{}\"", name)),
+    }
+}
+
+fn parse_header_value(value: &str) -> Result<HeaderValue, String> {
+    match HeaderValue::try_from(value) {
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("Invalid header value \"{}\"", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Upstream, UpstreamConfig};
+    use serde_json::json;
+
+    #[test]
+    fn upstream_config_defaults() {
+        let config: UpstreamConfig =
+            serde_json::from_value(json!({"url": "http://localhost:4000/"})).unwrap();
+
+        assert_eq!(config.url, "http://localhost:4000/");
+        assert!(config.connect_timeout_ms.is_none());
+        assert!(config.timeout_ms.is_none());
+        assert!(config.max_idle_connections.is_none());
+        assert_eq!(config.headers.len(), 0);
+        assert_eq!(config.forwarded_headers.len(), 0);
+    }
+
+    #[test]
+    fn upstream_is_created_from_config() {
+        let config: UpstreamConfig = serde_json::from_value(json!({
+            "url": "http://localhost:4000/",
+            "connect_timeout_ms": 500,
+            "timeout_ms": 3000,
+            "max_idle_connections": 8,
+            "headers": {"x-api-key": "secret"},
+            "forwarded_headers": ["Accept-Language", "x-request-id"]
+        }))
+        .unwrap();
+
+        let upstream = Upstream::new(&config).unwrap();
+        assert_eq!(upstream.url, "http://localhost:4000/");
+        assert_eq!(
+            upstream
+                .forwarded_headers
+                .iter()
+                .map(|h| h.as_str())
+                .collect::<Vec<_>>(),
+            vec!["accept-language", "x-request-id"]
+        );
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let config: UpstreamConfig = serde_json::from_value(json!({
+            "url": "http://localhost:4000/",
+            "headers": {"x-api-key": "line\nbreak"}
+        }))
+        .unwrap();
+        assert!(Upstream::new(&config).is_err());
+
+        let config: UpstreamConfig = serde_json::from_value(json!({
+            "url": "http://localhost:4000/",
+            "forwarded_headers": ["not a header"]
+        }))
+        .unwrap();
+        assert!(Upstream::new(&config).is_err());
+    }
+}